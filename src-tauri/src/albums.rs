@@ -0,0 +1,54 @@
+use reqwest::Method;
+use serde_json::json;
+use tauri::command;
+
+use crate::backend_log;
+use crate::spotify::{self, Album, Page, Track};
+
+/// The album with its complete track listing. Spotify only embeds the first
+/// page of tracks, so the remaining pages are fetched and appended.
+#[command]
+pub async fn get_album(app: tauri::AppHandle, access: String, id: String) -> Result<Album, String> {
+    let mut album: Album = spotify::get(&access, &format!("/albums/{}", id)).await?;
+
+    let mut next = album.tracks.next.take();
+    while let Some(url) = next {
+        let page: Page<Track> = spotify::get(&access, &url).await?;
+        album.tracks.items.extend(page.items);
+        next = page.next;
+    }
+    album.tracks.limit = album.tracks.items.len() as u32;
+
+    backend_log(
+        &app,
+        format!("Fetched album '{}' with {} tracks.", album.name, album.tracks.items.len()),
+    );
+    Ok(album)
+}
+
+/// Play the album `album_id`, starting at `track_uri` (or the first track).
+/// The album keeps playing in order after the selected track.
+#[command]
+pub async fn play_album_from_track(
+    app: tauri::AppHandle,
+    access: String,
+    album_id: String,
+    track_uri: Option<String>,
+) -> Result<(), String> {
+    let mut body = json!({ "context_uri": format!("spotify:album:{}", album_id) });
+    if let Some(uri) = &track_uri {
+        body["offset"] = json!({ "uri": uri });
+    }
+
+    spotify::send_empty(Method::PUT, &access, "/me/player/play", &body).await?;
+
+    backend_log(
+        &app,
+        format!(
+            "Playing album {} from {}.",
+            album_id,
+            track_uri.as_deref().unwrap_or("the first track")
+        ),
+    );
+    Ok(())
+}
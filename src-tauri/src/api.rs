@@ -0,0 +1,338 @@
+// Local control API served next to `/callback` by the embedded HTTP server,
+// for other tools on the machine. It is off unless `api.enabled` is set in
+// the config. Every request needs the API token as
+// `Authorization: Bearer <token>`; the token is generated on first start,
+// kept with the other credentials and shown by `get_api_token`.
+//
+// Handlers reuse the Tauri commands with the access token the frontend
+// stored on the backend, and always answer with JSON. The checks and routing
+// in `accept` are shared with the headless daemon, which serves the same API.
+// In both, `/api/now-playing` answers with Spotify's `/me/player` payload, or
+// `null` while no device is active.
+
+use std::sync::Arc;
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tauri::{command, Manager};
+
+use crate::control::{self, Action};
+use crate::credentials::Credentials;
+use crate::{events, player, ws};
+
+pub const TOKEN_KEY: &str = "api_token";
+// WebSocket stream of playback events, see `ws`.
+const EVENTS_PATH: &str = "/api/events";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ApiConfig {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+// Whether the API is served; managed at startup from the config.
+pub struct ApiEnabled(pub bool);
+
+#[derive(Deserialize)]
+struct VolumeRequest {
+    volume: u8,
+}
+
+// 32 bytes from the OS random number generator, hex encoded.
+fn generate_token() -> Result<String, String> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).map_err(|e| format!("Failed to generate API token: {}", e))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// The API token, created and stored the first time it is needed.
+pub fn ensure_token(credentials: &Credentials) -> Result<String, String> {
+    if let Some(token) = credentials.get(TOKEN_KEY) {
+        return Ok(token);
+    }
+    let token = generate_token()?;
+    credentials.set(TOKEN_KEY, Some(token.clone()))?;
+    Ok(token)
+}
+
+// Compare without stopping at the first difference, so response times do
+// not give the token away.
+fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+pub(crate) fn json_response(status: StatusCode, body: &impl Serialize) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(body).unwrap_or_default()))
+        .unwrap()
+}
+
+pub(crate) fn error(status: StatusCode, message: impl Into<String>) -> Response<Body> {
+    json_response(status, &json!({ "error": message.into() }))
+}
+
+// Answer with `value`, or report a failed Spotify request.
+pub(crate) fn reply<T: Serialize>(result: Result<T, String>) -> Response<Body> {
+    match result {
+        Ok(value) => json_response(StatusCode::OK, &value),
+        Err(e) => error(StatusCode::BAD_GATEWAY, e),
+    }
+}
+
+pub(crate) fn reply_ok(result: Result<(), String>) -> Response<Body> {
+    reply(result.map(|_| json!({ "ok": true })))
+}
+
+// Browsers cannot set headers on WebSocket requests, so the event stream
+// also accepts the token as a `token` query parameter.
+fn authorized(expected: Option<String>, req: &Request<Body>) -> bool {
+    let Some(expected) = expected else {
+        return false;
+    };
+    let header = req
+        .headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    let query = || {
+        req.uri().query().and_then(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == "token")
+                .map(|(_, token)| token.into_owned())
+        })
+    };
+    let given = match req.uri().path() {
+        EVENTS_PATH => header.or_else(query),
+        _ => header,
+    };
+    given.is_some_and(|given| token_matches(&given, &expected))
+}
+
+// What a request to the API asks for.
+pub(crate) enum Route {
+    NowPlaying,
+    Events,
+    Devices,
+    Playlist(String),
+    Control(Action),
+}
+
+/// Check that the API is on and the request carries the token, and work out
+/// its route. `Err` is the response to send instead.
+pub(crate) async fn accept(
+    enabled: bool,
+    credentials: Option<&Credentials>,
+    req: &mut Request<Body>,
+) -> Result<Route, Response<Body>> {
+    if !enabled {
+        return Err(error(StatusCode::NOT_FOUND, "The control API is disabled."));
+    }
+    if !authorized(credentials.and_then(|credentials| credentials.get(TOKEN_KEY)), req) {
+        return Err(error(StatusCode::UNAUTHORIZED, "Missing or invalid API token."));
+    }
+
+    let method = req.method().clone();
+    let path = req.uri().path().trim_end_matches('/').to_string();
+    match (method, path.as_str()) {
+        (Method::GET, EVENTS_PATH) => Ok(Route::Events),
+        (Method::GET, "/api/now-playing") => Ok(Route::NowPlaying),
+        (Method::POST, "/api/play") => Ok(Route::Control(Action::Play)),
+        (Method::POST, "/api/pause") => Ok(Route::Control(Action::Pause)),
+        (Method::POST, "/api/next") => Ok(Route::Control(Action::Next)),
+        (Method::POST, "/api/previous") => Ok(Route::Control(Action::Previous)),
+        (Method::PUT, "/api/volume") => {
+            let body = hyper::body::to_bytes(req.body_mut())
+                .await
+                .map_err(|e| error(StatusCode::BAD_REQUEST, e.to_string()))?;
+            match serde_json::from_slice::<VolumeRequest>(&body) {
+                Ok(request) if request.volume <= 100 => Ok(Route::Control(Action::SetVolume(request.volume))),
+                Ok(_) => Err(error(StatusCode::BAD_REQUEST, "'volume' must be between 0 and 100.")),
+                Err(e) => Err(error(StatusCode::BAD_REQUEST, format!("Invalid body: {}", e))),
+            }
+        }
+        (Method::POST, path) if path.starts_with("/api/playlist/") => {
+            let id = &path["/api/playlist/".len()..];
+            if id.is_empty() || id.contains('/') {
+                return Err(error(StatusCode::NOT_FOUND, "Not Found"));
+            }
+            Ok(Route::Playlist(id.to_string()))
+        }
+        (Method::GET, "/api/devices") => Ok(Route::Devices),
+        _ => Err(error(StatusCode::NOT_FOUND, "Not Found")),
+    }
+}
+
+/// Handle a request under `/api/`.
+pub async fn handle(app: &tauri::AppHandle, mut req: Request<Body>) -> Response<Body> {
+    let enabled = app.try_state::<ApiEnabled>().is_some_and(|enabled| enabled.0);
+    let credentials = app.try_state::<Arc<Credentials>>();
+    let route = match accept(enabled, credentials.as_deref().map(|c| c.as_ref()), &mut req).await {
+        Ok(Route::Events) => return ws::handle(events::subscribe(app), req),
+        Ok(route) => route,
+        Err(response) => return response,
+    };
+    let access = match control::access_token(app) {
+        Ok(access) => access,
+        Err(e) => return error(StatusCode::SERVICE_UNAVAILABLE, e),
+    };
+
+    match route {
+        Route::NowPlaying => match player::playback_state(&access).await {
+            Ok(state) => json_response(StatusCode::OK, &state),
+            Err(e) => error(StatusCode::BAD_GATEWAY, e),
+        },
+        Route::Control(action) => reply_ok(control::dispatch(app, action).await),
+        Route::Playlist(id) => reply_ok(crate::change_playlist(app.clone(), access, id).await),
+        Route::Devices => reply(crate::get_devices(app.clone(), access).await),
+        Route::Events => error(StatusCode::NOT_FOUND, "Not Found"),
+    }
+}
+
+#[command]
+pub fn get_api_token(credentials: tauri::State<'_, Arc<Credentials>>) -> Result<String, String> {
+    ensure_token(&credentials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify::fixtures::temp_path;
+
+    const TOKEN: &str = "0123456789abcdef";
+
+    fn credentials(name: &str) -> Credentials {
+        let credentials = Credentials::open(temp_path(&format!("api-{}-credentials.json", name)));
+        credentials.set(TOKEN_KEY, Some(TOKEN.to_string())).unwrap();
+        credentials
+    }
+
+    fn request(method: Method, uri: &str, token: Option<&str>, body: &str) -> Request<Body> {
+        let mut builder = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            builder = builder.header(hyper::header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        builder.body(Body::from(body.to_string())).unwrap()
+    }
+
+    async fn status(enabled: bool, credentials: Option<&Credentials>, mut req: Request<Body>) -> StatusCode {
+        match accept(enabled, credentials, &mut req).await {
+            Ok(_) => StatusCode::OK,
+            Err(response) => response.status(),
+        }
+    }
+
+    #[tokio::test]
+    async fn needs_the_token() {
+        let credentials = credentials("token");
+        let get = |token| request(Method::GET, "/api/now-playing", token, "");
+        assert_eq!(status(true, Some(&credentials), get(Some(TOKEN))).await, StatusCode::OK);
+        assert_eq!(
+            status(true, Some(&credentials), get(None)).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(true, Some(&credentials), get(Some("0123456789abcdee"))).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(true, Some(&credentials), get(Some(""))).await,
+            StatusCode::UNAUTHORIZED
+        );
+        // Without a stored token nothing gets in.
+        assert_eq!(status(true, None, get(Some(TOKEN))).await, StatusCode::UNAUTHORIZED);
+
+        // The query parameter only counts for the event stream.
+        let query = format!("?token={}", TOKEN);
+        let events = request(Method::GET, &format!("{}{}", EVENTS_PATH, query), None, "");
+        assert_eq!(status(true, Some(&credentials), events).await, StatusCode::OK);
+        let now_playing = request(Method::GET, &format!("/api/now-playing{}", query), None, "");
+        assert_eq!(
+            status(true, Some(&credentials), now_playing).await,
+            StatusCode::UNAUTHORIZED
+        );
+        std::fs::remove_file(temp_path("api-token-credentials.json")).unwrap();
+    }
+
+    #[tokio::test]
+    async fn disabled_by_default() {
+        assert!(!ApiConfig::default().enabled);
+        let config: ApiConfig = serde_json::from_str("{}").unwrap();
+        assert!(!config.enabled);
+
+        let credentials = credentials("disabled");
+        let req = request(Method::POST, "/api/play", Some(TOKEN), "");
+        assert_eq!(status(false, Some(&credentials), req).await, StatusCode::NOT_FOUND);
+        std::fs::remove_file(temp_path("api-disabled-credentials.json")).unwrap();
+    }
+
+    #[tokio::test]
+    async fn routes() {
+        let credentials = credentials("routes");
+        let accept = |method, uri: &str, body: &str| {
+            let mut req = request(method, uri, Some(TOKEN), body);
+            let credentials = &credentials;
+            async move { accept(true, Some(credentials), &mut req).await }
+        };
+
+        assert!(matches!(
+            accept(Method::PUT, "/api/volume", r#"{ "volume": 40 }"#).await,
+            Ok(Route::Control(Action::SetVolume(40)))
+        ));
+        for body in [r#"{ "volume": 101 }"#, r#"{ "volume": -1 }"#, "{}", "loud"] {
+            let response = accept(Method::PUT, "/api/volume", body).await.err().unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", body);
+        }
+
+        assert!(matches!(
+            accept(Method::POST, "/api/playlist/37i9dQZF1DXcBWIGoYBM5M", "").await,
+            Ok(Route::Playlist(id)) if id == "37i9dQZF1DXcBWIGoYBM5M"
+        ));
+        assert!(matches!(
+            accept(Method::POST, "/api/playlist/abc/", "").await,
+            Ok(Route::Playlist(id)) if id == "abc"
+        ));
+        for uri in ["/api/playlist/", "/api/playlist/abc/tracks"] {
+            let response = accept(Method::POST, uri, "").await.err().unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", uri);
+        }
+        let response = accept(Method::GET, "/api/playlist/abc", "").await.err().unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        assert!(matches!(
+            accept(Method::POST, "/api/next", "").await,
+            Ok(Route::Control(Action::Next))
+        ));
+        assert!(matches!(
+            accept(Method::GET, "/api/devices", "").await,
+            Ok(Route::Devices)
+        ));
+        let response = accept(Method::GET, "/api/unknown", "").await.err().unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        std::fs::remove_file(temp_path("api-routes-credentials.json")).unwrap();
+    }
+
+    #[test]
+    fn tokens_are_random_hex() {
+        let token = generate_token().unwrap();
+        assert_eq!(token.len(), 64);
+        assert!(token.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase()));
+        assert_ne!(token, generate_token().unwrap());
+    }
+
+    #[test]
+    fn token_comparison() {
+        assert!(token_matches("abc123", "abc123"));
+        assert!(!token_matches("abc124", "abc123"));
+        assert!(!token_matches("abc12", "abc123"));
+        assert!(!token_matches("", "abc123"));
+    }
+}
@@ -0,0 +1,245 @@
+// Cache of artist metadata (images, genres, followers, popularity).
+//
+// `fetch_current_song` needs the artist of the current track on every poll,
+// which is almost always the same one, so artists are kept in memory for a
+// configurable time and optionally persisted to the app cache directory.
+// Artist page overviews share the same lifetime but are not persisted.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{command, Manager};
+
+use crate::backend_log;
+use crate::history::{self, now_ms};
+use crate::spotify::{self, Artist, Page, SimplifiedAlbum, Track};
+
+// Oldest entries are dropped beyond this many artists.
+const MAX_ENTRIES: usize = 2000;
+// Overviews are bigger and only needed while browsing, so fewer are kept.
+const MAX_OVERVIEWS: usize = 100;
+
+fn default_ttl_hours() -> u64 {
+    24
+}
+
+fn default_persist() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArtistCacheConfig {
+    #[serde(default = "default_ttl_hours")]
+    pub ttl_hours: u64,
+    // Keep the cache across restarts.
+    #[serde(default = "default_persist")]
+    pub persist: bool,
+}
+
+impl Default for ArtistCacheConfig {
+    fn default() -> Self {
+        ArtistCacheConfig {
+            ttl_hours: default_ttl_hours(),
+            persist: default_persist(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Entry {
+    artist: Artist,
+    fetched_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArtistOverview {
+    pub artist: Artist,
+    pub top_tracks: Vec<Track>,
+    // Albums and singles, newest first as Spotify returns them.
+    pub albums: Page<SimplifiedAlbum>,
+    // Empty if Spotify refuses the request, which it does for newer apps.
+    pub related_artists: Vec<Artist>,
+}
+
+// Artist ID, album offset and album limit.
+type OverviewKey = (String, u32, u32);
+
+#[derive(Deserialize)]
+struct TopTracks {
+    tracks: Vec<Track>,
+}
+
+#[derive(Deserialize)]
+struct RelatedArtists {
+    artists: Vec<Artist>,
+}
+
+pub struct ArtistCache {
+    ttl_ms: i64,
+    path: Option<PathBuf>,
+    entries: Mutex<HashMap<String, Entry>>,
+    // Kept in memory only, with the time they were fetched.
+    overviews: Mutex<HashMap<OverviewKey, (i64, ArtistOverview)>>,
+}
+
+impl ArtistCache {
+    // `path` is where the cache is persisted, if `config.persist` is set.
+    pub fn new(config: &ArtistCacheConfig, path: PathBuf) -> Self {
+        let path = config.persist.then_some(path);
+        let entries = path
+            .as_ref()
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+
+        ArtistCache {
+            ttl_ms: (config.ttl_hours * 60 * 60 * 1000) as i64,
+            path,
+            entries: Mutex::new(entries),
+            overviews: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The cached artist, if it is still fresh.
+    pub fn cached(&self, id: &str) -> Option<Artist> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(id)
+            .filter(|entry| now_ms() - entry.fetched_at < self.ttl_ms)
+            .map(|entry| entry.artist.clone())
+    }
+
+    // Like `cached`, but also returns expired entries.
+    fn stale(&self, id: &str) -> Option<Artist> {
+        self.entries.lock().unwrap().get(id).map(|entry| entry.artist.clone())
+    }
+
+    fn cached_overview(&self, key: &OverviewKey) -> Option<ArtistOverview> {
+        let overviews = self.overviews.lock().unwrap();
+        overviews
+            .get(key)
+            .filter(|(fetched_at, _)| now_ms() - fetched_at < self.ttl_ms)
+            .map(|(_, overview)| overview.clone())
+    }
+
+    fn insert_overview(&self, key: OverviewKey, overview: ArtistOverview) {
+        let mut overviews = self.overviews.lock().unwrap();
+        if overviews.len() >= MAX_OVERVIEWS {
+            overviews.retain(|_, (fetched_at, _)| now_ms() - *fetched_at < self.ttl_ms);
+            if overviews.len() >= MAX_OVERVIEWS {
+                overviews.clear();
+            }
+        }
+        overviews.insert(key, (now_ms(), overview));
+    }
+
+    pub fn insert(&self, artist: Artist) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES && !entries.contains_key(&artist.id) {
+            if let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.fetched_at)
+                .map(|(id, _)| id.clone())
+            {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            artist.id.clone(),
+            Entry {
+                artist,
+                fetched_at: now_ms(),
+            },
+        );
+
+        if let Some(path) = &self.path {
+            let tmp = path.with_extension("tmp");
+            let result = serde_json::to_vec(&*entries)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| std::fs::write(&tmp, bytes).map_err(|e| e.to_string()))
+                .and_then(|_| std::fs::rename(&tmp, path).map_err(|e| e.to_string()));
+            if let Err(e) = result {
+                log::error!("Failed to save artist cache: {}", e);
+            }
+        }
+    }
+}
+
+/// The artist with the given ID, from the cache when fresh. If Spotify cannot
+/// be reached, an expired entry is better than nothing.
+pub async fn lookup(app: &tauri::AppHandle, access: &str, id: &str) -> Result<Artist, String> {
+    let cache = app.try_state::<ArtistCache>();
+    if let Some(artist) = cache.as_ref().and_then(|cache| cache.cached(id)) {
+        return Ok(artist);
+    }
+
+    match spotify::get::<Artist>(access, &format!("/artists/{}", id)).await {
+        Ok(artist) => {
+            history::record_artist(app, &artist);
+            if let Some(cache) = &cache {
+                cache.insert(artist.clone());
+            }
+            Ok(artist)
+        }
+        Err(e) => cache.and_then(|cache| cache.stale(id)).ok_or(e),
+    }
+}
+
+#[command]
+pub async fn get_artist(app: tauri::AppHandle, access: String, id: String) -> Result<Artist, String> {
+    lookup(&app, &access, &id).await
+}
+
+/// Everything the artist page shows: the profile, the top tracks in the
+/// user's market, one page of albums and singles, and related artists. The
+/// requests are made concurrently and the result is cached like the artist.
+#[command]
+pub async fn get_artist_overview(
+    app: tauri::AppHandle,
+    access: String,
+    id: String,
+    offset: Option<u32>,
+    limit: Option<u32>,
+) -> Result<ArtistOverview, String> {
+    let offset = offset.unwrap_or(0);
+    let limit = limit.unwrap_or(20).clamp(1, 50);
+    let key = (id.clone(), offset, limit);
+    let cache = app.try_state::<ArtistCache>();
+    if let Some(overview) = cache.as_ref().and_then(|cache| cache.cached_overview(&key)) {
+        return Ok(overview);
+    }
+
+    // `from_token` picks the market of the signed-in user.
+    let top_tracks_path = format!("/artists/{}/top-tracks?market=from_token", id);
+    let albums_path = format!(
+        "/artists/{}/albums?include_groups=album,single&market=from_token&offset={}&limit={}",
+        id, offset, limit
+    );
+    let related_path = format!("/artists/{}/related-artists", id);
+    let (artist, top_tracks, albums, related) = futures::join!(
+        lookup(&app, &access, &id),
+        spotify::get::<TopTracks>(&access, &top_tracks_path),
+        spotify::get::<Page<SimplifiedAlbum>>(&access, &albums_path),
+        spotify::get::<RelatedArtists>(&access, &related_path),
+    );
+
+    let overview = ArtistOverview {
+        artist: artist?,
+        top_tracks: top_tracks?.tracks,
+        albums: albums?,
+        related_artists: related
+            .map(|related| related.artists)
+            .unwrap_or_else(|e| {
+                log::error!("Failed to fetch related artists: {}", e);
+                Vec::new()
+            }),
+    };
+    if let Some(cache) = &cache {
+        cache.insert_overview(key, overview.clone());
+    }
+
+    backend_log(&app, format!("Fetched overview of artist '{}'.", overview.artist.name));
+    Ok(overview)
+}
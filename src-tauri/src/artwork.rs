@@ -0,0 +1,469 @@
+// Disk cache for album, artist and playlist artwork.
+//
+// Images are stored under the app cache directory, named by the SHA-256 of
+// their content, with an index mapping source URLs to blobs. The least
+// recently used blobs are evicted once the cache grows past its size limit.
+// The webview loads artwork (and the backdrops rendered from it) through the
+// `artwork` URI scheme, so images keep showing while the network is down as
+// long as they were seen before. Only images on Spotify's CDN are fetched, so
+// the scheme cannot be used to make the app request arbitrary URLs.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::http::{header, Request, Response, StatusCode};
+use tauri::{command, Manager};
+
+use crate::backdrop;
+use crate::history::now_ms;
+
+pub const SCHEME: &str = "artwork";
+const INDEX_FILE: &str = "index.json";
+// Hosts Spotify serves images from, besides any under `ALLOWED_DOMAIN`.
+const ALLOWED_HOSTS: &[&str] = &["i.scdn.co", "mosaic.scdn.co"];
+const ALLOWED_DOMAIN: &str = ".spotifycdn.com";
+// Lookups only touch the index in memory; it is written out at most this
+// often so that eviction order survives a restart.
+const SAVE_INTERVAL_MS: i64 = 60_000;
+
+// Numbers the temporary files of concurrent inserts.
+static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+
+fn default_max_size_mb() -> u64 {
+    200
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArtworkConfig {
+    #[serde(default = "default_max_size_mb")]
+    pub max_size_mb: u64,
+}
+
+impl Default for ArtworkConfig {
+    fn default() -> Self {
+        ArtworkConfig {
+            max_size_mb: default_max_size_mb(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Blob {
+    size: u64,
+    content_type: String,
+    last_used: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Index {
+    // Source key (usually a URL) to content hash.
+    keys: HashMap<String, String>,
+    blobs: HashMap<String, Blob>,
+    #[serde(skip)]
+    saved_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Artwork {
+    pub hash: String,
+    pub content_type: String,
+    pub bytes: Vec<u8>,
+}
+
+pub struct ArtworkCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    client: Client,
+    index: Mutex<Index>,
+}
+
+fn is_allowed(url: &Url) -> bool {
+    url.scheme() == "https"
+        && url.port().is_none()
+        && url
+            .host_str()
+            .is_some_and(|host| ALLOWED_HOSTS.contains(&host) || host.ends_with(ALLOWED_DOMAIN))
+}
+
+/// Check that `url` points at Spotify's image CDN.
+pub fn check_url(url: &str) -> Result<(), String> {
+    match Url::parse(url) {
+        Ok(parsed) if is_allowed(&parsed) => Ok(()),
+        Ok(_) => Err(format!("Artwork is only loaded from Spotify, not {}", url)),
+        Err(e) => Err(format!("Invalid artwork URL '{}': {}", url, e)),
+    }
+}
+
+// URL under which the webview can load `url` through the cache. Anything the
+// cache would refuse (such as the placeholder image) is left for the webview
+// to load directly.
+pub fn protocol_url(url: &str) -> String {
+    if check_url(url).is_err() {
+        return url.to_string();
+    }
+    scheme_url("/", url)
+}
+
+// URL of `path` on the `artwork` scheme with `url` as its query.
+pub fn scheme_url(path: &str, url: &str) -> String {
+    let encoded: String = url::form_urlencoded::byte_serialize(url.as_bytes()).collect();
+    // Windows and Android webviews only allow custom schemes as subdomains.
+    if cfg!(any(windows, target_os = "android")) {
+        format!("http://{}.localhost{}?url={}", SCHEME, path, encoded)
+    } else {
+        format!("{}://localhost{}?url={}", SCHEME, path, encoded)
+    }
+}
+
+impl ArtworkCache {
+    pub fn open(dir: PathBuf, config: &ArtworkConfig) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let mut index: Index = std::fs::read(dir.join(INDEX_FILE))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        // Forget blobs whose files were removed behind our back.
+        index.blobs.retain(|hash, _| dir.join(hash).exists());
+        let Index { keys, blobs, .. } = &mut index;
+        keys.retain(|_, hash| blobs.contains_key(hash));
+
+        // Redirects are held to the same hosts as the original request.
+        let client = Client::builder()
+            .redirect(Policy::custom(|attempt| {
+                if is_allowed(attempt.url()) {
+                    attempt.follow()
+                } else {
+                    attempt.stop()
+                }
+            }))
+            .build()
+            .map_err(std::io::Error::other)?;
+
+        Ok(ArtworkCache {
+            dir,
+            max_bytes: config.max_size_mb * 1024 * 1024,
+            client,
+            index: Mutex::new(index),
+        })
+    }
+
+    fn save_index(&self, index: &mut Index) {
+        index.saved_at = now_ms();
+        let path = self.dir.join(INDEX_FILE);
+        let tmp = path.with_extension("tmp");
+        let result = serde_json::to_vec(&*index)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| std::fs::write(&tmp, bytes).map_err(|e| e.to_string()))
+            .and_then(|_| std::fs::rename(&tmp, &path).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            log::error!("Failed to save artwork index: {}", e);
+        }
+    }
+
+    /// The cached image stored under `key`, if any.
+    pub fn lookup(&self, key: &str) -> Option<Artwork> {
+        let mut index = self.index.lock().unwrap();
+        let hash = index.keys.get(key)?.clone();
+        let bytes = std::fs::read(self.dir.join(&hash)).ok()?;
+        let now = now_ms();
+        let blob = index.blobs.get_mut(&hash)?;
+        blob.last_used = now;
+        let content_type = blob.content_type.clone();
+        if now - index.saved_at >= SAVE_INTERVAL_MS {
+            self.save_index(&mut index);
+        }
+        Some(Artwork {
+            content_type,
+            hash,
+            bytes,
+        })
+    }
+
+    /// Store an image under `key` and evict old images if the cache is full.
+    pub fn insert(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<Artwork, String> {
+        let hash = format!("{:x}", Sha256::digest(&bytes));
+        let path = self.dir.join(&hash);
+        if !path.exists() {
+            // Concurrent inserts of the same image each write their own
+            // temporary file; whichever rename lands last wins, with the
+            // same content.
+            let tmp = self.dir.join(format!(
+                "{}.{}-{}.tmp",
+                hash,
+                std::process::id(),
+                NEXT_TMP.fetch_add(1, Ordering::Relaxed)
+            ));
+            let result = std::fs::write(&tmp, &bytes).and_then(|_| std::fs::rename(&tmp, &path));
+            if let Err(e) = result {
+                let _ = std::fs::remove_file(&tmp);
+                if !path.exists() {
+                    return Err(format!("Failed to cache artwork: {}", e));
+                }
+            }
+        }
+
+        let mut index = self.index.lock().unwrap();
+        index.keys.insert(key.to_string(), hash.clone());
+        index.blobs.insert(
+            hash.clone(),
+            Blob {
+                size: bytes.len() as u64,
+                content_type: content_type.to_string(),
+                last_used: now_ms(),
+            },
+        );
+        self.evict(&mut index, &hash);
+        self.save_index(&mut index);
+
+        Ok(Artwork {
+            hash,
+            content_type: content_type.to_string(),
+            bytes,
+        })
+    }
+
+    // Drop least recently used blobs until the cache fits, never evicting
+    // the blob that was just added.
+    fn evict(&self, index: &mut Index, keep: &str) {
+        let mut total: u64 = index.blobs.values().map(|b| b.size).sum();
+        while total > self.max_bytes {
+            let Some(oldest) = index
+                .blobs
+                .iter()
+                .filter(|(hash, _)| hash.as_str() != keep)
+                .min_by_key(|(_, blob)| blob.last_used)
+                .map(|(hash, _)| hash.clone())
+            else {
+                break;
+            };
+
+            if let Some(blob) = index.blobs.remove(&oldest) {
+                total -= blob.size;
+            }
+            index.keys.retain(|_, hash| *hash != oldest);
+            let _ = std::fs::remove_file(self.dir.join(&oldest));
+        }
+    }
+
+    /// The image at `url`, from the cache if possible and downloaded otherwise.
+    pub async fn fetch(&self, url: &str) -> Result<Artwork, String> {
+        check_url(url)?;
+        if let Some(artwork) = self.lookup(url) {
+            return Ok(artwork);
+        }
+
+        let resp = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to download artwork: {:?}", e))?;
+        if !resp.status().is_success() {
+            return Err(format!("Artwork download failed with status {}", resp.status()));
+        }
+        let content_type = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("image/jpeg")
+            .to_string();
+        let bytes = resp
+            .bytes()
+            .await
+            .map_err(|e| format!("Failed to download artwork: {:?}", e))?;
+
+        self.insert(url, bytes.to_vec(), &content_type)
+    }
+}
+
+fn respond(status: StatusCode, content_type: &str, body: Vec<u8>) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(body)
+        .unwrap()
+}
+
+// Handle a request made through the `artwork` URI scheme: `/?url=...` for
+// the artwork itself and `/backdrop?url=...&size=...` for its backdrop.
+pub async fn serve(app: &tauri::AppHandle, request: Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let Some(cache) = app.try_state::<ArtworkCache>() else {
+        return respond(StatusCode::SERVICE_UNAVAILABLE, "text/plain", b"Artwork cache unavailable".to_vec());
+    };
+    let params: HashMap<String, String> = request
+        .uri()
+        .query()
+        .map(|query| url::form_urlencoded::parse(query.as_bytes()).into_owned().collect())
+        .unwrap_or_default();
+    let Some(url) = params.get("url") else {
+        return respond(StatusCode::BAD_REQUEST, "text/plain", b"Missing 'url' parameter.".to_vec());
+    };
+    if let Err(e) = check_url(url) {
+        return respond(StatusCode::FORBIDDEN, "text/plain", e.into_bytes());
+    }
+
+    let result = match request.uri().path() {
+        "/backdrop" => {
+            let size = params
+                .get("size")
+                .and_then(|size| size.parse().ok())
+                .unwrap_or(backdrop::SIZES[0]);
+            backdrop::get(app, url, size).await
+        }
+        _ => cache.fetch(url).await,
+    };
+
+    match result {
+        Ok(artwork) => {
+            let mut response = respond(StatusCode::OK, &artwork.content_type, artwork.bytes);
+            // Blobs never change, so the webview may keep them as long as it likes.
+            response.headers_mut().insert(
+                header::CACHE_CONTROL,
+                header::HeaderValue::from_static("public, max-age=31536000, immutable"),
+            );
+            response
+        }
+        Err(e) => respond(StatusCode::BAD_GATEWAY, "text/plain", e.into_bytes()),
+    }
+}
+
+// Turn a remote image URL into one served through the artwork cache.
+#[command]
+pub fn get_artwork_url(url: String) -> String {
+    protocol_url(&url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify::fixtures::temp_path;
+
+    const MB: usize = 1024 * 1024;
+
+    fn open(dir: &PathBuf) -> ArtworkCache {
+        ArtworkCache::open(dir.clone(), &ArtworkConfig { max_size_mb: 1 }).unwrap()
+    }
+
+    // An image of `size` bytes that differs from the others by `fill`.
+    fn image(fill: u8, size: usize) -> Vec<u8> {
+        vec![fill; size]
+    }
+
+    fn tick() {
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let dir = temp_path("artwork-evict");
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = open(&dir);
+        let a = cache.insert("a", image(1, 400 * 1024), "image/jpeg").unwrap();
+        tick();
+        let b = cache.insert("b", image(2, 400 * 1024), "image/png").unwrap();
+        tick();
+        assert_eq!(cache.lookup("a").unwrap().bytes.len(), 400 * 1024);
+        tick();
+
+        // Over the limit: `b` is now the least recently used.
+        cache.insert("c", image(3, 400 * 1024), "image/jpeg").unwrap();
+        assert!(cache.lookup("b").is_none());
+        assert!(!dir.join(&b.hash).exists());
+        assert!(dir.join(&a.hash).exists());
+        assert_eq!(cache.lookup("c").unwrap().content_type, "image/jpeg");
+
+        // A single image larger than the limit is still kept.
+        tick();
+        let big = cache.insert("big", image(4, MB + 1), "image/jpeg").unwrap();
+        assert!(cache.lookup("a").is_none());
+        assert!(cache.lookup("c").is_none());
+        assert_eq!(cache.lookup("big").unwrap().hash, big.hash);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lookups_are_remembered_across_restarts() {
+        let dir = temp_path("artwork-restart");
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = open(&dir);
+        cache.insert("a", image(1, 400 * 1024), "image/jpeg").unwrap();
+        tick();
+        cache.insert("b", image(2, 400 * 1024), "image/jpeg").unwrap();
+        tick();
+        drop(cache);
+
+        // The first lookup after opening writes the index out.
+        let cache = open(&dir);
+        assert!(cache.lookup("a").is_some());
+        drop(cache);
+
+        let cache = open(&dir);
+        tick();
+        cache.insert("c", image(3, 400 * 1024), "image/jpeg").unwrap();
+        assert!(cache.lookup("a").is_some());
+        assert!(cache.lookup("b").is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_inserts_of_one_image() {
+        let dir = temp_path("artwork-concurrent");
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = open(&dir);
+        std::thread::scope(|scope| {
+            for i in 0..8 {
+                let cache = &cache;
+                scope.spawn(move || {
+                    cache
+                        .insert(&format!("key{}", i), image(7, 64 * 1024), "image/jpeg")
+                        .unwrap()
+                });
+            }
+        });
+        let files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(files.len(), 2, "{:?}", files);
+        assert!(files.iter().any(|name| name == INDEX_FILE));
+        assert_eq!(cache.lookup("key3").unwrap().bytes, image(7, 64 * 1024));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_spotify_images_are_fetched() {
+        for url in [
+            "https://i.scdn.co/image/ab67616d0000b273",
+            "https://mosaic.scdn.co/640/ab67616d0000b273",
+            "https://image-cdn-ak.spotifycdn.com/image/ab67706c0000da84",
+            "https://I.SCDN.CO/image/upper",
+        ] {
+            assert!(check_url(url).is_ok(), "{}", url);
+        }
+        for url in [
+            "http://i.scdn.co/image/plain-http",
+            "https://i.scdn.co:8443/image/port",
+            "https://i.scdn.co.example.com/image",
+            "https://evilspotifycdn.com/image",
+            "https://spotifycdn.com.example.com/image",
+            "https://127.0.0.1/image",
+            "https://localhost/image",
+            "file:///etc/passwd",
+            "i.scdn.co/image/relative",
+            "",
+        ] {
+            assert!(check_url(url).is_err(), "{}", url);
+        }
+        assert_eq!(
+            protocol_url("https://via.placeholder.com/300"),
+            "https://via.placeholder.com/300"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+// Spotify OAuth tokens: exchanging the authorization code, refreshing, and
+// keeping the latest tokens in the credentials file so that tools running
+// without the window (such as `playback-ctl`) can act as the same user.
+
+use std::env;
+
+use dotenv::dotenv;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::credentials::Credentials;
+use crate::history::now_ms;
+
+pub const ACCESS_TOKEN_KEY: &str = "spotify_access_token";
+pub const REFRESH_TOKEN_KEY: &str = "spotify_refresh_token";
+// Milliseconds since the Unix epoch.
+pub const EXPIRES_AT_KEY: &str = "spotify_token_expires_at";
+
+// Refresh a little early so a token does not expire mid-request.
+const EXPIRY_MARGIN_MS: i64 = 60_000;
+
+// Spotify Token Response
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: u32,
+    pub refresh_token: Option<String>,
+    pub scope: String,
+}
+
+fn client_credentials() -> Result<(String, String), String> {
+    dotenv().ok();
+    let client_id = env::var("SPOTIFY_CLIENT_ID").map_err(|_| "SPOTIFY_CLIENT_ID not set".to_string())?;
+    let client_secret =
+        env::var("SPOTIFY_CLIENT_SECRET").map_err(|_| "SPOTIFY_CLIENT_SECRET not set".to_string())?;
+    Ok((client_id, client_secret))
+}
+
+async fn request_token(params: &[(&str, &str)]) -> Result<TokenResponse, String> {
+    let client = Client::new();
+    let response = client
+        .post("https://accounts.spotify.com/api/token")
+        .form(params)
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {:?}", e))?;
+
+    if response.status().is_success() {
+        response
+            .json()
+            .await
+            .map_err(|e| format!("JSON parse error: {:?}", e))
+    } else {
+        let error_text = response.text().await.unwrap_or("Unknown error".to_string());
+        Err(format!("Spotify error: {:?}", error_text))
+    }
+}
+
+/// Trade the code from the authorization redirect for tokens.
+pub async fn exchange_code(code: &str) -> Result<TokenResponse, String> {
+    let (client_id, client_secret) = client_credentials()?;
+    let redirect_uri = env::var("REDIRECT_URI").unwrap_or("http://127.0.0.1:4242/callback".to_string());
+    request_token(&[
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", &redirect_uri),
+        ("client_id", &client_id),
+        ("client_secret", &client_secret),
+    ])
+    .await
+}
+
+/// Get a new access token. Spotify does not always send a new refresh token,
+/// in which case the old one stays valid and is returned instead.
+pub async fn refresh(refresh_token: &str) -> Result<TokenResponse, String> {
+    let (client_id, client_secret) = client_credentials()?;
+    let mut tokens = request_token(&[
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token),
+        ("client_id", &client_id),
+        ("client_secret", &client_secret),
+    ])
+    .await?;
+    tokens.refresh_token.get_or_insert_with(|| refresh_token.to_string());
+    Ok(tokens)
+}
+
+/// Remember `tokens`, keeping the stored refresh token if there is no new one.
+pub fn save(credentials: &Credentials, tokens: &TokenResponse) -> Result<(), String> {
+    let expires_at = now_ms() + i64::from(tokens.expires_in) * 1000;
+    credentials.set(ACCESS_TOKEN_KEY, Some(tokens.access_token.clone()))?;
+    credentials.set(EXPIRES_AT_KEY, Some(expires_at.to_string()))?;
+    if let Some(refresh_token) = &tokens.refresh_token {
+        credentials.set(REFRESH_TOKEN_KEY, Some(refresh_token.clone()))?;
+    }
+    Ok(())
+}
+
+/// A valid access token from the stored credentials, refreshed (and stored
+/// again) if it has expired.
+pub async fn access_token(credentials: &Credentials) -> Result<String, String> {
+    let expires_at: i64 = credentials
+        .get(EXPIRES_AT_KEY)
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(0);
+    if let Some(access_token) = credentials.get(ACCESS_TOKEN_KEY) {
+        if now_ms() + EXPIRY_MARGIN_MS < expires_at {
+            return Ok(access_token);
+        }
+    }
+
+    let refresh_token = credentials
+        .get(REFRESH_TOKEN_KEY)
+        .ok_or("Not signed in to Spotify. Sign in once in the app first.")?;
+    let tokens = refresh(&refresh_token).await?;
+    save(credentials, &tokens)?;
+    Ok(tokens.access_token)
+}
@@ -0,0 +1,172 @@
+// Blurred, darkened versions of album art for use as page backgrounds.
+//
+// Blurring in CSS is too slow on the kiosk hardware, so the backend renders
+// the backdrops at a few fixed sizes, keeps them in the artwork cache and
+// serves them at `artwork://localhost/backdrop?url=<artwork URL>&size=<px>`.
+
+use std::io::Cursor;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use serde::Serialize;
+use tauri::{command, Manager};
+
+use crate::artwork::{self, Artwork, ArtworkCache};
+
+// Square output sizes in pixels; requests are rounded up to one of these.
+pub const SIZES: [u32; 3] = [480, 960, 1920];
+// The blur is applied to a copy this many times smaller than the output.
+const DOWNSCALE: u32 = 12;
+const BLUR_SIGMA: f32 = 2.5;
+const BRIGHTNESS: f32 = 0.45;
+const JPEG_QUALITY: u8 = 80;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Backdrop {
+    pub size: u32,
+    pub url: String,
+}
+
+fn snap_size(size: u32) -> u32 {
+    SIZES
+        .iter()
+        .copied()
+        .find(|&s| s >= size)
+        .unwrap_or(SIZES[SIZES.len() - 1])
+}
+
+fn cache_key(url: &str, size: u32) -> String {
+    format!("backdrop:{}:{}", size, url)
+}
+
+pub fn protocol_url(url: &str, size: u32) -> String {
+    format!("{}&size={}", artwork::scheme_url("/backdrop", url), size)
+}
+
+pub fn urls(url: &str) -> Vec<Backdrop> {
+    SIZES
+        .iter()
+        .map(|&size| Backdrop {
+            size,
+            url: protocol_url(url, size),
+        })
+        .collect()
+}
+
+/// Render an encoded image as a blurred, darkened `size`×`size` JPEG.
+pub fn render(bytes: &[u8], size: u32) -> Result<Vec<u8>, String> {
+    let image = image::load_from_memory(bytes).map_err(|e| format!("Failed to decode artwork: {}", e))?;
+
+    // Blurring a small copy and scaling it back up looks the same as
+    // blurring at full size, at a fraction of the cost.
+    let small_size = (size / DOWNSCALE).max(8);
+    let small = image
+        .resize_to_fill(small_size, small_size, FilterType::Triangle)
+        .to_rgb8();
+    let blurred = imageops::blur(&small, BLUR_SIGMA);
+    let mut backdrop = imageops::resize(&blurred, size, size, FilterType::Triangle);
+    for pixel in backdrop.pixels_mut() {
+        for channel in pixel.0.iter_mut() {
+            *channel = (f32::from(*channel) * BRIGHTNESS) as u8;
+        }
+    }
+
+    let mut jpeg = Cursor::new(Vec::new());
+    JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY)
+        .encode_image(&backdrop)
+        .map_err(|e| format!("Failed to encode backdrop: {}", e))?;
+    Ok(jpeg.into_inner())
+}
+
+/// The backdrop of the artwork at `url`, rendered on first use.
+pub async fn get(app: &tauri::AppHandle, url: &str, size: u32) -> Result<Artwork, String> {
+    let cache = app
+        .try_state::<ArtworkCache>()
+        .ok_or("Artwork cache unavailable")?;
+    let size = snap_size(size);
+    let key = cache_key(url, size);
+    if let Some(backdrop) = cache.lookup(&key) {
+        return Ok(backdrop);
+    }
+
+    let source = cache.fetch(url).await?;
+    let jpeg = tauri::async_runtime::spawn_blocking(move || render(&source.bytes, size))
+        .await
+        .map_err(|e| e.to_string())??;
+    cache.insert(&key, jpeg, "image/jpeg")
+}
+
+/// Render every size ahead of time so the page never waits for one.
+pub async fn prepare(app: &tauri::AppHandle, url: &str) {
+    for size in SIZES {
+        if let Err(e) = get(app, url, size).await {
+            log::error!("Failed to render backdrop: {}", e);
+            return;
+        }
+    }
+}
+
+#[command]
+pub fn get_backdrop_urls(url: String) -> Vec<Backdrop> {
+    urls(&url)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GenericImageView, ImageFormat, RgbImage};
+
+    use super::*;
+
+    // A `width`×`height` PNG, white on the left half and red on the right.
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbImage::from_fn(width, height, |x, _| {
+            if x < width / 2 {
+                image::Rgb([255, 255, 255])
+            } else {
+                image::Rgb([255, 0, 0])
+            }
+        });
+        let mut bytes = Cursor::new(Vec::new());
+        image.write_to(&mut bytes, ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn sizes_round_up() {
+        assert_eq!(snap_size(0), 480);
+        assert_eq!(snap_size(480), 480);
+        assert_eq!(snap_size(481), 960);
+        assert_eq!(snap_size(1920), 1920);
+        assert_eq!(snap_size(4000), 1920);
+    }
+
+    #[test]
+    fn renders_square_darkened_jpegs() {
+        // Not square, so it has to be cropped to fill.
+        let source = png(300, 200);
+        for size in SIZES {
+            let jpeg = render(&source, size).unwrap();
+            assert_eq!(image::guess_format(&jpeg).unwrap(), ImageFormat::Jpeg);
+            let backdrop = image::load_from_memory(&jpeg).unwrap();
+            assert_eq!(backdrop.dimensions(), (size, size));
+
+            // Even the white half comes out well below full brightness.
+            let brightest = backdrop.to_rgb8().pixels().flat_map(|pixel| pixel.0).max().unwrap();
+            assert!(f32::from(brightest) <= 255.0 * BRIGHTNESS + 8.0, "{}", brightest);
+        }
+        assert!(render(b"not an image", 480).is_err());
+    }
+
+    #[test]
+    fn urls_for_every_size() {
+        let backdrops = urls("https://i.scdn.co/image/x");
+        assert_eq!(backdrops.iter().map(|b| b.size).collect::<Vec<_>>(), SIZES);
+        assert!(backdrops[1]
+            .url
+            .contains("/backdrop?url=https%3A%2F%2Fi.scdn.co%2Fimage%2Fx&size=960"));
+        assert_ne!(
+            cache_key("https://i.scdn.co/image/x", 480),
+            cache_key("https://i.scdn.co/image/x", 960)
+        );
+    }
+}
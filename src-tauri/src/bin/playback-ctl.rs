@@ -0,0 +1,229 @@
+// Command line control for scripts and cron, without opening the window.
+//
+// Uses the Spotify credentials the app stored when the user signed in, and
+// refreshes the access token the same way the app does.
+
+use std::process::ExitCode;
+
+use serde_json::json;
+
+use app_lib::credentials::{self, Credentials};
+use app_lib::spotify::{Device, PlaybackState};
+use app_lib::{auth, player};
+
+const USAGE: &str = "Usage: playback-ctl [--json] <command>
+
+Commands:
+  status                 Show what is playing
+  play                   Resume playback
+  pause                  Pause playback
+  next                   Skip to the next track
+  prev                   Skip to the previous track
+  volume <0-100>         Set the volume
+  shuffle <on|off>       Turn shuffle on or off
+  playlist <id|name>     Play one of your playlists
+  devices                List available devices
+  transfer <device>      Move playback to a device, by ID or name
+
+Options:
+  --json                 Print results as JSON";
+
+enum Command {
+    Status,
+    Play,
+    Pause,
+    Next,
+    Previous,
+    Volume(u8),
+    Shuffle(bool),
+    Playlist(String),
+    Devices,
+    Transfer(String),
+}
+
+fn parse(args: &[String]) -> Result<Command, String> {
+    let (name, rest) = args.split_first().ok_or("No command given.")?;
+    let argument = || match rest {
+        [value] => Ok(value.clone()),
+        [] => Err(format!("'{}' needs an argument.", name)),
+        _ => Err(format!("'{}' takes a single argument.", name)),
+    };
+    let no_arguments = |command| match rest {
+        [] => Ok(command),
+        _ => Err(format!("'{}' takes no arguments.", name)),
+    };
+
+    match name.as_str() {
+        "status" => no_arguments(Command::Status),
+        "play" => no_arguments(Command::Play),
+        "pause" => no_arguments(Command::Pause),
+        "next" => no_arguments(Command::Next),
+        "prev" => no_arguments(Command::Previous),
+        "devices" => no_arguments(Command::Devices),
+        "volume" => match argument()?.parse::<u8>() {
+            Ok(volume) if volume <= 100 => Ok(Command::Volume(volume)),
+            _ => Err("The volume must be a number from 0 to 100.".to_string()),
+        },
+        "shuffle" => match argument()?.as_str() {
+            "on" => Ok(Command::Shuffle(true)),
+            "off" => Ok(Command::Shuffle(false)),
+            _ => Err("Shuffle must be 'on' or 'off'.".to_string()),
+        },
+        "playlist" => argument().map(Command::Playlist),
+        "transfer" => argument().map(Command::Transfer),
+        _ => Err(format!("Unknown command '{}'.", name)),
+    }
+}
+
+fn format_time(ms: u32) -> String {
+    let seconds = ms / 1000;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn describe(state: &Option<PlaybackState>) -> String {
+    let Some(state) = state else {
+        return "No active device.".to_string();
+    };
+    let status = if state.is_playing { "Playing" } else { "Paused" };
+    let mut lines = vec![match &state.item {
+        Some(track) => {
+            let mut line = format!("{}: {}", status, track.name);
+            if !track.artists.is_empty() {
+                line += &format!(" by {}", track.artist_names());
+            }
+            format!(
+                "{} ({} / {})",
+                line,
+                format_time(state.progress_ms.unwrap_or(0)),
+                format_time(track.duration_ms)
+            )
+        }
+        None => format!("{}: nothing", status),
+    }];
+    if let Some(device) = &state.device {
+        match device.volume_percent {
+            Some(volume) => lines.push(format!("Device: {} ({}%)", device.name, volume)),
+            None => lines.push(format!("Device: {}", device.name)),
+        }
+    }
+    if let Some(shuffle) = state.shuffle_state {
+        lines.push(format!("Shuffle: {}", if shuffle { "on" } else { "off" }));
+    }
+    lines.join("\n")
+}
+
+fn describe_devices(devices: &[Device]) -> String {
+    if devices.is_empty() {
+        return "No devices available.".to_string();
+    }
+    devices
+        .iter()
+        .map(|device| {
+            format!(
+                "{}\t{}\t{}",
+                device.id.as_deref().unwrap_or("-"),
+                device.kind,
+                device.name
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Run `command` and return what to print, as text and as JSON.
+async fn run(command: Command) -> Result<(String, serde_json::Value), String> {
+    let path = credentials::default_path().ok_or("Could not find the app data directory.")?;
+    let credentials = Credentials::open(path);
+    let access = auth::access_token(&credentials).await?;
+
+    let done = |message: String| Ok((message, json!({ "ok": true })));
+    match command {
+        Command::Status => {
+            let state = player::playback_state(&access).await?;
+            Ok((describe(&state), json!(state)))
+        }
+        Command::Play => {
+            player::play(&access).await?;
+            done("Playing.".to_string())
+        }
+        Command::Pause => {
+            player::pause(&access).await?;
+            done("Paused.".to_string())
+        }
+        Command::Next => {
+            player::next(&access).await?;
+            done("Skipped to the next track.".to_string())
+        }
+        Command::Previous => {
+            player::previous(&access).await?;
+            done("Skipped to the previous track.".to_string())
+        }
+        Command::Volume(volume) => {
+            player::set_volume(&access, volume).await?;
+            done(format!("Volume set to {}%.", volume))
+        }
+        Command::Shuffle(shuffle) => {
+            player::set_shuffle(&access, shuffle).await?;
+            done(format!("Shuffle {}.", if shuffle { "on" } else { "off" }))
+        }
+        Command::Playlist(query) => {
+            let id = player::find_playlist(&access, &query).await?;
+            player::play_context(&access, &format!("spotify:playlist:{}", id)).await?;
+            Ok((format!("Playing playlist {}.", id), json!({ "ok": true, "playlist": id })))
+        }
+        Command::Devices => {
+            let devices = player::devices(&access).await?;
+            Ok((describe_devices(&devices), json!(devices)))
+        }
+        Command::Transfer(query) => {
+            let device = player::find_device(&access, &query).await?;
+            let id = device.id.clone().ok_or(format!("'{}' cannot be controlled.", device.name))?;
+            player::transfer(&access, &id, false).await?;
+            Ok((format!("Playback moved to {}.", device.name), json!({ "ok": true, "device": device })))
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let json_output = args.iter().any(|arg| arg == "--json");
+    args.retain(|arg| arg != "--json");
+
+    let command = match parse(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to start: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    match runtime.block_on(run(command)) {
+        Ok((text, value)) => {
+            if json_output {
+                println!("{}", value);
+            } else {
+                println!("{}", text);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            if json_output {
+                eprintln!("{}", json!({ "error": e }));
+            } else {
+                eprintln!("{}", e);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
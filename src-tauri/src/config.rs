@@ -0,0 +1,66 @@
+// User configuration, read from `config.json` in the app config directory.
+// Every section is optional; a missing file means everything is off.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::api::ApiConfig;
+use crate::artists::ArtistCacheConfig;
+use crate::artwork::ArtworkConfig;
+use crate::credentials::APP_IDENTIFIER;
+use crate::daemon::DaemonConfig;
+use crate::listenbrainz::ListenBrainzConfig;
+use crate::midi::MidiConfig;
+use crate::mqtt::MqttConfig;
+use crate::osc::OscConfig;
+use crate::overlay::OverlayConfig;
+use crate::scrobble::ScrobblerConfig;
+use crate::shortcuts::ShortcutsConfig;
+use crate::tray::TrayConfig;
+
+pub const CONFIG_FILE: &str = "config.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Config {
+    #[serde(default)]
+    pub scrobbler: Option<ScrobblerConfig>,
+    #[serde(default)]
+    pub listenbrainz: Option<ListenBrainzConfig>,
+    #[serde(default)]
+    pub artwork: ArtworkConfig,
+    #[serde(default)]
+    pub artists: ArtistCacheConfig,
+    #[serde(default)]
+    pub api: ApiConfig,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
+    #[serde(default)]
+    pub overlay: Option<OverlayConfig>,
+    #[serde(default)]
+    pub mqtt: Option<MqttConfig>,
+    #[serde(default)]
+    pub osc: Option<OscConfig>,
+    #[serde(default)]
+    pub midi: Option<MidiConfig>,
+    #[serde(default)]
+    pub shortcuts: ShortcutsConfig,
+    #[serde(default)]
+    pub tray: TrayConfig,
+}
+
+/// Where the app reads its config, for modes that run without Tauri.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(APP_IDENTIFIER).join(CONFIG_FILE))
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| format!("Invalid config file {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(format!("Failed to read config file {}: {}", path.display(), e)),
+        }
+    }
+}
@@ -0,0 +1,208 @@
+// Playback control from outside the webview, such as the desktop's media
+// controls. The commands are called by the frontend with its own token; the
+// actions here use the token the frontend stored with `store_access_token`.
+//
+// The last playback payload the frontend polled is kept as well, so outside
+// controls can show what is playing without asking Spotify themselves.
+
+use std::sync::{Arc, Mutex};
+
+use reqwest::Method;
+use serde_json::json;
+use tauri::Manager;
+use tokio::sync::mpsc;
+
+use crate::player;
+use crate::spotify::{self, PlaybackState};
+use crate::AppState;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Play,
+    Pause,
+    PlayPause,
+    Next,
+    Previous,
+    // Position in the current track, in milliseconds.
+    Seek(u32),
+    SetVolume(u8),
+    // Raise or lower the volume by this many percent.
+    ChangeVolume(i8),
+    SetShuffle(bool),
+    ToggleShuffle,
+    // `off`, `context` or `track`.
+    SetRepeat(String),
+    // A `spotify:` URI of a track or of a context (album, playlist, artist).
+    OpenUri(String),
+    // Play one of the user's playlists, by ID, URI or name.
+    Playlist(String),
+    // Move playback to a device, by ID or name.
+    Transfer(String),
+    // Save the current track to the user's library.
+    Like,
+}
+
+#[derive(Default)]
+pub struct NowPlaying(Mutex<Option<PlaybackState>>);
+
+/// `state` with the device, shuffle and repeat state it lacks taken from
+/// `last`. `/me/player/currently-playing` has none of them.
+pub fn merge(last: Option<&PlaybackState>, state: &PlaybackState) -> PlaybackState {
+    let mut state = state.clone();
+    if let Some(last) = last {
+        if state.device.is_none() {
+            state.device = last.device.clone();
+        }
+        if state.shuffle_state.is_none() {
+            state.shuffle_state = last.shuffle_state;
+        }
+        if state.repeat_state.is_none() {
+            state.repeat_state.clone_from(&last.repeat_state);
+        }
+    }
+    state
+}
+
+/// Remember the latest playback payload, merged with the earlier ones.
+pub fn observe(app: &tauri::AppHandle, state: &PlaybackState) {
+    let now_playing = app.state::<NowPlaying>();
+    let mut last = now_playing.0.lock().unwrap();
+    *last = Some(merge(last.as_ref(), state));
+}
+
+/// The latest playback payload, if any was seen yet.
+pub fn now_playing(app: &tauri::AppHandle) -> Option<PlaybackState> {
+    app.try_state::<NowPlaying>()
+        .and_then(|now_playing| now_playing.0.lock().unwrap().clone())
+}
+
+// Apply a successful action to the remembered state right away, so outside
+// controls do not show stale values until the frontend polls again.
+fn assume(app: &tauri::AppHandle, action: &Action) {
+    let Some(now_playing) = app.try_state::<NowPlaying>() else {
+        return;
+    };
+    let mut last = now_playing.0.lock().unwrap();
+    if let Some(state) = last.as_mut() {
+        apply(state, action);
+    }
+}
+
+/// Update `state` to what it will be once `action` has taken effect, as far
+/// as that can be known without asking Spotify.
+pub fn apply(state: &mut PlaybackState, action: &Action) {
+    match action {
+        Action::Play => state.is_playing = true,
+        Action::Pause => state.is_playing = false,
+        Action::PlayPause => state.is_playing = !state.is_playing,
+        Action::Seek(position_ms) => state.progress_ms = Some(*position_ms),
+        Action::SetVolume(volume) => {
+            if let Some(device) = state.device.as_mut() {
+                device.volume_percent = Some(u32::from(*volume));
+            }
+        }
+        Action::ChangeVolume(change) => {
+            if let Some(volume) = state.device.as_mut().and_then(|device| device.volume_percent.as_mut()) {
+                *volume = volume.saturating_add_signed(i32::from(*change)).min(100);
+            }
+        }
+        Action::SetShuffle(shuffle) => state.shuffle_state = Some(*shuffle),
+        Action::ToggleShuffle => state.shuffle_state = Some(!state.shuffle_state.unwrap_or(false)),
+        Action::SetRepeat(mode) => state.repeat_state = Some(mode.clone()),
+        Action::Next
+        | Action::Previous
+        | Action::OpenUri(_)
+        | Action::Playlist(_)
+        | Action::Transfer(_)
+        | Action::Like => {}
+    }
+}
+
+pub fn access_token(app: &tauri::AppHandle) -> Result<String, String> {
+    let state = app.state::<Arc<AppState>>();
+    let token = state.access_token.lock().map_err(|e| e.to_string())?.clone();
+    token.ok_or("No access token stored on backend.".to_string())
+}
+
+/// Send `action` to Spotify. `known` is the last playback payload, which
+/// saves looking it up for the actions that depend on it.
+pub async fn perform(access: &str, action: &Action, known: Option<&PlaybackState>) -> Result<(), String> {
+    let current = || async {
+        match known {
+            Some(state) => Ok(Some(state.clone())),
+            None => player::playback_state(access).await,
+        }
+    };
+    match action {
+        Action::Play => player::play(access).await,
+        Action::Pause => player::pause(access).await,
+        Action::PlayPause => {
+            let playing = current().await?.is_some_and(|state| state.is_playing);
+            if playing {
+                player::pause(access).await
+            } else {
+                player::play(access).await
+            }
+        }
+        Action::Next => player::next(access).await,
+        Action::Previous => player::previous(access).await,
+        Action::Seek(position_ms) => player::seek(access, *position_ms).await,
+        Action::SetVolume(volume) => player::set_volume(access, *volume).await,
+        Action::ChangeVolume(change) => {
+            let volume = current()
+                .await?
+                .and_then(|state| state.device?.volume_percent)
+                .ok_or("The active device has no volume control.")?;
+            let volume = volume.saturating_add_signed(i32::from(*change)).min(100);
+            player::set_volume(access, volume as u8).await
+        }
+        Action::SetShuffle(shuffle) => player::set_shuffle(access, *shuffle).await,
+        Action::ToggleShuffle => {
+            let shuffle = current().await?.and_then(|state| state.shuffle_state).unwrap_or(false);
+            player::set_shuffle(access, !shuffle).await
+        }
+        Action::SetRepeat(mode) => player::set_repeat(access, mode).await,
+        Action::OpenUri(uri) => {
+            if !uri.starts_with("spotify:") {
+                return Err(format!("Cannot play '{}': not a Spotify URI.", uri));
+            }
+            if uri.starts_with("spotify:track:") {
+                spotify::send_empty(Method::PUT, access, "/me/player/play", &json!({ "uris": [uri] })).await
+            } else {
+                player::play_context(access, uri).await
+            }
+        }
+        Action::Playlist(query) => {
+            let id = player::find_playlist(access, query).await?;
+            player::play_context(access, &format!("spotify:playlist:{}", id)).await
+        }
+        Action::Transfer(query) => {
+            let device = player::find_device(access, query).await?;
+            let id = device.id.ok_or(format!("'{}' cannot be controlled.", device.name))?;
+            player::transfer(access, &id, false).await
+        }
+        Action::Like => {
+            let track = current().await?.and_then(|state| state.item).ok_or("Nothing is playing.")?;
+            let id = track.id.ok_or(format!("'{}' is a local file and cannot be saved.", track.name))?;
+            player::save_track(access, &id).await
+        }
+    }
+}
+
+/// Carry out `action` with the stored access token.
+pub async fn dispatch(app: &tauri::AppHandle, action: Action) -> Result<(), String> {
+    let access = access_token(app)?;
+    perform(&access, &action, now_playing(app).as_ref()).await?;
+    assume(app, &action);
+    Ok(())
+}
+
+/// Carry out actions from an outside controller, such as an MQTT broker, OSC
+/// or a MIDI device, until it stops sending them. `source` names it in the backend log.
+pub async fn run_commands(app: tauri::AppHandle, source: &'static str, mut commands: mpsc::UnboundedReceiver<Action>) {
+    while let Some(action) = commands.recv().await {
+        if let Err(e) = dispatch(&app, action).await {
+            crate::backend_log(&app, format!("{} command failed: {}", source, e));
+        }
+    }
+}
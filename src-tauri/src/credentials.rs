@@ -0,0 +1,165 @@
+// Secrets the backend needs to keep across restarts (service tokens and the
+// like), stored as a JSON object in `credentials.json` in the app data
+// directory. On Unix the file is only readable by the current user.
+//
+// The app, the daemon and the command line tool can all have the file open,
+// so it is read again for every lookup, and changes are made while holding a
+// lock file so that one process does not overwrite another's keys.
+
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+pub const CREDENTIALS_FILE: &str = "credentials.json";
+// Matches `identifier` in `tauri.conf.json`, which names the app data directory.
+pub const APP_IDENTIFIER: &str = "com.afheredi.playback";
+// How long to wait for another process to finish a change, and how old a
+// lock file has to be to be left over from a process that died.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+const STALE_LOCK: Duration = Duration::from_secs(30);
+
+/// Where the app keeps its credentials, for tools that run without Tauri.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join(APP_IDENTIFIER).join(CREDENTIALS_FILE))
+}
+
+pub struct Credentials {
+    path: PathBuf,
+}
+
+// Held while changing the file; removes the lock file when dropped.
+struct Lock(PathBuf);
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+impl Credentials {
+    pub fn open(path: PathBuf) -> Self {
+        Credentials { path }
+    }
+
+    // A missing or unreadable file has no credentials in it.
+    fn read(&self) -> BTreeMap<String, String> {
+        std::fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.read().remove(key)
+    }
+
+    /// Store `value` under `key`, or forget the key if `value` is `None`.
+    pub fn set(&self, key: &str, value: Option<String>) -> Result<(), String> {
+        let _lock = self.lock().map_err(|e| format!("Failed to lock credentials: {}", e))?;
+        let mut values = self.read();
+        match value {
+            Some(value) => values.insert(key.to_string(), value),
+            None => values.remove(key),
+        };
+        self.save(&values)
+            .map_err(|e| format!("Failed to save credentials: {}", e))
+    }
+
+    fn lock(&self) -> std::io::Result<Lock> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let path = self.path.with_extension("lock");
+        let started = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(Lock(path)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let age = std::fs::metadata(&path)
+                        .and_then(|metadata| metadata.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok());
+                    if age.is_some_and(|age| age > STALE_LOCK) {
+                        let _ = std::fs::remove_file(&path);
+                    } else if started.elapsed() > LOCK_TIMEOUT {
+                        return Err(std::io::Error::new(
+                            ErrorKind::TimedOut,
+                            format!("{} is held by another process", path.display()),
+                        ));
+                    } else {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn save(&self, values: &BTreeMap<String, String>) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("tmp");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let mut file = options.open(&tmp)?;
+        file.write_all(&serde_json::to_vec_pretty(values)?)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify::fixtures::temp_path;
+
+    #[test]
+    fn processes_keep_each_others_keys() {
+        let dir = temp_path("credentials");
+        let path = dir.join("data").join(CREDENTIALS_FILE);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // Opened before the directory exists, as on first start.
+        let app = Credentials::open(path.clone());
+        let daemon = Credentials::open(path.clone());
+        app.set("a", Some("1".to_string())).unwrap();
+        daemon.set("b", Some("2".to_string())).unwrap();
+        app.set("c", Some("3".to_string())).unwrap();
+        assert_eq!(daemon.get("a").as_deref(), Some("1"));
+        assert_eq!(daemon.get("c").as_deref(), Some("3"));
+        assert_eq!(app.get("b").as_deref(), Some("2"));
+
+        daemon.set("a", None).unwrap();
+        assert_eq!(app.get("a"), None);
+        assert_eq!(Credentials::open(path.clone()).read().len(), 2);
+        assert!(!path.with_extension("lock").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_changes_are_all_kept() {
+        let path = temp_path("credentials-concurrent.json");
+        let _ = std::fs::remove_file(&path);
+        std::thread::scope(|scope| {
+            for i in 0..8 {
+                let path = path.clone();
+                scope.spawn(move || {
+                    Credentials::open(path)
+                        .set(&format!("key{}", i), Some(i.to_string()))
+                        .unwrap()
+                });
+            }
+        });
+        assert_eq!(Credentials::open(path.clone()).read().len(), 8);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
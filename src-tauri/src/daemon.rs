@@ -0,0 +1,434 @@
+// Headless mode (`app --daemon`) for machines that run the backend as a
+// service, without a window or a display.
+//
+// Instead of following the frontend's polling, the daemon polls Spotify
+// itself with the tokens stored when the user signed in through the app, and
+// feeds the listening history, the scrobblers, the overlay files and the
+// outside controllers, set up by `services` just like in the app. It serves
+// the local control API and its event stream on the same port as the app.
+// `TrackChanged` events carry no palette or backdrops, since those come from
+// the app's artwork cache.
+//
+// Only one of the app and the daemon can run at a time: the app does not
+// attach to a running daemon as a client, and refuses to start its server
+// while the daemon holds the port.
+//
+// Logs go to stdout, with syslog priority prefixes when stdout is connected
+// to journald. SIGTERM (or Ctrl+C) stops the daemon; SIGHUP reloads the
+// config and the credentials.
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::{mpsc, watch};
+
+use crate::api::{self, Route};
+use crate::config::{self, Config};
+use crate::control::{self, Action};
+use crate::credentials::APP_IDENTIFIER;
+use crate::events::{PlaybackEvents, TrackChanged};
+use crate::history::History;
+use crate::scrobble;
+use crate::services::{Commands, Services};
+use crate::spotify::PlaybackState;
+use crate::{auth, player, ws};
+
+fn default_poll_interval_secs() -> u64 {
+    5
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DaemonConfig {
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+    // One of `error`, `warn`, `info`, `debug` or `trace`.
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        DaemonConfig {
+            poll_interval_secs: default_poll_interval_secs(),
+            log_level: default_log_level(),
+        }
+    }
+}
+
+struct Logger {
+    journald: bool,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        // Losing stdout must not take the daemon down.
+        let mut stdout = std::io::stdout().lock();
+        if self.journald {
+            // journald adds its own timestamps and reads the priority from
+            // the `<N>` prefix.
+            let priority = match record.level() {
+                log::Level::Error => 3,
+                log::Level::Warn => 4,
+                log::Level::Info => 6,
+                log::Level::Debug | log::Level::Trace => 7,
+            };
+            let _ = writeln!(stdout, "<{}>{}", priority, record.args());
+        } else {
+            let _ = writeln!(
+                stdout,
+                "{} {:<5} {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn set_log_level(level: &str) {
+    match level.parse::<log::LevelFilter>() {
+        Ok(level) => log::set_max_level(level),
+        Err(_) => log::warn!("Unknown log level '{}', keeping the current one.", level),
+    }
+}
+
+// Everything that is rebuilt from disk on SIGHUP.
+struct Loaded {
+    config: DaemonConfig,
+    services: Services,
+}
+
+impl Loaded {
+    fn load(config_path: &Path, data_dir: &Path, previous: Option<&Loaded>) -> (Self, Vec<Commands>) {
+        let config = Config::load(config_path).unwrap_or_else(|e| {
+            log::error!("{}", e);
+            Config::default()
+        });
+        let (services, commands) = Services::load(
+            &config,
+            config_path,
+            data_dir,
+            previous.map(|previous| &previous.services),
+        );
+        let loaded = Loaded {
+            config: config.daemon,
+            services,
+        };
+        (loaded, commands)
+    }
+}
+
+struct Daemon {
+    config_path: PathBuf,
+    data_dir: PathBuf,
+    history: Option<History>,
+    loaded: Mutex<Arc<Loaded>>,
+    // The last playback payload, `None` if no device is active.
+    playback: Mutex<Option<PlaybackState>>,
+    events: PlaybackEvents,
+}
+
+impl Daemon {
+    fn loaded(&self) -> Arc<Loaded> {
+        self.loaded.lock().unwrap().clone()
+    }
+
+    fn reload(self: &Arc<Self>) {
+        let previous = self.loaded();
+        let (loaded, commands) = Loaded::load(&self.config_path, &self.data_dir, Some(&previous));
+        set_log_level(&loaded.config.log_level);
+        *self.loaded.lock().unwrap() = Arc::new(loaded);
+        for (source, commands) in commands {
+            tauri::async_runtime::spawn(run_commands(self.clone(), source, commands));
+        }
+        log::info!("Reloaded {}.", self.config_path.display());
+    }
+
+    async fn control(&self, action: Action) -> Result<(), String> {
+        let access = self.access_token().await?;
+        let known = self.playback.lock().unwrap().clone();
+        control::perform(&access, &action, known.as_ref()).await?;
+        if let Some(state) = self.playback.lock().unwrap().as_mut() {
+            control::apply(state, &action);
+        }
+        Ok(())
+    }
+
+    async fn access_token(&self) -> Result<String, String> {
+        auth::access_token(&self.loaded().services.credentials).await
+    }
+
+    fn observe(&self, state: Option<&PlaybackState>) {
+        let loaded = self.loaded();
+        let services = &loaded.services;
+        if let Some(overlay) = &services.overlay {
+            if let Some(cover) = overlay.update(state) {
+                let overlay = overlay.clone();
+                tauri::async_runtime::spawn(async move { overlay.save_cover(cover, None).await });
+            }
+        }
+        if let Some(mqtt) = &services.mqtt {
+            mqtt.update(state);
+        }
+        if let Some(osc) = &services.osc {
+            osc.update(state);
+        }
+        let Some(state) = state else {
+            return;
+        };
+        if let Some(history) = &self.history {
+            if let Err(e) = history.observe(state) {
+                log::error!("Failed to record listening history: {}", e);
+            }
+        }
+        services.scrobblers.observe(state, |message| log::info!("{}", message));
+
+        let (events, new_track) = self.events.changes(state);
+        for event in events {
+            self.events.send(event);
+        }
+        if let Some((track, generation)) = new_track {
+            let changed = TrackChanged {
+                track,
+                palette: None,
+                backdrops: Vec::new(),
+            };
+            self.events
+                .track_changed(generation, changed, |event| self.events.send(event));
+        }
+    }
+}
+
+async fn poll(daemon: Arc<Daemon>, mut stopped: watch::Receiver<bool>) {
+    // Only log an error when it changes, not on every poll.
+    let mut last_error = None;
+    loop {
+        let result = match daemon.access_token().await {
+            Ok(access) => player::playback_state(&access).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(state) => {
+                if last_error.take().is_some() {
+                    log::info!("Polling Spotify again.");
+                }
+                daemon.observe(state.as_ref());
+                *daemon.playback.lock().unwrap() = state;
+            }
+            Err(e) => {
+                if last_error.as_ref() != Some(&e) {
+                    log::warn!("Failed to fetch playback: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        let interval = Duration::from_secs(daemon.loaded().config.poll_interval_secs.max(1));
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = stopped.changed() => return,
+        }
+    }
+}
+
+// Carry out commands from an outside controller until it is replaced.
+async fn run_commands(daemon: Arc<Daemon>, source: &'static str, mut commands: mpsc::UnboundedReceiver<Action>) {
+    while let Some(action) = commands.recv().await {
+        if let Err(e) = daemon.control(action).await {
+            log::warn!("{} command failed: {}", source, e);
+        }
+    }
+}
+
+async fn retry_queue(daemon: Arc<Daemon>, mut stopped: watch::Receiver<bool>) {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(scrobble::RETRY_INTERVAL) => {}
+            _ = stopped.changed() => return,
+        }
+        daemon
+            .loaded()
+            .services
+            .scrobblers
+            .retry(|message| log::info!("{}", message))
+            .await;
+    }
+}
+
+async fn handle(daemon: Arc<Daemon>, mut req: Request<Body>) -> Response<Body> {
+    if !req.uri().path().starts_with("/api/") {
+        return api::error(StatusCode::NOT_FOUND, "Not Found");
+    }
+    let loaded = daemon.loaded();
+    let services = &loaded.services;
+    let route = match api::accept(services.api_enabled, Some(&services.credentials), &mut req).await {
+        Ok(Route::Events) => return ws::handle(daemon.events.subscribe(), req),
+        Ok(route) => route,
+        Err(response) => return response,
+    };
+    let access = match daemon.access_token().await {
+        Ok(access) => access,
+        Err(e) => return api::error(StatusCode::SERVICE_UNAVAILABLE, e),
+    };
+
+    match route {
+        Route::NowPlaying => {
+            let playback = daemon.playback.lock().unwrap().clone();
+            api::json_response(StatusCode::OK, &playback)
+        }
+        Route::Control(action) => api::reply_ok(daemon.control(action).await),
+        Route::Playlist(id) => {
+            api::reply_ok(player::play_context(&access, &format!("spotify:playlist:{}", id)).await)
+        }
+        Route::Devices => api::reply(
+            player::devices(&access)
+                .await
+                .map(|devices| json!({ "devices": devices })),
+        ),
+        Route::Events => api::error(StatusCode::NOT_FOUND, "Not Found"),
+    }
+}
+
+// Resolves once the daemon is told to stop.
+async fn stop_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(e) => {
+                log::error!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(unix)]
+async fn reload_on_hangup(daemon: Arc<Daemon>) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            log::error!("Failed to listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        daemon.reload();
+    }
+}
+
+async fn serve(daemon: Arc<Daemon>) -> Result<(), String> {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 4242));
+    let server = hyper::Server::try_bind(&addr)
+        .map_err(|e| format!("Failed to listen on {} (is the app running?): {}", addr, e))?;
+
+    let (stop, stopped) = watch::channel(false);
+    let poller = tauri::async_runtime::spawn(poll(daemon.clone(), stopped.clone()));
+    let retry = tauri::async_runtime::spawn(retry_queue(daemon.clone(), stopped.clone()));
+    #[cfg(unix)]
+    let reload = tauri::async_runtime::spawn(reload_on_hangup(daemon.clone()));
+
+    let make_svc = make_service_fn(move |_conn| {
+        let daemon = daemon.clone();
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req| {
+                let daemon = daemon.clone();
+                async move { Ok::<_, hyper::Error>(handle(daemon, req).await) }
+            }))
+        }
+    });
+    let mut shutdown = stopped.clone();
+    let server = server.serve(make_svc).with_graceful_shutdown(async move {
+        let _ = shutdown.changed().await;
+    });
+    log::info!("Serving the control API on http://{}.", addr);
+
+    tokio::select! {
+        result = server => result.map_err(|e| format!("Server error: {}", e))?,
+        _ = stop_signal() => {
+            log::info!("Stopping.");
+            let _ = stop.send(true);
+        }
+    }
+    let _ = poller.await;
+    let _ = retry.await;
+    #[cfg(unix)]
+    reload.abort();
+    Ok(())
+}
+
+/// Run the backend without a window until it is told to stop, and return the
+/// process exit code.
+pub fn run() -> i32 {
+    let journald = std::env::var_os("JOURNAL_STREAM").is_some();
+    if log::set_logger(Box::leak(Box::new(Logger { journald }))).is_err() {
+        eprintln!("A logger is already installed.");
+    }
+    log::set_max_level(log::LevelFilter::Info);
+
+    let (Some(config_path), Some(data_dir)) =
+        (config::default_path(), dirs::data_dir().map(|dir| dir.join(APP_IDENTIFIER)))
+    else {
+        log::error!("Could not find the app config and data directories.");
+        return 1;
+    };
+    if let Err(e) = std::fs::create_dir_all(&data_dir) {
+        log::error!("Failed to create {}: {}", data_dir.display(), e);
+        return 1;
+    }
+
+    let (loaded, commands) = Loaded::load(&config_path, &data_dir, None);
+    set_log_level(&loaded.config.log_level);
+    // Playback keeps working without the history.
+    let history = History::open(&data_dir.join("history.sqlite3"))
+        .map_err(|e| log::error!("Failed to open listening history: {}", e))
+        .ok();
+
+    let daemon = Arc::new(Daemon {
+        config_path,
+        data_dir,
+        history,
+        loaded: Mutex::new(Arc::new(loaded)),
+        playback: Mutex::new(None),
+        events: PlaybackEvents::default(),
+    });
+    for (source, commands) in commands {
+        tauri::async_runtime::spawn(run_commands(daemon.clone(), source, commands));
+    }
+    match tauri::async_runtime::block_on(serve(daemon)) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::error!("{}", e);
+            1
+        }
+    }
+}
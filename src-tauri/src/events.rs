@@ -0,0 +1,301 @@
+// Events the backend emits as it follows playback.
+//
+// Each event goes to the frontend as a Tauri event and onto a broadcast bus
+// for other consumers (the WebSocket stream). `PlaybackEvents` works out the
+// events without Tauri, so the headless daemon can run the same bus. On the bus events are wrapped
+// in an `Envelope` carrying the schema version; bump `SCHEMA_VERSION` when
+// an event's fields change incompatibly.
+
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::{Emitter, Manager};
+use tokio::sync::broadcast;
+
+use crate::backdrop::{self, Backdrop};
+use crate::history::now_ms;
+use crate::palette::{self, Palette};
+use crate::spotify::{Device, PlaybackState, Track};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Tauri event names.
+pub const TRACK_CHANGED: &str = "track-changed";
+pub const PLAYBACK_STATE_CHANGED: &str = "playback-state-changed";
+pub const PROGRESS: &str = "playback-progress";
+pub const VOLUME_CHANGED: &str = "volume-changed";
+pub const DEVICE_CHANGED: &str = "device-changed";
+
+// Events waiting for a slow consumer before it starts missing some.
+const BUS_CAPACITY: usize = 64;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TrackChanged {
+    pub track: Track,
+    // Missing if the album art could not be loaded or decoded.
+    pub palette: Option<Palette>,
+    // Blurred backgrounds rendered from the album art, smallest first.
+    pub backdrops: Vec<Backdrop>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PlaybackStateChanged {
+    pub is_playing: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Progress {
+    pub progress_ms: u32,
+    pub duration_ms: u32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct VolumeChanged {
+    pub volume_percent: u32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum PlaybackEvent {
+    TrackChanged(Box<TrackChanged>),
+    PlaybackState(PlaybackStateChanged),
+    Progress(Progress),
+    Volume(VolumeChanged),
+    Device(Device),
+}
+
+impl PlaybackEvent {
+    // Names used in the `type` field and in subscription filters.
+    pub const KINDS: [&'static str; 5] = ["track_changed", "playback_state", "progress", "volume", "device"];
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PlaybackEvent::TrackChanged(_) => "track_changed",
+            PlaybackEvent::PlaybackState(_) => "playback_state",
+            PlaybackEvent::Progress(_) => "progress",
+            PlaybackEvent::Volume(_) => "volume",
+            PlaybackEvent::Device(_) => "device",
+        }
+    }
+
+    // Emit to the frontend, with the same payload as the `data` on the bus.
+    fn emit(&self, app: &tauri::AppHandle) -> tauri::Result<()> {
+        match self {
+            PlaybackEvent::TrackChanged(data) => app.emit(TRACK_CHANGED, data),
+            PlaybackEvent::PlaybackState(data) => app.emit(PLAYBACK_STATE_CHANGED, data),
+            PlaybackEvent::Progress(data) => app.emit(PROGRESS, data),
+            PlaybackEvent::Volume(data) => app.emit(VOLUME_CHANGED, data),
+            PlaybackEvent::Device(data) => app.emit(DEVICE_CHANGED, data),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Envelope {
+    pub version: u32,
+    // Milliseconds since the Unix epoch.
+    pub timestamp: i64,
+    #[serde(flatten)]
+    pub event: PlaybackEvent,
+}
+
+// What the previous playback payload showed.
+#[derive(Default)]
+struct Last {
+    track: Option<String>,
+    // Counts track changes, so a slow `TrackChanged` can tell that a newer
+    // track has started.
+    generation: u64,
+    is_playing: Option<bool>,
+    device: Option<String>,
+    volume: Option<u32>,
+}
+
+pub struct PlaybackEvents {
+    last: Mutex<Last>,
+    bus: broadcast::Sender<Envelope>,
+}
+
+impl Default for PlaybackEvents {
+    fn default() -> Self {
+        PlaybackEvents {
+            last: Mutex::new(Last::default()),
+            bus: broadcast::channel(BUS_CAPACITY).0,
+        }
+    }
+}
+
+impl PlaybackEvents {
+    /// Receive every event sent from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Envelope> {
+        self.bus.subscribe()
+    }
+
+    /// Put `event` on the bus.
+    pub fn send(&self, event: PlaybackEvent) {
+        // Sending only fails when nobody is listening.
+        let _ = self.bus.send(Envelope {
+            version: SCHEMA_VERSION,
+            timestamp: now_ms(),
+            event,
+        });
+    }
+
+    /// What changed since the previous playback payload, and the progress of
+    /// every payload with a track. A new track is returned separately with
+    /// its generation, to be announced with `track_changed` once its
+    /// palette is known.
+    pub fn changes(&self, state: &PlaybackState) -> (Vec<PlaybackEvent>, Option<(Track, u64)>) {
+        let mut events = Vec::new();
+        let mut last = self.last.lock().unwrap();
+
+        if last.is_playing != Some(state.is_playing) {
+            last.is_playing = Some(state.is_playing);
+            events.push(PlaybackEvent::PlaybackState(PlaybackStateChanged {
+                is_playing: state.is_playing,
+            }));
+        }
+        // `/me/player/currently-playing` has no device, so only payloads
+        // with one can tell whether it changed.
+        if let Some(device) = &state.device {
+            if last.device != device.id {
+                last.device = device.id.clone();
+                events.push(PlaybackEvent::Device(device.clone()));
+            }
+            if let Some(volume) = device.volume_percent.filter(|v| last.volume != Some(*v)) {
+                last.volume = Some(volume);
+                events.push(PlaybackEvent::Volume(VolumeChanged { volume_percent: volume }));
+            }
+        }
+        if let Some(track) = &state.item {
+            events.push(PlaybackEvent::Progress(Progress {
+                progress_ms: state.progress_ms.unwrap_or(0),
+                duration_ms: track.duration_ms,
+            }));
+        }
+
+        let new_track = match &state.item {
+            Some(track) if last.track.as_deref() != Some(track.uri.as_str()) => {
+                last.track = Some(track.uri.clone());
+                last.generation += 1;
+                Some((track.clone(), last.generation))
+            }
+            _ => None,
+        };
+        (events, new_track)
+    }
+
+    /// Hand `changed` to `publish`, unless a newer track has started since
+    /// the one from `generation`.
+    pub fn track_changed(&self, generation: u64, changed: TrackChanged, publish: impl FnOnce(PlaybackEvent)) {
+        // Held while publishing so a newer track cannot slip in between.
+        let last = self.last.lock().unwrap();
+        if last.generation == generation {
+            publish(PlaybackEvent::TrackChanged(Box::new(changed)));
+        }
+    }
+}
+
+/// Receive every event published from now on.
+pub fn subscribe(app: &tauri::AppHandle) -> broadcast::Receiver<Envelope> {
+    app.state::<PlaybackEvents>().subscribe()
+}
+
+pub fn publish(app: &tauri::AppHandle, event: PlaybackEvent) {
+    if let Err(err) = event.emit(app) {
+        log::error!("Failed to emit {} event: {:?}", event.kind(), err);
+    }
+    app.state::<PlaybackEvents>().send(event);
+}
+
+// Publish what a playback payload changed. `TrackChanged` follows once the
+// palette of the new track's artwork is ready.
+pub fn observe(app: &tauri::AppHandle, state: &PlaybackState) {
+    let (events, new_track) = app.state::<PlaybackEvents>().changes(state);
+    for event in events {
+        publish(app, event);
+    }
+
+    let Some((track, generation)) = new_track else {
+        return;
+    };
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let image = track.album.images.first().map(|image| image.url.clone());
+        let palette = match &image {
+            Some(url) => palette::for_image(&app, url)
+                .await
+                .map_err(|e| log::warn!("Failed to compute palette: {}", e))
+                .ok(),
+            None => None,
+        };
+        let backdrops = image.as_deref().map(backdrop::urls).unwrap_or_default();
+        let changed = TrackChanged {
+            track,
+            palette,
+            backdrops,
+        };
+        app.state::<PlaybackEvents>()
+            .track_changed(generation, changed, |event| publish(&app, event));
+
+        if let Some(url) = image {
+            backdrop::prepare(&app, &url).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify::fixtures::playing;
+
+    fn kinds(events: &[PlaybackEvent]) -> Vec<&'static str> {
+        events.iter().map(PlaybackEvent::kind).collect()
+    }
+
+    #[test]
+    fn only_changes_are_reported() {
+        let events = PlaybackEvents::default();
+        let (first, new_track) = events.changes(&playing("a", 0));
+        assert_eq!(kinds(&first), ["playback_state", "device", "volume", "progress"]);
+        assert_eq!(
+            new_track.map(|(track, generation)| (track.name, generation)),
+            Some(("Title a".to_string(), 1))
+        );
+
+        let (second, new_track) = events.changes(&playing("a", 5_000));
+        assert_eq!(kinds(&second), ["progress"]);
+        assert!(new_track.is_none());
+
+        // Without a device nothing is known about it, so nothing changed.
+        let mut paused = playing("b", 0);
+        paused.is_playing = false;
+        paused.device = None;
+        let (third, new_track) = events.changes(&paused);
+        assert_eq!(kinds(&third), ["playback_state", "progress"]);
+        assert_eq!(new_track.map(|(_, generation)| generation), Some(2));
+    }
+
+    #[test]
+    fn stale_track_changes_are_dropped() {
+        let events = PlaybackEvents::default();
+        let mut bus = events.subscribe();
+        let (_, first) = events.changes(&playing("a", 0));
+        let (_, second) = events.changes(&playing("b", 0));
+        let changed = |(track, _): &(Track, u64)| TrackChanged {
+            track: track.clone(),
+            palette: None,
+            backdrops: Vec::new(),
+        };
+        let (first, second) = (first.unwrap(), second.unwrap());
+
+        // The newer track's event is ready first; the older one arrives late.
+        events.track_changed(second.1, changed(&second), |event| events.send(event));
+        events.track_changed(first.1, changed(&first), |event| events.send(event));
+        match bus.try_recv().unwrap().event {
+            PlaybackEvent::TrackChanged(changed) => assert_eq!(changed.track.name, "Title b"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(bus.try_recv().is_err());
+    }
+}
@@ -0,0 +1,315 @@
+// Export and import of the listening history as CSV or JSON Lines.
+//
+// Both formats carry the same flat record. Every record states the format
+// version it was written with and a `key` identifying the play, so files can
+// be imported any number of times without duplicating plays. Artist names and
+// IDs are a JSON array in a single column.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use chrono::{DateTime, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use tauri::command;
+
+use crate::history::{History, HistoryQuery, PlayRecord};
+
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+}
+
+impl ExportFormat {
+    // Guess the format from a file extension, for imports.
+    fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "csv" => Some(ExportFormat::Csv),
+            "jsonl" | "ndjson" => Some(ExportFormat::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ExportRecord {
+    pub version: u32,
+    // `<track URI>@<start time in Unix milliseconds>`.
+    pub key: String,
+    // RFC 3339, UTC, millisecond precision.
+    pub played_at: String,
+    pub track_id: Option<String>,
+    pub track_uri: String,
+    pub title: String,
+    pub artists: String,
+    pub artist_ids: String,
+    pub album: String,
+    pub context_uri: Option<String>,
+    pub device: Option<String>,
+    pub duration_ms: u32,
+    pub listened_ms: u32,
+    pub skipped: bool,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ImportSummary {
+    pub read: usize,
+    pub imported: usize,
+    pub duplicates: usize,
+}
+
+fn dedup_key(track_uri: &str, started_at: i64) -> String {
+    format!("{}@{}", track_uri, started_at)
+}
+
+fn join_list(items: &[String]) -> String {
+    serde_json::to_string(items).unwrap_or_default()
+}
+
+fn split_list(list: &str) -> Result<Vec<String>, String> {
+    if list.is_empty() {
+        return Ok(Vec::new());
+    }
+    serde_json::from_str(list).map_err(|e| format!("Invalid list '{}': {}", list, e))
+}
+
+impl ExportRecord {
+    pub fn from_play(play: &PlayRecord) -> Self {
+        let played_at = DateTime::from_timestamp_millis(play.started_at)
+            .unwrap_or_default()
+            .to_rfc3339_opts(SecondsFormat::Millis, true);
+        ExportRecord {
+            version: FORMAT_VERSION,
+            key: dedup_key(&play.track_uri, play.started_at),
+            played_at,
+            track_id: play.track_id.clone(),
+            track_uri: play.track_uri.clone(),
+            title: play.title.clone(),
+            artists: join_list(&play.artists),
+            artist_ids: join_list(&play.artist_ids),
+            album: play.album.clone(),
+            context_uri: play.context_uri.clone(),
+            device: play.device.clone(),
+            duration_ms: play.duration_ms,
+            listened_ms: play.listened_ms,
+            skipped: play.skipped,
+        }
+    }
+
+    pub fn into_play(self) -> Result<PlayRecord, String> {
+        if self.version == 0 || self.version > FORMAT_VERSION {
+            return Err(format!("Unsupported history format version {}.", self.version));
+        }
+        let started_at = DateTime::parse_from_rfc3339(&self.played_at)
+            .map_err(|e| format!("Invalid played_at '{}': {}", self.played_at, e))?
+            .timestamp_millis();
+        if self.key != dedup_key(&self.track_uri, started_at) {
+            return Err(format!("Key '{}' does not match the record.", self.key));
+        }
+        let artists = split_list(&self.artists)?;
+        let artist_ids = split_list(&self.artist_ids)?;
+
+        Ok(PlayRecord {
+            id: 0,
+            track_id: self.track_id.filter(|id| !id.is_empty()),
+            track_uri: self.track_uri,
+            title: self.title,
+            artists,
+            artist_ids,
+            album: self.album,
+            context_uri: self.context_uri.filter(|uri| !uri.is_empty()),
+            device: self.device.filter(|device| !device.is_empty()),
+            started_at,
+            duration_ms: self.duration_ms,
+            listened_ms: self.listened_ms,
+            skipped: self.skipped,
+        })
+    }
+}
+
+pub fn write_records(path: &Path, format: ExportFormat, plays: &[PlayRecord]) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    let records = plays.iter().map(ExportRecord::from_play);
+
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(file);
+            for record in records {
+                writer.serialize(record).map_err(|e| e.to_string())?;
+            }
+            writer.flush().map_err(|e| e.to_string())
+        }
+        ExportFormat::Jsonl => {
+            let mut writer = BufWriter::new(file);
+            for record in records {
+                serde_json::to_writer(&mut writer, &record).map_err(|e| e.to_string())?;
+                writer.write_all(b"\n").map_err(|e| e.to_string())?;
+            }
+            writer.flush().map_err(|e| e.to_string())
+        }
+    }
+}
+
+pub fn read_records(path: &Path, format: ExportFormat) -> Result<Vec<PlayRecord>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+
+    let records: Vec<ExportRecord> = match format {
+        ExportFormat::Csv => csv::Reader::from_reader(file)
+            .deserialize()
+            .enumerate()
+            .map(|(i, record)| record.map_err(|e| format!("Row {}: {}", i + 1, e)))
+            .collect::<Result<_, _>>()?,
+        ExportFormat::Jsonl => BufReader::new(file)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|(i, line)| {
+                let line = line.map_err(|e| e.to_string())?;
+                serde_json::from_str(&line).map_err(|e| format!("Line {}: {}", i + 1, e))
+            })
+            .collect::<Result<_, String>>()?,
+    };
+
+    records.into_iter().map(ExportRecord::into_play).collect()
+}
+
+// Writes the plays in the given range (Unix milliseconds, both optional) and
+// returns how many were exported.
+#[command]
+pub fn export_history(
+    history: tauri::State<'_, History>,
+    path: String,
+    format: ExportFormat,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<usize, String> {
+    let query = HistoryQuery {
+        from,
+        to,
+        ..Default::default()
+    };
+    let mut plays = history
+        .query(&query)
+        .map_err(|e| format!("Failed to query listening history: {}", e))?;
+    // Oldest first reads more naturally in a spreadsheet.
+    plays.reverse();
+
+    write_records(Path::new(&path), format, &plays)?;
+    Ok(plays.len())
+}
+
+// `format` defaults to the one matching the file extension.
+#[command]
+pub fn import_history(
+    history: tauri::State<'_, History>,
+    path: String,
+    format: Option<ExportFormat>,
+) -> Result<ImportSummary, String> {
+    let path = Path::new(&path);
+    let format = format
+        .or_else(|| ExportFormat::from_path(path))
+        .ok_or("Unknown history file format, expected .csv or .jsonl.")?;
+
+    let plays = read_records(path, format)?;
+    let imported = history
+        .import(&plays)
+        .map_err(|e| format!("Failed to import listening history: {}", e))?;
+
+    Ok(ImportSummary {
+        read: plays.len(),
+        imported,
+        duplicates: plays.len() - imported,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify::fixtures::temp_path;
+
+    fn play(track: &str, artists: &[&str], started_at: i64) -> PlayRecord {
+        PlayRecord {
+            id: 0,
+            track_id: Some(track.to_string()),
+            track_uri: format!("spotify:track:{}", track),
+            title: "Title, \"quoted\"\nand split".to_string(),
+            artists: artists.iter().map(|name| name.to_string()).collect(),
+            artist_ids: artists.iter().map(|name| format!("id{}", name.len())).collect(),
+            album: "Album".to_string(),
+            context_uri: Some("spotify:playlist:p".to_string()),
+            device: None,
+            started_at,
+            duration_ms: 200_000,
+            listened_ms: 150_000,
+            skipped: false,
+        }
+    }
+
+    fn in_memory() -> History {
+        History::open(Path::new(":memory:")).unwrap()
+    }
+
+    #[test]
+    fn round_trips_lists_with_separators() {
+        let plays = [
+            play("a", &["Crosby, Stills; Nash", "Young"], 1_700_000_000_123),
+            play("b", &[], 1_700_000_300_000),
+        ];
+        for (format, name) in [
+            (ExportFormat::Csv, "export-lists.csv"),
+            (ExportFormat::Jsonl, "export-lists.jsonl"),
+        ] {
+            let path = temp_path(name);
+            write_records(&path, format, &plays).unwrap();
+            let read = read_records(&path, format).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(read, plays, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn rejects_mismatched_keys_and_versions() {
+        let mut record = ExportRecord::from_play(&play("a", &["One"], 1_700_000_000_000));
+        record.key = "spotify:track:a@1".to_string();
+        assert!(record.into_play().is_err());
+
+        let mut record = ExportRecord::from_play(&play("a", &["One"], 1_700_000_000_000));
+        record.version = FORMAT_VERSION + 1;
+        assert!(record.into_play().is_err());
+    }
+
+    #[test]
+    fn import_ignores_duplicates() {
+        let source = in_memory();
+        source
+            .import(&[
+                play("a", &["One"], 1_700_000_000_000),
+                play("b", &["Two"], 1_700_000_200_000),
+                play("a", &["One"], 1_700_000_400_000),
+            ])
+            .unwrap();
+        let exported = source.query(&HistoryQuery::default()).unwrap();
+        let path = temp_path("export-import.jsonl");
+        write_records(&path, ExportFormat::Jsonl, &exported).unwrap();
+        let plays = read_records(&path, ExportFormat::Jsonl).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let target = in_memory();
+        assert_eq!(target.import(&plays).unwrap(), 3);
+        // Importing the same file again adds nothing.
+        assert_eq!(target.import(&plays).unwrap(), 0);
+        // Neither do duplicates within one file.
+        assert_eq!(target.import(&[plays[0].clone(), plays[0].clone()]).unwrap(), 0);
+
+        let mut imported = target.query(&HistoryQuery::default()).unwrap();
+        assert_eq!(imported.len(), 3);
+        for (imported, exported) in imported.iter_mut().zip(&exported) {
+            imported.id = exported.id;
+        }
+        assert_eq!(imported, exported);
+    }
+}
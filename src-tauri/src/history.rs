@@ -0,0 +1,600 @@
+// Local listening history.
+//
+// Every playback payload the backend fetches is fed to `History::observe`,
+// which works out when the track changes and keeps one row per play in an
+// SQLite database under the app data directory.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use tauri::{command, Manager};
+
+use crate::spotify::{Artist, PlaybackState};
+
+// A play that stops more than this far from the end of the track counts as
+// skipped. Polling only samples the progress every few seconds, so a track
+// that ran out is usually last seen a little before its end.
+const END_SLACK_MS: u32 = 10_000;
+
+// Two observations of the same track are treated as the same play (e.g.
+// across an app restart) if their derived start times are this close.
+const RESUME_SLACK_MS: i64 = 10_000;
+
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE plays (
+        id          INTEGER PRIMARY KEY,
+        track_id    TEXT,
+        track_uri   TEXT NOT NULL,
+        title       TEXT NOT NULL,
+        artists     TEXT NOT NULL,
+        artist_ids  TEXT NOT NULL,
+        album       TEXT NOT NULL,
+        context_uri TEXT,
+        device      TEXT,
+        started_at  INTEGER NOT NULL,
+        duration_ms INTEGER NOT NULL,
+        listened_ms INTEGER NOT NULL DEFAULT 0,
+        skipped     INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX plays_started_at ON plays (started_at);
+    CREATE INDEX plays_context_uri ON plays (context_uri);
+"#, r#"
+    CREATE TABLE artist_genres (
+        artist_id  TEXT PRIMARY KEY,
+        name       TEXT NOT NULL,
+        genres     TEXT NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+"#, r#"
+    DELETE FROM plays WHERE id NOT IN (
+        SELECT MIN(id) FROM plays GROUP BY track_uri, started_at
+    );
+    CREATE UNIQUE INDEX plays_dedup ON plays (track_uri, started_at);
+"#];
+
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+// One row of the `plays` table. Times are Unix timestamps in milliseconds.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlayRecord {
+    pub id: i64,
+    pub track_id: Option<String>,
+    pub track_uri: String,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub artist_ids: Vec<String>,
+    pub album: String,
+    pub context_uri: Option<String>,
+    pub device: Option<String>,
+    pub started_at: i64,
+    pub duration_ms: u32,
+    pub listened_ms: u32,
+    pub skipped: bool,
+}
+
+impl PlayRecord {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let artists: String = row.get("artists")?;
+        let artist_ids: String = row.get("artist_ids")?;
+        Ok(PlayRecord {
+            id: row.get("id")?,
+            track_id: row.get("track_id")?,
+            track_uri: row.get("track_uri")?,
+            title: row.get("title")?,
+            artists: serde_json::from_str(&artists).unwrap_or_default(),
+            artist_ids: serde_json::from_str(&artist_ids).unwrap_or_default(),
+            album: row.get("album")?,
+            context_uri: row.get("context_uri")?,
+            device: row.get("device")?,
+            started_at: row.get("started_at")?,
+            duration_ms: row.get("duration_ms")?,
+            listened_ms: row.get("listened_ms")?,
+            skipped: row.get("skipped")?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HistoryQuery {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    // Artist name (case-insensitive) or Spotify artist ID.
+    pub artist: Option<String>,
+    // Playlist ID or `spotify:playlist:` URI.
+    pub playlist: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+// The play currently being tracked.
+struct CurrentPlay {
+    row_id: i64,
+    track_uri: String,
+    duration_ms: u32,
+    progress_ms: u32,
+    listened_ms: u32,
+    seen_at: i64,
+}
+
+pub struct History {
+    conn: Mutex<Connection>,
+    current: Mutex<Option<CurrentPlay>>,
+}
+
+impl History {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    fn with_connection(mut conn: Connection) -> rusqlite::Result<Self> {
+        migrate(&mut conn)?;
+        Ok(History {
+            conn: Mutex::new(conn),
+            current: Mutex::new(None),
+        })
+    }
+
+    /// Record a playback payload. Returns `true` when it starts a new play.
+    pub fn observe(&self, state: &PlaybackState) -> Result<bool, String> {
+        self.observe_at(state, now_ms()).map_err(|e| e.to_string())
+    }
+
+    fn observe_at(&self, state: &PlaybackState, now: i64) -> rusqlite::Result<bool> {
+        // Nothing to record while an ad plays; the current play carries on.
+        let Some(track) = &state.item else {
+            return Ok(false);
+        };
+        let progress_ms = state.progress_ms.unwrap_or(0);
+        let device = state.device.as_ref().map(|d| d.name.clone());
+
+        let conn = self.conn.lock().unwrap();
+        let mut current = self.current.lock().unwrap();
+
+        if let Some(play) = current.as_mut() {
+            let same_track = play.track_uri == track.uri;
+            let replayed = same_track
+                && progress_ms < play.progress_ms
+                && play.progress_ms + END_SLACK_MS >= play.duration_ms;
+
+            if same_track && !replayed {
+                // Only count progress that could have happened since the last
+                // observation, so seeking ahead does not add listening time.
+                if progress_ms > play.progress_ms {
+                    let elapsed = u32::try_from((now - play.seen_at).max(0)).unwrap_or(u32::MAX);
+                    play.listened_ms += (progress_ms - play.progress_ms).min(elapsed.saturating_add(1_000));
+                }
+                play.progress_ms = progress_ms;
+                play.seen_at = now;
+
+                conn.execute(
+                    "UPDATE plays SET listened_ms = ?1, device = COALESCE(device, ?2) WHERE id = ?3",
+                    params![play.listened_ms, device, play.row_id],
+                )?;
+                return Ok(false);
+            }
+
+            conn.execute(
+                "UPDATE plays SET listened_ms = ?1, skipped = ?2 WHERE id = ?3",
+                params![
+                    play.listened_ms,
+                    play.progress_ms + END_SLACK_MS < play.duration_ms,
+                    play.row_id
+                ],
+            )?;
+        }
+
+        let started_at = now - progress_ms as i64;
+
+        // After a restart, pick the play that was in progress back up instead
+        // of recording it twice.
+        let resumed = if current.is_none() {
+            conn.query_row(
+                "SELECT id, listened_ms FROM plays
+                 WHERE track_uri = ?1 AND ABS(started_at - ?2) < ?3
+                 ORDER BY id DESC LIMIT 1",
+                params![track.uri, started_at, RESUME_SLACK_MS],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, u32>(1)?)),
+            )
+            .optional()?
+        } else {
+            None
+        };
+
+        let (row_id, listened_ms) = match resumed {
+            Some(resumed) => resumed,
+            None => {
+                let artists: Vec<&str> = track.artists.iter().map(|a| a.name.as_str()).collect();
+                let artist_ids: Vec<&str> =
+                    track.artists.iter().filter_map(|a| a.id.as_deref()).collect();
+                conn.execute(
+                    "INSERT INTO plays (track_id, track_uri, title, artists, artist_ids, album,
+                                        context_uri, device, started_at, duration_ms)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                    params![
+                        track.id,
+                        track.uri,
+                        track.name,
+                        serde_json::to_string(&artists).unwrap_or_default(),
+                        serde_json::to_string(&artist_ids).unwrap_or_default(),
+                        track.album.name,
+                        state.context.as_ref().map(|c| c.uri.as_str()),
+                        device,
+                        started_at,
+                        track.duration_ms,
+                    ],
+                )?;
+                (conn.last_insert_rowid(), 0)
+            }
+        };
+
+        *current = Some(CurrentPlay {
+            row_id,
+            track_uri: track.uri.clone(),
+            duration_ms: track.duration_ms,
+            progress_ms,
+            listened_ms,
+            seen_at: now,
+        });
+        Ok(true)
+    }
+
+    /// Plays matching `query`, most recent first.
+    pub fn query(&self, query: &HistoryQuery) -> rusqlite::Result<Vec<PlayRecord>> {
+        let mut sql = "SELECT * FROM plays WHERE 1 = 1".to_string();
+        let mut args: Vec<rusqlite::types::Value> = Vec::new();
+
+        if let Some(from) = query.from {
+            args.push(from.into());
+            sql.push_str(&format!(" AND started_at >= ?{}", args.len()));
+        }
+        if let Some(to) = query.to {
+            args.push(to.into());
+            sql.push_str(&format!(" AND started_at < ?{}", args.len()));
+        }
+        if let Some(artist) = &query.artist {
+            args.push(artist.clone().into());
+            sql.push_str(&format!(
+                " AND (EXISTS (SELECT 1 FROM json_each(plays.artists) WHERE value = ?{n} COLLATE NOCASE)
+                    OR EXISTS (SELECT 1 FROM json_each(plays.artist_ids) WHERE value = ?{n}))",
+                n = args.len()
+            ));
+        }
+        if let Some(playlist) = &query.playlist {
+            let uri = if playlist.starts_with("spotify:") {
+                playlist.clone()
+            } else {
+                format!("spotify:playlist:{}", playlist)
+            };
+            args.push(uri.into());
+            sql.push_str(&format!(" AND context_uri = ?{}", args.len()));
+        }
+
+        sql.push_str(" ORDER BY started_at DESC");
+        args.push(i64::from(query.limit.unwrap_or(u32::MAX)).into());
+        sql.push_str(&format!(" LIMIT ?{}", args.len()));
+        args.push(i64::from(query.offset.unwrap_or(0)).into());
+        sql.push_str(&format!(" OFFSET ?{}", args.len()));
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(args), PlayRecord::from_row)?;
+        rows.collect()
+    }
+
+    /// Insert plays from elsewhere (e.g. an export from another machine).
+    /// Plays of the same track starting at the same millisecond are only
+    /// stored once, so importing the same file twice changes nothing. Returns
+    /// how many plays were new.
+    pub fn import(&self, records: &[PlayRecord]) -> rusqlite::Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let mut imported = 0;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR IGNORE INTO plays (track_id, track_uri, title, artists, artist_ids, album,
+                                              context_uri, device, started_at, duration_ms,
+                                              listened_ms, skipped)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            )?;
+            for record in records {
+                imported += stmt.execute(params![
+                    record.track_id,
+                    record.track_uri,
+                    record.title,
+                    serde_json::to_string(&record.artists).unwrap_or_default(),
+                    serde_json::to_string(&record.artist_ids).unwrap_or_default(),
+                    record.album,
+                    record.context_uri,
+                    record.device,
+                    record.started_at,
+                    record.duration_ms,
+                    record.listened_ms,
+                    record.skipped,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(imported)
+    }
+
+    /// Remember the genres of an artist seen while playing, for the genre mix
+    /// in the listening stats.
+    pub fn record_artist(&self, artist: &Artist) -> rusqlite::Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO artist_genres (artist_id, name, genres, updated_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (artist_id) DO UPDATE
+             SET name = excluded.name, genres = excluded.genres, updated_at = excluded.updated_at",
+            params![
+                artist.id,
+                artist.name,
+                serde_json::to_string(&artist.genres).unwrap_or_default(),
+                now_ms()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Known genres by artist ID.
+    pub fn artist_genres(&self) -> rusqlite::Result<HashMap<String, Vec<String>>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT artist_id, genres FROM artist_genres")?;
+        let rows = stmt.query_map([], |row| {
+            let genres: String = row.get(1)?;
+            Ok((row.get(0)?, serde_json::from_str(&genres).unwrap_or_default()))
+        })?;
+        rows.collect()
+    }
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+// Feed a playback payload to the history, if it could be opened at startup.
+pub fn observe(app: &tauri::AppHandle, state: &PlaybackState) {
+    if let Some(history) = app.try_state::<History>() {
+        if let Err(e) = history.observe(state) {
+            log::error!("Failed to record listening history: {}", e);
+        }
+    }
+}
+
+pub fn record_artist(app: &tauri::AppHandle, artist: &Artist) {
+    if let Some(history) = app.try_state::<History>() {
+        if let Err(e) = history.record_artist(artist) {
+            log::error!("Failed to record artist genres: {}", e);
+        }
+    }
+}
+
+#[command]
+pub fn query_history(
+    history: tauri::State<'_, History>,
+    from: Option<i64>,
+    to: Option<i64>,
+    artist: Option<String>,
+    playlist: Option<String>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+) -> Result<Vec<PlayRecord>, String> {
+    let query = HistoryQuery {
+        from,
+        to,
+        artist,
+        playlist,
+        limit,
+        offset,
+    };
+    history.query(&query).map_err(|e| format!("Failed to query listening history: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify::{fixtures, Context};
+
+    fn history() -> History {
+        History::open(Path::new(":memory:")).unwrap()
+    }
+
+    fn playing(track: &str, progress_ms: u32, context: Option<&str>) -> PlaybackState {
+        let mut state = fixtures::playing(track, progress_ms);
+        state.context = context.map(|uri| Context { uri: uri.to_string() });
+        state
+    }
+
+    fn all(history: &History) -> Vec<PlayRecord> {
+        history.query(&HistoryQuery::default()).unwrap()
+    }
+
+    #[test]
+    fn same_track_is_one_play() {
+        let history = history();
+        assert!(history.observe_at(&playing("a", 0, None), 1_000_000).unwrap());
+        assert!(!history.observe_at(&playing("a", 5_000, None), 1_005_000).unwrap());
+        assert!(!history.observe_at(&playing("a", 10_000, None), 1_010_000).unwrap());
+
+        let plays = all(&history);
+        assert_eq!(plays.len(), 1);
+        assert_eq!(plays[0].started_at, 1_000_000);
+        assert_eq!(plays[0].listened_ms, 10_000);
+        assert_eq!(plays[0].artists, vec!["One", "Two"]);
+        assert_eq!(plays[0].device.as_deref(), Some("Desk"));
+    }
+
+    #[test]
+    fn long_gaps_between_polls() {
+        let history = history();
+        history.observe_at(&playing("a", 0, None), 1_000_000).unwrap();
+        // Far more than a u32 of milliseconds later.
+        history
+            .observe_at(&playing("a", 100_000, None), 10_000_000_000)
+            .unwrap();
+        assert_eq!(all(&history)[0].listened_ms, 100_000);
+    }
+
+    #[test]
+    fn start_is_derived_from_progress() {
+        let history = history();
+        history.observe_at(&playing("a", 42_000, None), 1_000_000).unwrap();
+        assert_eq!(all(&history)[0].started_at, 958_000);
+    }
+
+    #[test]
+    fn seeking_does_not_count_as_listening() {
+        let history = history();
+        history.observe_at(&playing("a", 0, None), 1_000_000).unwrap();
+        // Jumped to two minutes in after five seconds.
+        history.observe_at(&playing("a", 120_000, None), 1_005_000).unwrap();
+        // Seeking back is still the same play.
+        assert!(!history.observe_at(&playing("a", 30_000, None), 1_010_000).unwrap());
+
+        let plays = all(&history);
+        assert_eq!(plays.len(), 1);
+        assert_eq!(plays[0].listened_ms, 6_000);
+    }
+
+    #[test]
+    fn track_change_ends_the_play() {
+        let history = history();
+        history.observe_at(&playing("a", 0, None), 1_000_000).unwrap();
+        history.observe_at(&playing("a", 30_000, None), 1_030_000).unwrap();
+        assert!(history.observe_at(&playing("b", 0, None), 1_031_000).unwrap());
+        history.observe_at(&playing("b", 195_000, None), 1_226_000).unwrap();
+        assert!(history.observe_at(&playing("c", 1_000, None), 1_232_000).unwrap());
+
+        let plays = all(&history);
+        let uris: Vec<&str> = plays.iter().map(|play| play.track_uri.as_str()).collect();
+        assert_eq!(uris, ["spotify:track:c", "spotify:track:b", "spotify:track:a"]);
+        // Stopped halfway through.
+        assert!(plays[2].skipped);
+        // Last seen within the slack of its end.
+        assert!(!plays[1].skipped);
+        assert!(!plays[0].skipped);
+    }
+
+    #[test]
+    fn replaying_from_the_end_is_a_new_play() {
+        let history = history();
+        history.observe_at(&playing("a", 0, None), 1_000_000).unwrap();
+        history.observe_at(&playing("a", 195_000, None), 1_195_000).unwrap();
+        assert!(history.observe_at(&playing("a", 2_000, None), 1_202_000).unwrap());
+        assert_eq!(all(&history).len(), 2);
+    }
+
+    #[test]
+    fn restart_resumes_the_play() {
+        let history = history();
+        history.observe_at(&playing("a", 10_000, None), 1_010_000).unwrap();
+        // What opening the database again after a restart looks like.
+        *history.current.lock().unwrap() = None;
+        assert!(history.observe_at(&playing("a", 60_000, None), 1_061_000).unwrap());
+        assert_eq!(all(&history).len(), 1);
+
+        // Much later, the same track is a separate play.
+        *history.current.lock().unwrap() = None;
+        history.observe_at(&playing("a", 10_000, None), 2_000_000).unwrap();
+        assert_eq!(all(&history).len(), 2);
+    }
+
+    #[test]
+    fn ads_do_not_end_the_play() {
+        let history = history();
+        history.observe_at(&playing("a", 0, None), 1_000_000).unwrap();
+        let mut ad = playing("a", 0, None);
+        ad.item = None;
+        assert!(!history.observe_at(&ad, 1_005_000).unwrap());
+        assert!(!history.observe_at(&playing("a", 10_000, None), 1_010_000).unwrap());
+        assert_eq!(all(&history).len(), 1);
+    }
+
+    #[test]
+    fn query_filters() {
+        let history = history();
+        history
+            .observe_at(&playing("a", 0, Some("spotify:playlist:p1")), 1_000_000)
+            .unwrap();
+        history.observe_at(&playing("b", 0, None), 2_000_000).unwrap();
+        history
+            .observe_at(&playing("c", 0, Some("spotify:playlist:p1")), 3_000_000)
+            .unwrap();
+
+        let uris = |query: HistoryQuery| -> Vec<String> {
+            history
+                .query(&query)
+                .unwrap()
+                .into_iter()
+                .map(|play| play.track_uri)
+                .collect()
+        };
+        assert_eq!(
+            uris(HistoryQuery {
+                from: Some(2_000_000),
+                ..Default::default()
+            }),
+            ["spotify:track:c", "spotify:track:b"]
+        );
+        assert_eq!(
+            uris(HistoryQuery {
+                to: Some(2_000_000),
+                ..Default::default()
+            }),
+            ["spotify:track:a"]
+        );
+        assert_eq!(
+            uris(HistoryQuery {
+                playlist: Some("p1".to_string()),
+                ..Default::default()
+            }),
+            ["spotify:track:c", "spotify:track:a"]
+        );
+        assert_eq!(
+            uris(HistoryQuery {
+                playlist: Some("spotify:playlist:p1".to_string()),
+                limit: Some(1),
+                offset: Some(1),
+                ..Default::default()
+            }),
+            ["spotify:track:a"]
+        );
+        assert_eq!(
+            uris(HistoryQuery {
+                artist: Some("tWO".to_string()),
+                ..Default::default()
+            })
+            .len(),
+            3
+        );
+        assert_eq!(
+            uris(HistoryQuery {
+                artist: Some("a1".to_string()),
+                ..Default::default()
+            })
+            .len(),
+            3
+        );
+        assert!(uris(HistoryQuery {
+            artist: Some("Someone Else".to_string()),
+            ..Default::default()
+        })
+        .is_empty());
+    }
+}
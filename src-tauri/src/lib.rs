@@ -1,16 +1,651 @@
+use serde::{Deserialize, Serialize};
+use tauri::{ Manager}; // Ensure Manager is imported
+use tauri::command;
+use dotenv::dotenv;
+use tauri::{Emitter};
+use std::env;
+use std::sync::Mutex;
+use std::sync::Arc;
+use hyper::{Body, Request, Response, Method, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
+use url::Url; 
+
+mod albums;
+mod api;
+mod artists;
+mod artwork;
+pub mod auth;
+mod backdrop;
+mod config;
+mod control;
+pub mod credentials;
+pub mod daemon;
+mod events;
+mod export;
+mod history;
+mod listenbrainz;
+mod listening;
+#[cfg(target_os = "linux")]
+mod mpris;
+mod midi;
+mod mqtt;
+mod osc;
+mod overlay;
+mod palette;
+pub mod player;
+mod playlists;
+mod scrobble;
+mod services;
+mod shortcuts;
+pub mod spotify;
+mod stats;
+mod tray;
+mod ws;
+
+// Song Data Structure
+#[derive(Serialize, Deserialize, Debug)]
+struct Song {
+    title: String,
+    // All artists joined for display, e.g. "Artist A, Artist B".
+    artist: String,
+    artists: Vec<SongArtist>,
+    album: String,
+    album_id: Option<String>,
+    release_date: Option<String>,
+    track_number: Option<u32>,
+    explicit: bool,
+    popularity: Option<u32>,
+    uri: String,
+    image: String,
+    // Image of the first artist.
+    artist_image: String,
+    progress_ms: u32,
+    duration_ms: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SongArtist {
+    id: Option<String>,
+    name: String,
+    image: Option<String>,
+}
+
+
+// Forward a message to the frontend's backend log.
+pub(crate) fn backend_log(app: &tauri::AppHandle, message: impl Into<String>) {
+    app.emit("backend-log", message.into())
+        .unwrap_or_else(|err| log::error!("Failed to emit log: {:?}", err));
+}
+
+// Hand a playback payload fetched for the frontend to the backend services
+// that follow along with playback.
+fn observe_playback(app: &tauri::AppHandle, state: &spotify::PlaybackState) {
+    control::observe(app, state);
+    #[cfg(target_os = "linux")]
+    mpris::observe(app);
+    tray::observe(app);
+    history::observe(app, state);
+    scrobble::observe(app, state);
+    events::observe(app, state);
+    // Outside services get the merged state, so the device, volume and
+    // shuffle state do not come and go with the payload kind.
+    let merged = control::now_playing(app);
+    overlay::observe(app, merged.as_ref());
+    mqtt::observe(app, merged.as_ref());
+    osc::observe(app, merged.as_ref());
+}
+
+// State for storing the access token globally
+struct AppState {
+    access_token: Mutex<Option<String>>,
+    auth_code: Mutex<Option<String>>,
+}
+
+#[command]
+fn store_access_token(state: tauri::State<Arc<AppState>>, token: String) -> Result<(), String> {
+    let mut at = state.access_token.lock().map_err(|e| e.to_string())?;
+    *at = Some(token);
+    Ok(())
+}
+
+#[command]
+fn get_auth_code(state: tauri::State<Arc<AppState>>) -> Option<String> {
+    state.auth_code.lock().unwrap().clone()
+}
+
+// Error returned by `fetch_current_song` when nothing is playing.
+const NOTHING_PLAYING: &str = "No song is currently playing.";
+// Error returned by the playback state commands when no device is active.
+const NO_ACTIVE_DEVICE: &str = "No active device.";
+
+#[command]
+async fn fetch_current_song(app: tauri::AppHandle, access: String) -> Result<Song, String> {
+    let playing: Option<spotify::PlaybackState> = spotify::get_optional(&access, "/me/player/currently-playing")
+        .await
+        .inspect_err(|e| backend_log(&app, e.clone()))?;
+
+    match playing {
+        None => {
+            overlay::observe(&app, None);
+            mqtt::observe(&app, None);
+            osc::observe(&app, None);
+            let message = NOTHING_PLAYING.to_string();
+            app.emit("backend-log", message.clone()).unwrap();
+            Err(message)
+        }
+        Some(playing) => {
+            observe_playback(&app, &playing);
+            let track = playing.item.ok_or("No track is currently playing.")?;
+
+            let album_image = track
+                .album
+                .images
+                .first()
+                .map(|img| img.url.clone())
+                .unwrap_or("https://via.placeholder.com/300".to_string());
+            let progress_ms = playing.progress_ms.unwrap_or(0);
+            let duration_ms = track.duration_ms;
+
+            if track.artists.first().and_then(|a| a.id.as_ref()).is_none() {
+                return Err("No artist ID found.".to_string());
+            }
+
+            // Look up every artist at once. These are usually served from the
+            // artist cache, since the artists rarely change between polls.
+            let lookups = track.artists.iter().map(|a| async {
+                match &a.id {
+                    Some(id) => Some(artists::lookup(&app, &access, id).await),
+                    None => None,
+                }
+            });
+            let artist_data = futures::future::join_all(lookups).await;
+
+            let mut song_artists = Vec::new();
+            for (i, (artist, data)) in track.artists.iter().zip(artist_data).enumerate() {
+                let data = match data {
+                    Some(Ok(data)) => Some(data),
+                    // Only the first artist is essential; the others just go without an image.
+                    Some(Err(e)) if i == 0 => return Err(format!("Failed to fetch artist info: {}", e)),
+                    _ => None,
+                };
+                song_artists.push(SongArtist {
+                    id: artist.id.clone(),
+                    name: artist.name.clone(),
+                    image: data
+                        .and_then(|d| d.images.first().map(|img| img.url.clone()))
+                        .map(|url| artwork::protocol_url(&url)),
+                });
+            }
+
+            let artist_image = song_artists[0]
+                .image
+                .clone()
+                .unwrap_or(artwork::protocol_url("https://via.placeholder.com/300"));
+            let artist = track.artist_names();
+
+            app.emit("backend-log", "Successfully fetched current song.".to_string()).unwrap();
+            Ok(Song {
+                title: track.name,
+                artist,
+                artists: song_artists,
+                album: track.album.name,
+                album_id: track.album.id,
+                release_date: track.album.release_date,
+                track_number: track.track_number,
+                explicit: track.explicit,
+                popularity: track.popularity,
+                uri: track.uri,
+                image: artwork::protocol_url(&album_image),
+                artist_image,
+                progress_ms,
+                duration_ms,
+            })
+        }
+    }
+}
+
+
+// Save tokens on the backend too, so the CLI and the control API can act
+// as the signed-in user.
+fn save_tokens(app: &tauri::AppHandle, tokens: &auth::TokenResponse) {
+    if let Some(credentials) = app.try_state::<Arc<credentials::Credentials>>() {
+        if let Err(e) = auth::save(&credentials, tokens) {
+            log::error!("{}", e);
+        }
+    }
+}
+
+#[command]
+async fn exchange_spotify_token(app: tauri::AppHandle, code: String) -> Result<auth::TokenResponse, String> {
+    let tokens = auth::exchange_code(&code).await?;
+    save_tokens(&app, &tokens);
+    Ok(tokens)
+}
+
+#[command]
+async fn refresh_spotify_token(app: tauri::AppHandle, refresh_token: String) -> Result<auth::TokenResponse, String> {
+    let tokens = auth::refresh(&refresh_token).await?;
+    save_tokens(&app, &tokens);
+    Ok(tokens)
+}
+
+#[command]
+fn get_spotify_auth_url(app: tauri::AppHandle) -> String {
+    dotenv().ok();
+    app.emit("backend-log", "Generating Spotify Auth URL...").unwrap_or_else(|err| {
+        log::error!("Failed to emit log: {:?}", err);
+    });
+
+    let client_id = env::var("SPOTIFY_CLIENT_ID").expect("SPOTIFY_CLIENT_ID not set");
+    let redirect_uri = env::var("REDIRECT_URI").unwrap_or("http://127.0.0.1:4242/callback".to_string());
+    let scopes = "user-read-playback-state user-modify-playback-state streaming playlist-read-private playlist-read-collaborative playlist-modify-public playlist-modify-private user-read-recently-played user-top-read user-library-modify";
+
+    let auth_url = format!(
+        "https://accounts.spotify.com/authorize?client_id={}&response_type=code&redirect_uri={}&scope={}",
+        client_id, redirect_uri, scopes
+    );
+
+    app.emit("backend-log", format!("Generated Auth URL: {}", auth_url)).unwrap_or_else(|err| {
+        log::error!("Failed to emit log: {:?}", err);
+    });
+
+    auth_url
+}
+
+#[command]
+async fn play(app: tauri::AppHandle, access: String) -> Result<(), String> {
+    player::play(&access).await?;
+    app.emit("backend-log", "Playback started.".to_string())
+        .unwrap_or_else(|err| log::error!("Failed to emit log: {:?}", err));
+    Ok(())
+}
+
+
+#[command]
+async fn pause(app: tauri::AppHandle, access: String) -> Result<(), String> {
+    player::pause(&access).await?;
+    app.emit("backend-log", "Playback paused.".to_string())
+        .unwrap_or_else(|err| log::error!("Failed to emit log: {:?}", err));
+    Ok(())
+}
+
+
+#[command]
+async fn skip_next(app: tauri::AppHandle, access: String) -> Result<(), String> {
+    player::next(&access).await?;
+    app.emit("backend-log", "Successfully skipped to the next track.".to_string())
+        .unwrap_or_else(|err| log::error!("Failed to emit log: {:?}", err));
+    Ok(())
+}
+
+
+#[command]
+async fn skip_previous(app: tauri::AppHandle, access: String) -> Result<(), String> {
+    player::previous(&access).await?;
+    app.emit("backend-log", "Successfully skipped to the previous track.".to_string())
+        .unwrap_or_else(|err| log::error!("Failed to emit log: {:?}", err));
+    Ok(())
+}
+
+
+
+#[command]
+async fn toggle_shuffle(app: tauri::AppHandle, access: String) -> Result<bool, String> {
+    let current_shuffle = player::playback_state(&access)
+        .await?
+        .and_then(|state| state.shuffle_state)
+        .unwrap_or(false);
+
+    // Toggle shuffle state
+    let new_shuffle_state = !current_shuffle;
+    player::set_shuffle(&access, new_shuffle_state).await?;
+    app.emit(
+        "backend-log",
+        format!(
+            "Shuffle toggled. New state: {}",
+            if new_shuffle_state { "enabled" } else { "disabled" }
+        ),
+    )
+    .unwrap_or_else(|err| log::error!("Failed to emit log: {:?}", err));
+    Ok(new_shuffle_state)
+}
+
+
+
+#[command]
+async fn restart_song(app: tauri::AppHandle, access: String) -> Result<(), String> {
+    // Seek to the beginning of the current track (0 milliseconds)
+    player::seek(&access, 0).await?;
+    app.emit("backend-log", "Successfully restarted the current song.".to_string())
+        .unwrap_or_else(|err| log::error!("Failed to emit log: {:?}", err));
+    Ok(())
+}
+
+
+#[command]
+async fn fetch_playlists(app: tauri::AppHandle, access: String) -> Result<serde_json::Value, String> {
+    let playlists: serde_json::Value = spotify::get(&access, "/me/playlists").await?;
+    app.emit("backend-log", "Playlists fetched successfully.".to_string())
+        .unwrap_or_else(|err| log::error!("Failed to emit log: {:?}", err));
+    Ok(playlists)
+}
+
+
+
+#[command]
+async fn change_playlist(app: tauri::AppHandle, access: String, id: String) -> Result<(), String> {
+    player::play_context(&access, &format!("spotify:playlist:{}", id)).await?;
+    app.emit("backend-log", format!("Playlist successfully changed to ID: {}", id))
+        .unwrap_or_else(|err| log::error!("Failed to emit log: {:?}", err));
+    Ok(())
+}
+
+
+
+#[command]
+async fn set_volume(app: tauri::AppHandle, access: String, volume: u8) -> Result<(), String> {
+    player::set_volume(&access, volume).await?;
+    app.emit("backend-log", format!("Volume set to {}%.", volume))
+        .unwrap_or_else(|err| log::error!("Failed to emit log: {:?}", err));
+    Ok(())
+}
+
+
+
+#[command]
+async fn get_devices(app: tauri::AppHandle, access: String) -> Result<serde_json::Value, String> {
+    let devices: serde_json::Value = spotify::get(&access, "/me/player/devices").await?;
+    app.emit("backend-log", "Fetched available devices.".to_string())
+        .unwrap_or_else(|err| log::error!("Failed to emit log: {:?}", err));
+    Ok(devices)
+}
+
+
+#[command]
+async fn get_playback_state(app: tauri::AppHandle, access: String) -> Result<serde_json::Value, String> {
+    let playback_data: serde_json::Value = spotify::get_optional(&access, "/me/player")
+        .await?
+        .ok_or(NO_ACTIVE_DEVICE)?;
+
+    if let Ok(state) = serde_json::from_value::<spotify::PlaybackState>(playback_data.clone()) {
+        observe_playback(&app, &state);
+    }
+
+    app.emit("backend-log", "Successfully fetched playback state.".to_string())
+        .unwrap_or_else(|err| log::error!("Failed to emit log: {:?}", err));
+
+    Ok(playback_data)
+}
+
+
+#[command]
+async fn get_current_playback(state: tauri::State<'_, Arc<AppState>>) -> Result<serde_json::Value, String> {
+    let access_token = {
+        let guard = state.access_token.lock().map_err(|e| e.to_string())?;
+        guard.clone().ok_or("No access token stored on backend.")?
+    };
+
+    spotify::get_optional(&access_token, "/me/player")
+        .await?
+        .ok_or(NO_ACTIVE_DEVICE.to_string())
+}
+
+#[command]
+async fn toggle_fullscreen(app: tauri::AppHandle) -> Result<(), String> {
+    // Use the Manager trait to access the main window
+    if let Some(window) = app.get_webview_window("main") {
+        let is_fullscreen = window.is_fullscreen().unwrap_or(false);
+        window.set_fullscreen(!is_fullscreen).map_err(|e| e.to_string())?;
+        app.emit("redraw", {}).map_err(|e| e.to_string())?;
+        Ok(())
+    } else {
+        Err("Window 'main' not found.".to_string())
+    }
+}
+
+// Fetch the user's Spotify profile (for profile image)
+#[tauri::command]
+async fn get_user_profile(access: String) -> Result<serde_json::Value, String> {
+    spotify::get(&access, "/me").await
+}
+
+// Update the function to use playlistId (camelCase) parameter instead of snake_case
+#[tauri::command]
+async fn get_playlist_image(access: String, playlistId: String) -> Result<String, String> {
+    let playlist: serde_json::Value = spotify::get(&access, &format!("/playlists/{}", playlistId)).await?;
+
+    let image_url = playlist["images"]
+        .get(0)
+        .and_then(|img| img["url"].as_str())
+        .unwrap_or("https://placehold.co/600x600/222/fff?text=No+Image")
+        .to_string();
+
+    Ok(artwork::protocol_url(&image_url))
+}
+
+async fn callback_service(
+    req: Request<Body>,
+    app_state: Arc<AppState>,
+    app: tauri::AppHandle,
+) -> Result<Response<Body>, hyper::Error> {
+    if req.uri().path().starts_with("/api/") {
+        return Ok(api::handle(&app, req).await);
+    }
+
+    if req.method() == Method::GET && req.uri().path() == "/callback" {
+        if let Some(query) = req.uri().query() {
+            let url = Url::parse(&format!("http://127.0.0.1:4242/callback?{}", query))
+                .unwrap_or_else(|_| Url::parse("http://127.0.0.1:4242/callback").unwrap());
+            let code_param = url.query_pairs().find(|(k, _)| k == "code").map(|(_, v)| v.to_string());
+
+            if let Some(code) = code_param {
+                // Store the code in AppState for future use if needed
+                if let Ok(mut auth_code) = app_state.auth_code.lock() {
+                    *auth_code = Some(code.clone());
+                }
+
+                // Redirect to the frontend callback page with the code
+                let body = format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="UTF-8" />
+<title>Authentication Complete</title>
+<script>
+    window.location.href = 'tauri://localhost/callback?code={code}';
+</script>
+</head>
+<body>
+</body>
+</html>"#, code = code);
+                return Ok(Response::new(Body::from(body)));
+            }
+        }
+
+        // Return an error response if no code is provided
+        let body = "Missing 'code' parameter.";
+        return Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from(body))
+            .unwrap());
+    }
+
+    // If the path is not /callback, return a 404 Not Found
+    Ok(Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::from("Not Found"))
+        .unwrap())
+}
+
+
+async fn start_server(app_state: Arc<AppState>, app: tauri::AppHandle) {
+    let addr = ([127, 0, 0, 1], 4242).into();
+    // The daemon serves the same port; the app does not attach to it.
+    let server = match hyper::Server::try_bind(&addr) {
+        Ok(server) => server,
+        Err(e) => {
+            backend_log(&app, format!("Failed to listen on {} (is the daemon running?): {}", addr, e));
+            return;
+        }
+    };
+    let make_svc = make_service_fn(move |_conn| {
+        let state = app_state.clone();
+        let app = app.clone();
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req| {
+                let state_inner = state.clone();
+                let app_inner = app.clone();
+                async move { callback_service(req, state_inner, app_inner).await }
+            }))
+        }
+    });
+
+    if let Err(e) = server.serve(make_svc).await {
+        log::error!("server error: {}", e);
+    }
+}
+
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-  tauri::Builder::default()
-    .setup(|app| {
-      if cfg!(debug_assertions) {
-        app.handle().plugin(
-          tauri_plugin_log::Builder::default()
-            .level(log::LevelFilter::Info)
-            .build(),
-        )?;
-      }
-      Ok(())
-    })
-    .run(tauri::generate_context!())
-    .expect("error while running tauri application");
+    let app_state = Arc::new(AppState {
+        access_token: Mutex::new(None),
+        auth_code: Mutex::new(None), // NEW FIELD
+    });
+
+    tauri::Builder::default()
+        .manage(app_state.clone()) // pass the Arc-managed state to Tauri
+        .manage(events::PlaybackEvents::default())
+        .manage(palette::PaletteCache::default())
+        .manage(control::NowPlaying::default())
+        .register_asynchronous_uri_scheme_protocol(artwork::SCHEME, |ctx, request, responder| {
+            let app = ctx.app_handle().clone();
+            tauri::async_runtime::spawn(async move {
+                responder.respond(artwork::serve(&app, request).await);
+            });
+        })
+        .on_window_event(tray::on_window_event)
+        .setup(move |app| {
+            // Registered in release builds too: the services report their
+            // failures through `log`.
+            app.handle().plugin(
+                tauri_plugin_log::Builder::default()
+                    .level(log::LevelFilter::Info)
+                    .build(),
+            )?;
+
+            // Start the local HTTP server in the background
+            tauri::async_runtime::spawn(start_server(app_state, app.handle().clone()));
+
+            // Open the local listening history; playback keeps working without it.
+            let data_dir = app.path().app_data_dir()?;
+            std::fs::create_dir_all(&data_dir)?;
+            match history::History::open(&data_dir.join("history.sqlite3")) {
+                Ok(history) => {
+                    app.manage(history);
+                }
+                Err(e) => log::error!("Failed to open listening history: {}", e),
+            }
+
+            let config_path = app.path().app_config_dir()?.join(config::CONFIG_FILE);
+            let config = config::Config::load(&config_path).unwrap_or_else(|e| {
+                log::error!("{}", e);
+                config::Config::default()
+            });
+
+            match artwork::ArtworkCache::open(app.path().app_cache_dir()?.join("artwork"), &config.artwork) {
+                Ok(cache) => {
+                    app.manage(cache);
+                }
+                Err(e) => log::error!("Failed to open artwork cache: {}", e),
+            }
+
+            app.manage(artists::ArtistCache::new(
+                &config.artists,
+                app.path().app_cache_dir()?.join("artists.json"),
+            ));
+
+            let (services, commands) = services::Services::load(&config, &config_path, &data_dir, None);
+            app.manage(services.credentials);
+            app.manage(api::ApiEnabled(services.api_enabled));
+            if let Some(overlay) = services.overlay {
+                app.manage(overlay);
+            }
+            if let Some(mqtt) = services.mqtt {
+                app.manage(mqtt);
+            }
+            if let Some(osc) = services.osc {
+                app.manage(osc);
+            }
+            if let Some(midi) = services.midi {
+                app.manage(midi);
+            }
+            for (source, commands) in commands {
+                tauri::async_runtime::spawn(control::run_commands(app.handle().clone(), source, commands));
+            }
+            let retry = !services.scrobblers.0.is_empty();
+            app.manage(services.scrobblers);
+            if retry {
+                tauri::async_runtime::spawn(scrobble::retry_queue(app.handle().clone()));
+            }
+
+            tray::create(app, &config.tray)?;
+
+            app.manage(shortcuts::Shortcuts::default());
+            app.handle().plugin(
+                tauri_plugin_global_shortcut::Builder::new()
+                    .with_handler(shortcuts::handle)
+                    .build(),
+            )?;
+            shortcuts::watch(app.handle().clone(), config_path);
+
+            #[cfg(target_os = "linux")]
+            tauri::async_runtime::spawn(mpris::start(app.handle().clone()));
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            get_spotify_auth_url,
+            exchange_spotify_token,
+            refresh_spotify_token,
+            fetch_current_song,
+            store_access_token,
+            get_auth_code,      
+            play,
+            pause,
+            skip_next,
+            skip_previous,
+            toggle_shuffle,
+            restart_song,
+            change_playlist,
+            set_volume,
+            get_devices,
+            get_playback_state,
+            fetch_playlists,
+            get_current_playback,
+            toggle_fullscreen,
+            get_user_profile,
+            get_playlist_image,
+            playlists::create_playlist,
+            playlists::update_playlist_details,
+            playlists::reorder_playlist_items,
+            playlists::remove_playlist_items,
+            listening::get_recently_played,
+            listening::get_top_items,
+            history::query_history,
+            stats::get_listening_stats,
+            export::export_history,
+            export::import_history,
+            listenbrainz::set_listenbrainz_token,
+            midi::learn_midi,
+            shortcuts::get_shortcut_conflicts,
+            artwork::get_artwork_url,
+            palette::get_palette,
+            backdrop::get_backdrop_urls,
+            artists::get_artist,
+            artists::get_artist_overview,
+            albums::get_album,
+            albums::play_album_from_track,
+            api::get_api_token,
+        ])
+        .run(tauri::generate_context!())
+        .expect("error while running Tauri application");
 }
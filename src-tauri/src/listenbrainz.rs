@@ -0,0 +1,357 @@
+// ListenBrainz client for the JSON submission API
+// (https://listenbrainz.readthedocs.io/en/latest/users/api/core.html).
+//
+// The user token lives in the credential store rather than in the config
+// file and is read on every request, so it can be changed at runtime.
+
+use std::sync::Arc;
+
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tauri::command;
+
+use crate::credentials::Credentials;
+use crate::scrobble::{Api, Scrobble, Scrobblers, SubmitError};
+
+pub const TOKEN_KEY: &str = "listenbrainz_token";
+pub const MAX_LISTENS_PER_REQUEST: usize = 1000;
+
+fn default_api_url() -> String {
+    "https://api.listenbrainz.org".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ListenBrainzConfig {
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+}
+
+impl Default for ListenBrainzConfig {
+    fn default() -> Self {
+        ListenBrainzConfig {
+            api_url: default_api_url(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TokenValidation {
+    pub valid: bool,
+    pub user_name: Option<String>,
+}
+
+pub struct ListenBrainz {
+    api_url: String,
+    client: Client,
+    credentials: Arc<Credentials>,
+}
+
+fn track_metadata(scrobble: &Scrobble) -> serde_json::Value {
+    let mut additional_info = json!({
+        "duration_ms": scrobble.duration_ms,
+        "artist_names": scrobble.artists,
+        "music_service": "spotify.com",
+        "submission_client": "playback-controller",
+        "submission_client_version": env!("CARGO_PKG_VERSION"),
+    });
+    if let Some(id) = &scrobble.track_id {
+        let url = format!("https://open.spotify.com/track/{}", id);
+        additional_info["spotify_id"] = json!(url);
+        additional_info["origin_url"] = json!(url);
+    }
+
+    let mut metadata = json!({
+        "artist_name": scrobble.artists.join(", "),
+        "track_name": scrobble.track,
+        "additional_info": additional_info,
+    });
+    if !scrobble.album.is_empty() {
+        metadata["release_name"] = json!(scrobble.album);
+    }
+    metadata
+}
+
+impl ListenBrainz {
+    pub fn new(config: ListenBrainzConfig, credentials: Arc<Credentials>) -> Self {
+        ListenBrainz {
+            api_url: config.api_url.trim_end_matches('/').to_string(),
+            client: Client::new(),
+            credentials,
+        }
+    }
+
+    fn token(&self) -> Result<String, String> {
+        self.credentials
+            .get(TOKEN_KEY)
+            .ok_or("No ListenBrainz token set.".to_string())
+    }
+
+    // Without a token the listens stay queued until one is set.
+    async fn submit_listens(&self, listen_type: &str, payload: Vec<serde_json::Value>) -> Result<(), SubmitError> {
+        let token = self.token().map_err(SubmitError::Retry)?;
+        let resp = self
+            .client
+            .post(format!("{}/1/submit-listens", self.api_url))
+            .header("Authorization", format!("Token {}", token))
+            .json(&json!({ "listen_type": listen_type, "payload": payload }))
+            .send()
+            .await
+            .map_err(|e| SubmitError::Retry(format!("Failed to reach ListenBrainz: {:?}", e)))?;
+
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            let status = resp.status();
+            let body: serde_json::Value = resp.json().await.unwrap_or_default();
+            let message = body["error"].as_str().unwrap_or("Unknown error");
+            Err(SubmitError::from_status(
+                status,
+                format!("ListenBrainz error ({}): {}", status, message),
+            ))
+        }
+    }
+
+    pub async fn playing_now(&self, scrobble: &Scrobble) -> Result<(), String> {
+        let payload = vec![json!({ "track_metadata": track_metadata(scrobble) })];
+        self.submit_listens("playing_now", payload)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Submit finished listens, as a `single` listen or a batched `import`.
+    pub async fn submit(&self, batch: &[Scrobble]) -> Result<(), SubmitError> {
+        let listen_type = if batch.len() == 1 { "single" } else { "import" };
+        let payload = batch
+            .iter()
+            .map(|scrobble| {
+                json!({
+                    "listened_at": scrobble.timestamp,
+                    "track_metadata": track_metadata(scrobble),
+                })
+            })
+            .collect();
+        self.submit_listens(listen_type, payload).await
+    }
+
+    pub async fn validate_token(&self, token: &str) -> Result<TokenValidation, String> {
+        let resp = self
+            .client
+            .get(format!("{}/1/validate-token", self.api_url))
+            .header("Authorization", format!("Token {}", token))
+            .send()
+            .await
+            .map_err(|e| format!("Failed to reach ListenBrainz: {:?}", e))?;
+
+        resp.json()
+            .await
+            .map_err(|e| format!("Could not parse ListenBrainz response: {:?}", e))
+    }
+}
+
+// Checks the token against ListenBrainz and stores it if it is valid. Returns
+// the ListenBrainz user name. An empty token removes the stored one.
+#[command]
+pub async fn set_listenbrainz_token(
+    credentials: tauri::State<'_, Arc<Credentials>>,
+    scrobblers: tauri::State<'_, Arc<Scrobblers>>,
+    token: String,
+) -> Result<Option<String>, String> {
+    let token = token.trim().to_string();
+    if token.is_empty() {
+        credentials.set(TOKEN_KEY, None)?;
+        return Ok(None);
+    }
+
+    let configured = scrobblers.0.iter().find_map(|s| match &s.api {
+        Api::ListenBrainz(api) => Some(api),
+        _ => None,
+    });
+    let validation = match configured {
+        Some(api) => api.validate_token(&token).await?,
+        None => {
+            ListenBrainz::new(ListenBrainzConfig::default(), credentials.inner().clone())
+                .validate_token(&token)
+                .await?
+        }
+    };
+    if !validation.valid {
+        return Err("ListenBrainz rejected the token.".to_string());
+    }
+
+    credentials.set(TOKEN_KEY, Some(token))?;
+    Ok(validation.user_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use hyper::StatusCode;
+
+    use super::*;
+    use crate::scrobble::Scrobbler;
+    use crate::spotify::fixtures::{mock_server, temp_path, Received};
+
+    // Stands in for the ListenBrainz API, answering every request with
+    // `status`.
+    struct Mock {
+        url: String,
+        received: Arc<Mutex<Vec<Received>>>,
+        status: Arc<Mutex<StatusCode>>,
+    }
+
+    async fn mock() -> Mock {
+        let status = Arc::new(Mutex::new(StatusCode::OK));
+        let answer = status.clone();
+        let server = mock_server(move |_| {
+            let status = *answer.lock().unwrap();
+            if status.is_success() {
+                (status, json!({ "status": "ok" }))
+            } else {
+                (status, json!({ "code": status.as_u16(), "error": "Nope." }))
+            }
+        })
+        .await;
+        Mock {
+            url: server.url,
+            received: server.received,
+            status,
+        }
+    }
+
+    fn client(mock: &Mock, name: &str) -> ListenBrainz {
+        let credentials = Arc::new(Credentials::open(temp_path(&format!(
+            "listenbrainz-{}-credentials.json",
+            name
+        ))));
+        credentials.set(TOKEN_KEY, Some("secret".to_string())).unwrap();
+        ListenBrainz::new(
+            ListenBrainzConfig {
+                api_url: mock.url.clone(),
+            },
+            credentials,
+        )
+    }
+
+    fn scrobble(track: &str, timestamp: i64) -> Scrobble {
+        Scrobble {
+            artist: "One".to_string(),
+            artists: vec!["One".to_string(), "Two".to_string()],
+            track: track.to_string(),
+            album: "Album".to_string(),
+            track_id: Some("abc".to_string()),
+            duration_ms: 200_000,
+            timestamp,
+        }
+    }
+
+    #[tokio::test]
+    async fn playing_now_payload() {
+        let mock = mock().await;
+        client(&mock, "playing-now")
+            .playing_now(&scrobble("Song", 1_700_000_000))
+            .await
+            .unwrap();
+
+        let received = mock.received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].path, "/1/submit-listens");
+        assert_eq!(received[0].authorization.as_deref(), Some("Token secret"));
+        let body = &received[0].json();
+        assert_eq!(body["listen_type"], "playing_now");
+        let listen = &body["payload"][0];
+        assert!(listen.get("listened_at").is_none());
+        let metadata = &listen["track_metadata"];
+        assert_eq!(metadata["artist_name"], "One, Two");
+        assert_eq!(metadata["track_name"], "Song");
+        assert_eq!(metadata["release_name"], "Album");
+        assert_eq!(metadata["additional_info"]["duration_ms"], 200_000);
+        assert_eq!(
+            metadata["additional_info"]["spotify_id"],
+            "https://open.spotify.com/track/abc"
+        );
+    }
+
+    #[tokio::test]
+    async fn single_and_import_payloads() {
+        let mock = mock().await;
+        let api = client(&mock, "submit");
+        api.submit(&[scrobble("First", 1_700_000_000)]).await.unwrap();
+        api.submit(&[scrobble("Second", 1_700_000_300), scrobble("Third", 1_700_000_600)])
+            .await
+            .unwrap();
+
+        let received = mock.received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].json()["listen_type"], "single");
+        assert_eq!(received[0].json()["payload"][0]["listened_at"], 1_700_000_000);
+        assert_eq!(received[1].json()["listen_type"], "import");
+        let import = received[1].json();
+        let payload = import["payload"].as_array().unwrap();
+        let tracks: Vec<&str> = payload
+            .iter()
+            .map(|listen| listen["track_metadata"]["track_name"].as_str().unwrap())
+            .collect();
+        assert_eq!(tracks, ["Second", "Third"]);
+        assert_eq!(payload[1]["listened_at"], 1_700_000_600);
+        assert!(received
+            .iter()
+            .all(|request| request.authorization.as_deref() == Some("Token secret")));
+    }
+
+    #[tokio::test]
+    async fn only_transient_failures_stay_queued() {
+        let mock = mock().await;
+        let queue_path = temp_path("listenbrainz-queue.json");
+        let rejected_path = queue_path.with_extension("rejected.json");
+        let scrobbler = Scrobbler::new(
+            "ListenBrainz",
+            Api::ListenBrainz(client(&mock, "queue")),
+            queue_path.clone(),
+        );
+
+        // A bad request is set aside rather than retried forever.
+        *mock.status.lock().unwrap() = StatusCode::BAD_REQUEST;
+        scrobbler.enqueue(scrobble("Bad", 1_700_000_000));
+        assert_eq!(scrobbler.flush().await.unwrap(), 0);
+        assert_eq!(scrobbler.pending(), 0);
+        let rejected: Vec<Scrobble> = serde_json::from_str(&std::fs::read_to_string(&rejected_path).unwrap()).unwrap();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].track, "Bad");
+
+        *mock.status.lock().unwrap() = StatusCode::SERVICE_UNAVAILABLE;
+        scrobbler.enqueue(scrobble("Song", 1_700_000_300));
+        let error = scrobbler.flush().await.unwrap_err();
+        assert!(error.contains("503"), "{}", error);
+        assert!(error.contains("Nope."), "{}", error);
+        assert_eq!(scrobbler.pending(), 1);
+        let saved: Vec<Scrobble> = serde_json::from_str(&std::fs::read_to_string(&queue_path).unwrap()).unwrap();
+        assert_eq!(saved.len(), 1);
+
+        *mock.status.lock().unwrap() = StatusCode::OK;
+        assert_eq!(scrobbler.flush().await.unwrap(), 1);
+        assert_eq!(scrobbler.pending(), 0);
+        std::fs::remove_file(&queue_path).unwrap();
+        std::fs::remove_file(&rejected_path).unwrap();
+
+        // The rejected, the failed and the accepted attempt.
+        let received = mock.received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[2].json()["payload"][0]["track_metadata"]["track_name"], "Song");
+    }
+
+    #[tokio::test]
+    async fn missing_token_sends_nothing() {
+        let mock = mock().await;
+        let credentials = Arc::new(Credentials::open(temp_path("listenbrainz-missing-credentials.json")));
+        let api = ListenBrainz::new(
+            ListenBrainzConfig {
+                api_url: mock.url.clone(),
+            },
+            credentials,
+        );
+        assert!(api.submit(&[scrobble("Song", 1_700_000_000)]).await.is_err());
+        assert!(mock.received.lock().unwrap().is_empty());
+    }
+}
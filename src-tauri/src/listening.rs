@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+use tauri::command;
+
+use crate::backend_log;
+use crate::spotify::{self, Artist, Context, CursorPage, Page, Track};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayHistory {
+    pub track: Track,
+    pub played_at: String,
+    pub context: Option<Context>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum TopItemType {
+    Tracks,
+    Artists,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub enum TimeRange {
+    // Roughly the last 4 weeks.
+    #[serde(rename = "short_term")]
+    Short,
+    // Roughly the last 6 months.
+    #[default]
+    #[serde(rename = "medium_term")]
+    Medium,
+    // About a year.
+    #[serde(rename = "long_term")]
+    Long,
+}
+
+impl TimeRange {
+    fn as_str(self) -> &'static str {
+        match self {
+            TimeRange::Short => "short_term",
+            TimeRange::Medium => "medium_term",
+            TimeRange::Long => "long_term",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TopItems {
+    Tracks(Page<Track>),
+    Artists(Page<Artist>),
+}
+
+// Spotify caps both endpoints at 50 items per page.
+fn page_limit(limit: Option<u32>) -> u32 {
+    limit.unwrap_or(20).clamp(1, 50)
+}
+
+// `before` and `after` are the cursors returned in the previous page (Unix
+// timestamps in milliseconds). Only one of them may be set.
+#[command]
+pub async fn get_recently_played(
+    app: tauri::AppHandle,
+    access: String,
+    limit: Option<u32>,
+    before: Option<String>,
+    after: Option<String>,
+) -> Result<CursorPage<PlayHistory>, String> {
+    let mut path = format!("/me/player/recently-played?limit={}", page_limit(limit));
+    match (before, after) {
+        (Some(_), Some(_)) => {
+            return Err("Only one of 'before' and 'after' can be set.".to_string());
+        }
+        (Some(before), None) => path.push_str(&format!("&before={}", before)),
+        (None, Some(after)) => path.push_str(&format!("&after={}", after)),
+        (None, None) => {}
+    }
+
+    let page: CursorPage<PlayHistory> = spotify::get(&access, &path).await?;
+    backend_log(&app, format!("Fetched {} recently played track(s).", page.items.len()));
+    Ok(page)
+}
+
+#[command]
+pub async fn get_top_items(
+    app: tauri::AppHandle,
+    access: String,
+    kind: TopItemType,
+    time_range: Option<TimeRange>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+) -> Result<TopItems, String> {
+    let query = format!(
+        "time_range={}&limit={}&offset={}",
+        time_range.unwrap_or_default().as_str(),
+        page_limit(limit),
+        offset.unwrap_or(0)
+    );
+
+    let items = match kind {
+        TopItemType::Tracks => {
+            TopItems::Tracks(spotify::get(&access, &format!("/me/top/tracks?{}", query)).await?)
+        }
+        TopItemType::Artists => {
+            TopItems::Artists(spotify::get(&access, &format!("/me/top/artists?{}", query)).await?)
+        }
+    };
+
+    backend_log(&app, "Fetched top items.");
+    Ok(items)
+}
@@ -0,0 +1,576 @@
+// MIDI input, so a USB controller's pads, buttons and knobs can act as a
+// hardware remote.
+//
+// Bindings live in a mapping file (`midi-mapping.json` next to the config by
+// default), as a list of `{ "note": 36, "action": "play_pause" }` or
+// `{ "cc": 7, "channel": 1, "action": "volume" }` entries. Without a channel
+// a binding matches every channel. Notes trigger on note on, and CCs bound to
+// buttons when they go from below 64 to 64 or above. A CC bound to `volume`
+// sets the volume from its position, at most once per `volume_interval_ms`
+// so that turning a knob does not flood Spotify with requests; the final
+// position is always sent.
+//
+// In learn mode the next note or CC (only a CC for `volume`) is bound to the
+// chosen action, replacing any binding for the same control, and the mapping
+// file is saved.
+//
+// The mapping engine only sees parsed messages and the time they arrived, so
+// it does not need a device.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use midir::{Ignore, MidiInput};
+use serde::{Deserialize, Serialize};
+use tauri::{command, Manager};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::control::Action;
+
+pub const MAPPING_FILE: &str = "midi-mapping.json";
+const LEARN_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn default_volume_interval_ms() -> u64 {
+    150
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MidiConfig {
+    // Part of the name of the input port to use; the first port if unset.
+    #[serde(default)]
+    pub port: Option<String>,
+    #[serde(default)]
+    pub mapping: Option<PathBuf>,
+    #[serde(default = "default_volume_interval_ms")]
+    pub volume_interval_ms: u64,
+}
+
+/// What a binding does.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Control {
+    PlayPause,
+    Next,
+    Previous,
+    Shuffle,
+    Volume,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Note(u8),
+    Cc(u8),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Binding {
+    // 1 to 16.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<u8>,
+    #[serde(flatten)]
+    pub source: Source,
+    pub action: Control,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Mapping {
+    #[serde(default)]
+    pub bindings: Vec<Binding>,
+}
+
+impl Mapping {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|e| format!("Invalid MIDI mapping {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Mapping::default()),
+            Err(e) => Err(format!("Failed to read MIDI mapping {}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, text).map_err(|e| format!("Failed to save MIDI mapping {}: {}", path.display(), e))
+    }
+}
+
+/// The channel messages a mapping can use. Channels are 1 to 16.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Message {
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    NoteOff { channel: u8, note: u8 },
+    ControlChange { channel: u8, controller: u8, value: u8 },
+}
+
+impl Message {
+    /// Parse a raw MIDI message, or `None` for anything a mapping cannot use.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        let [status, data1, data2, ..] = *bytes else {
+            return None;
+        };
+        let channel = (status & 0x0f) + 1;
+        match status & 0xf0 {
+            // Note on with velocity 0 is how many devices send note off.
+            0x90 if data2 > 0 => Some(Message::NoteOn { channel, note: data1, velocity: data2 }),
+            0x80 | 0x90 => Some(Message::NoteOff { channel, note: data1 }),
+            0xb0 => Some(Message::ControlChange { channel, controller: data1, value: data2 }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+    Action(Action),
+    Learned(Binding),
+}
+
+// Sends the volume at most once per interval, keeping the latest value
+// until it can be sent.
+struct Smoother {
+    interval: Duration,
+    sent: Option<(Instant, u8)>,
+    pending: Option<u8>,
+}
+
+impl Smoother {
+    fn set(&mut self, volume: u8, now: Instant) -> Option<u8> {
+        self.pending = Some(volume);
+        self.flush(now)
+    }
+
+    fn flush(&mut self, now: Instant) -> Option<u8> {
+        let volume = self.pending?;
+        match self.sent {
+            Some((_, sent)) if sent == volume => {
+                self.pending = None;
+                None
+            }
+            Some((at, _)) if now.duration_since(at) < self.interval => None,
+            _ => {
+                self.pending = None;
+                self.sent = Some((now, volume));
+                Some(volume)
+            }
+        }
+    }
+}
+
+pub struct Engine {
+    mapping: Mapping,
+    learning: Option<Control>,
+    // Last value of each (channel, controller), to see buttons go down.
+    controllers: HashMap<(u8, u8), u8>,
+    volume: Smoother,
+}
+
+impl Engine {
+    pub fn new(mapping: Mapping, volume_interval: Duration) -> Self {
+        Engine {
+            mapping,
+            learning: None,
+            controllers: HashMap::new(),
+            volume: Smoother {
+                interval: volume_interval,
+                sent: None,
+                pending: None,
+            },
+        }
+    }
+
+    pub fn mapping(&self) -> &Mapping {
+        &self.mapping
+    }
+
+    /// Bind the next suitable message to `control`, or stop learning.
+    pub fn learn(&mut self, control: Option<Control>) {
+        self.learning = control;
+    }
+
+    pub fn handle(&mut self, message: Message, now: Instant) -> Option<Output> {
+        let (channel, source, value) = match message {
+            Message::NoteOn { channel, note, .. } => (channel, Source::Note(note), None),
+            Message::NoteOff { .. } => return None,
+            Message::ControlChange { channel, controller, value } => {
+                let previous = self.controllers.insert((channel, controller), value);
+                (channel, Source::Cc(controller), Some((previous, value)))
+            }
+        };
+
+        if let Some(control) = self.learning {
+            if control == Control::Volume && value.is_none() {
+                return None;
+            }
+            self.learning = None;
+            let binding = Binding {
+                channel: Some(channel),
+                source,
+                action: control,
+            };
+            self.mapping
+                .bindings
+                .retain(|existing| !(existing.source == source && existing.channel.map_or(true, |c| c == channel)));
+            self.mapping.bindings.push(binding.clone());
+            return Some(Output::Learned(binding));
+        }
+
+        let binding = self
+            .mapping
+            .bindings
+            .iter()
+            .find(|binding| binding.source == source && binding.channel.map_or(true, |c| c == channel))?;
+        let action = match (binding.action, value) {
+            (Control::Volume, Some((_, value))) => {
+                let volume = (u32::from(value) * 100 + 63) / 127;
+                return self.volume.set(volume as u8, now).map(|volume| Output::Action(Action::SetVolume(volume)));
+            }
+            (Control::Volume, None) => return None,
+            // Buttons sending CCs fire once when pressed.
+            (_, Some((previous, value))) if value < 64 || previous.is_some_and(|previous| previous >= 64) => {
+                return None
+            }
+            (Control::PlayPause, _) => Action::PlayPause,
+            (Control::Next, _) => Action::Next,
+            (Control::Previous, _) => Action::Previous,
+            (Control::Shuffle, _) => Action::ToggleShuffle,
+        };
+        Some(Output::Action(action))
+    }
+
+    /// A volume change held back by smoothing, once it is due.
+    pub fn flush(&mut self, now: Instant) -> Option<Action> {
+        self.volume.flush(now).map(Action::SetVolume)
+    }
+}
+
+pub struct Midi {
+    engine: Arc<Mutex<Engine>>,
+    // Where learned bindings go.
+    learned: Arc<Mutex<Option<oneshot::Sender<Binding>>>>,
+    // Dropping this closes the port.
+    stop: Mutex<Option<std::sync::mpsc::Sender<()>>>,
+    holder: Mutex<Option<std::thread::JoinHandle<()>>>,
+}
+
+impl Midi {
+    /// Open the configured input port and follow `mapping_path` (unless the
+    /// config names another file). Actions arrive on the returned receiver.
+    pub fn start(
+        config: MidiConfig,
+        mapping_path: PathBuf,
+    ) -> Result<(Arc<Self>, mpsc::UnboundedReceiver<Action>), String> {
+        let mapping_path = config.mapping.clone().unwrap_or(mapping_path);
+        let mapping = Mapping::load(&mapping_path)?;
+        let interval = Duration::from_millis(config.volume_interval_ms);
+        let engine = Arc::new(Mutex::new(Engine::new(mapping, interval)));
+
+        let mut input = MidiInput::new("Playback Controller").map_err(|e| format!("Failed to open MIDI: {}", e))?;
+        input.ignore(Ignore::All);
+        let ports = input.ports();
+        let port = ports
+            .iter()
+            .find(|port| {
+                let name = input.port_name(port).unwrap_or_default();
+                config.port.as_ref().map_or(true, |wanted| name.contains(wanted.as_str()))
+            })
+            .ok_or(match &config.port {
+                Some(wanted) => format!("No MIDI input port matching '{}'.", wanted),
+                None => "No MIDI input ports found.".to_string(),
+            })?;
+        let name = input.port_name(port).unwrap_or_default();
+
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let learned: Arc<Mutex<Option<oneshot::Sender<Binding>>>> = Arc::new(Mutex::new(None));
+        let connection = {
+            let engine = engine.clone();
+            let learned = learned.clone();
+            let commands = commands.clone();
+            input
+                .connect(
+                    port,
+                    "playback-input",
+                    move |_, bytes, _| {
+                        let Some(message) = Message::parse(bytes) else {
+                            return;
+                        };
+                        let mut engine = engine.lock().unwrap();
+                        match engine.handle(message, Instant::now()) {
+                            Some(Output::Action(action)) => {
+                                let _ = commands.send(action);
+                            }
+                            Some(Output::Learned(binding)) => {
+                                if let Err(e) = engine.mapping().save(&mapping_path) {
+                                    log::error!("Failed to save MIDI mapping: {}", e);
+                                }
+                                if let Some(waiting) = learned.lock().unwrap().take() {
+                                    let _ = waiting.send(binding);
+                                }
+                            }
+                            None => {}
+                        }
+                    },
+                    (),
+                )
+                .map_err(|e| format!("Failed to connect to MIDI port {}: {}", name, e))?
+        };
+
+        // Holds the connection open and sends smoothed volume changes that
+        // were held back, until the sender is dropped.
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        let holder = {
+            let engine = engine.clone();
+            std::thread::spawn(move || {
+                let _connection = connection;
+                while let Err(std::sync::mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    if let Some(action) = engine.lock().unwrap().flush(Instant::now()) {
+                        if commands.send(action).is_err() {
+                            return;
+                        }
+                    }
+                }
+            })
+        };
+        log::info!("Listening to MIDI port {}.", name);
+
+        let midi = Midi {
+            engine,
+            learned,
+            stop: Mutex::new(Some(stop)),
+            holder: Mutex::new(Some(holder)),
+        };
+        Ok((Arc::new(midi), receiver))
+    }
+
+    /// Close the port now rather than when the last reference goes, so it
+    /// can be opened again straight away.
+    pub fn stop(&self) {
+        self.stop.lock().unwrap().take();
+        if let Some(holder) = self.holder.lock().unwrap().take() {
+            let _ = holder.join();
+        }
+    }
+
+    /// Bind the next message from the controller to `control`.
+    pub async fn learn(&self, control: Control) -> Result<Binding, String> {
+        let (sender, receiver) = oneshot::channel();
+        *self.learned.lock().unwrap() = Some(sender);
+        self.engine.lock().unwrap().learn(Some(control));
+        match tokio::time::timeout(LEARN_TIMEOUT, receiver).await {
+            Ok(Ok(binding)) => Ok(binding),
+            // Replaced by a newer request.
+            Ok(Err(_)) => Err("MIDI learning was cancelled.".to_string()),
+            Err(_) => {
+                self.engine.lock().unwrap().learn(None);
+                self.learned.lock().unwrap().take();
+                Err("No MIDI message received.".to_string())
+            }
+        }
+    }
+}
+
+#[command]
+pub async fn learn_midi(app: tauri::AppHandle, action: Control) -> Result<Binding, String> {
+    let midi = app.try_state::<Arc<Midi>>().ok_or("MIDI input is not configured.")?;
+    midi.learn(action).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(150);
+
+    fn binding(channel: Option<u8>, source: Source, action: Control) -> Binding {
+        Binding {
+            channel,
+            source,
+            action,
+        }
+    }
+
+    fn engine(bindings: Vec<Binding>) -> Engine {
+        Engine::new(Mapping { bindings }, INTERVAL)
+    }
+
+    fn cc(channel: u8, controller: u8, value: u8) -> Message {
+        Message::ControlChange {
+            channel,
+            controller,
+            value,
+        }
+    }
+
+    fn action(output: Option<Output>) -> Option<Action> {
+        match output {
+            Some(Output::Action(action)) => Some(action),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn note_on_and_note_off() {
+        assert_eq!(
+            Message::parse(&[0x90, 36, 100]),
+            Some(Message::NoteOn {
+                channel: 1,
+                note: 36,
+                velocity: 100
+            })
+        );
+        // Velocity 0 is a note off.
+        assert_eq!(
+            Message::parse(&[0x9f, 36, 0]),
+            Some(Message::NoteOff { channel: 16, note: 36 })
+        );
+        assert_eq!(
+            Message::parse(&[0x80, 36, 64]),
+            Some(Message::NoteOff { channel: 1, note: 36 })
+        );
+        assert_eq!(Message::parse(&[0xb2, 7, 127]), Some(cc(3, 7, 127)));
+        assert_eq!(Message::parse(&[0xe0, 0, 64]), None);
+        assert_eq!(Message::parse(&[0x90, 36]), None);
+
+        let mut engine = engine(vec![binding(None, Source::Note(36), Control::PlayPause)]);
+        let now = Instant::now();
+        let on = Message::parse(&[0x90, 36, 100]).unwrap();
+        let off = Message::parse(&[0x90, 36, 0]).unwrap();
+        assert_eq!(action(engine.handle(on, now)), Some(Action::PlayPause));
+        assert_eq!(engine.handle(off, now), None);
+    }
+
+    #[test]
+    fn cc_buttons_fire_when_pressed() {
+        let mut engine = engine(vec![binding(Some(1), Source::Cc(20), Control::Next)]);
+        let now = Instant::now();
+        // The first message counts as a press if it is high.
+        assert_eq!(action(engine.handle(cc(1, 20, 127), now)), Some(Action::Next));
+        assert_eq!(engine.handle(cc(1, 20, 100), now), None);
+        assert_eq!(engine.handle(cc(1, 20, 63), now), None);
+        assert_eq!(action(engine.handle(cc(1, 20, 64), now)), Some(Action::Next));
+        assert_eq!(engine.handle(cc(1, 20, 0), now), None);
+        // Other channels have their own state and no binding.
+        assert_eq!(engine.handle(cc(2, 20, 127), now), None);
+        assert_eq!(action(engine.handle(cc(1, 20, 127), now)), Some(Action::Next));
+    }
+
+    #[test]
+    fn bindings_without_a_channel_match_every_channel() {
+        let mut engine = engine(vec![
+            binding(Some(10), Source::Note(36), Control::Previous),
+            binding(None, Source::Note(36), Control::Shuffle),
+        ]);
+        let now = Instant::now();
+        let note = |channel| Message::NoteOn {
+            channel,
+            note: 36,
+            velocity: 1,
+        };
+        assert_eq!(action(engine.handle(note(10), now)), Some(Action::Previous));
+        assert_eq!(action(engine.handle(note(1), now)), Some(Action::ToggleShuffle));
+        assert_eq!(action(engine.handle(note(16), now)), Some(Action::ToggleShuffle));
+    }
+
+    #[test]
+    fn learning_replaces_bindings() {
+        let mut engine = engine(vec![
+            binding(Some(1), Source::Note(36), Control::Next),
+            binding(None, Source::Note(37), Control::Previous),
+            binding(Some(2), Source::Note(36), Control::Shuffle),
+        ]);
+        let now = Instant::now();
+        engine.learn(Some(Control::PlayPause));
+        let learned = engine.handle(
+            Message::NoteOn {
+                channel: 1,
+                note: 36,
+                velocity: 90,
+            },
+            now,
+        );
+        assert_eq!(
+            learned,
+            Some(Output::Learned(binding(Some(1), Source::Note(36), Control::PlayPause)))
+        );
+        assert_eq!(
+            engine.mapping().bindings,
+            vec![
+                binding(None, Source::Note(37), Control::Previous),
+                binding(Some(2), Source::Note(36), Control::Shuffle),
+                binding(Some(1), Source::Note(36), Control::PlayPause),
+            ]
+        );
+        // Learning is over, so the note now plays.
+        let note = Message::NoteOn {
+            channel: 1,
+            note: 36,
+            velocity: 90,
+        };
+        assert_eq!(action(engine.handle(note, now)), Some(Action::PlayPause));
+
+        // A binding without a channel covers the learned channel too.
+        engine.learn(Some(Control::Next));
+        engine.handle(
+            Message::NoteOn {
+                channel: 5,
+                note: 37,
+                velocity: 90,
+            },
+            now,
+        );
+        assert!(!engine
+            .mapping()
+            .bindings
+            .iter()
+            .any(|binding| binding.action == Control::Previous));
+    }
+
+    #[test]
+    fn volume_is_only_learned_from_a_cc() {
+        let mut engine = engine(Vec::new());
+        let now = Instant::now();
+        engine.learn(Some(Control::Volume));
+        assert_eq!(
+            engine.handle(
+                Message::NoteOn {
+                    channel: 1,
+                    note: 36,
+                    velocity: 90
+                },
+                now
+            ),
+            None
+        );
+        assert!(engine.mapping().bindings.is_empty());
+        assert_eq!(
+            engine.handle(cc(1, 7, 0), now),
+            Some(Output::Learned(binding(Some(1), Source::Cc(7), Control::Volume)))
+        );
+    }
+
+    #[test]
+    fn volume_is_throttled_and_flushed() {
+        let mut engine = engine(vec![binding(None, Source::Cc(7), Control::Volume)]);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(
+            action(engine.handle(cc(1, 7, 127), at(0))),
+            Some(Action::SetVolume(100))
+        );
+        assert_eq!(engine.handle(cc(1, 7, 100), at(50)), None);
+        assert_eq!(engine.handle(cc(1, 7, 64), at(100)), None);
+        assert_eq!(engine.flush(at(140)), None);
+        // The last position is sent once the interval is up, and only once.
+        assert_eq!(engine.flush(at(150)), Some(Action::SetVolume(50)));
+        assert_eq!(engine.flush(at(400)), None);
+
+        assert_eq!(action(engine.handle(cc(1, 7, 0), at(400))), Some(Action::SetVolume(0)));
+        // Going back to the value that was sent needs nothing.
+        assert_eq!(engine.handle(cc(1, 7, 1), at(420)), None);
+        assert_eq!(engine.handle(cc(1, 7, 0), at(440)), None);
+        assert_eq!(engine.flush(at(600)), None);
+    }
+}
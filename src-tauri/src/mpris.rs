@@ -0,0 +1,671 @@
+// MPRIS media player service on the D-Bus session bus, so the media keys,
+// lock screen widgets and `playerctl` on GNOME and KDE can see and control
+// playback.
+//
+// Method calls and property writes are mapped onto `control::Action`s. The
+// properties are read from the last playback payload the frontend polled,
+// and PropertiesChanged is emitted whenever a poll changes one of them.
+// Seeks made through MPRIS are announced with the Seeked signal.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use futures::future::BoxFuture;
+use tauri::Manager;
+use zbus::fdo;
+use zbus::zvariant::{ObjectPath, OwnedValue, Value};
+use zbus::{interface, Connection, SignalContext};
+
+use crate::control::{self, Action};
+use crate::spotify::{PlaybackState, Track};
+
+// Should be unique per player; the suffix is this app's product name.
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.playback_controller";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+const IDENTITY: &str = "Playback Controller";
+
+// What the interfaces need from the app: the Tauri app itself, or a stand-in
+// in tests.
+trait Host: Send + Sync {
+    fn now_playing(&self) -> Option<PlaybackState>;
+    fn dispatch(&self, action: Action) -> BoxFuture<'_, Result<(), String>>;
+    fn raise(&self);
+    fn quit(&self);
+    // Announce what the last action changed.
+    fn changed(&self);
+}
+
+impl Host for tauri::AppHandle {
+    fn now_playing(&self) -> Option<PlaybackState> {
+        control::now_playing(self)
+    }
+
+    fn dispatch(&self, action: Action) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(control::dispatch(self, action))
+    }
+
+    fn raise(&self) {
+        if let Some(window) = self.get_webview_window("main") {
+            let _ = window.unminimize();
+            let _ = window.show();
+            let _ = window.set_focus();
+        }
+    }
+
+    fn quit(&self) {
+        self.exit(0);
+    }
+
+    fn changed(&self) {
+        observe(self);
+    }
+}
+
+fn failed(e: String) -> fdo::Error {
+    fdo::Error::Failed(e)
+}
+
+// MPRIS track IDs are object paths, so they are built from the Spotify ID
+// (base-62, which is valid in a path). Local files have no ID.
+fn track_id(track: &Track) -> ObjectPath<'static> {
+    track
+        .id
+        .as_ref()
+        .and_then(|id| ObjectPath::try_from(format!("/org/mpris/MediaPlayer2/Track/{}", id)).ok())
+        .unwrap_or_else(|| ObjectPath::from_static_str_unchecked(NO_TRACK))
+}
+
+fn owned<'a>(value: impl Into<Value<'a>>) -> OwnedValue {
+    value
+        .into()
+        .try_to_owned()
+        .expect("metadata values never contain file descriptors")
+}
+
+fn metadata(track: Option<&Track>) -> HashMap<String, OwnedValue> {
+    let mut metadata = HashMap::new();
+    let Some(track) = track else {
+        metadata.insert("mpris:trackid".to_string(), owned(ObjectPath::from_static_str_unchecked(NO_TRACK)));
+        return metadata;
+    };
+
+    let artists: Vec<String> = track.artists.iter().map(|a| a.name.clone()).collect();
+    let album_artists: Vec<String> = track.album.artists.iter().map(|a| a.name.clone()).collect();
+    metadata.insert("mpris:trackid".to_string(), owned(track_id(track)));
+    // Microseconds.
+    metadata.insert("mpris:length".to_string(), owned(i64::from(track.duration_ms) * 1000));
+    metadata.insert("xesam:title".to_string(), owned(track.name.clone()));
+    metadata.insert("xesam:artist".to_string(), owned(artists));
+    metadata.insert("xesam:albumArtist".to_string(), owned(album_artists));
+    metadata.insert("xesam:album".to_string(), owned(track.album.name.clone()));
+    metadata.insert("xesam:url".to_string(), owned(track.uri.clone()));
+    if let Some(number) = track.track_number {
+        metadata.insert("xesam:trackNumber".to_string(), owned(number as i32));
+    }
+    // Other programs cannot load the `artwork://` URLs, so the original is used.
+    if let Some(image) = track.album.images.first() {
+        metadata.insert("mpris:artUrl".to_string(), owned(image.url.clone()));
+    }
+    metadata
+}
+
+fn playback_status(state: Option<&PlaybackState>) -> &'static str {
+    match state {
+        Some(state) if state.item.is_some() && state.is_playing => "Playing",
+        Some(state) if state.item.is_some() => "Paused",
+        _ => "Stopped",
+    }
+}
+
+// `org.mpris.MediaPlayer2`
+struct Root {
+    host: Arc<dyn Host>,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {
+        self.host.raise();
+    }
+
+    fn quit(&self) {
+        self.host.quit();
+    }
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> String {
+        IDENTITY.to_string()
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        vec!["spotify".to_string()]
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+// `org.mpris.MediaPlayer2.Player`
+struct Player {
+    host: Arc<dyn Host>,
+}
+
+impl Player {
+    async fn run(&self, action: Action) -> fdo::Result<()> {
+        self.host.dispatch(action).await.map_err(failed)?;
+        // Announce what the action changed. Spawned, since the interface is
+        // locked until this call returns.
+        self.host.changed();
+        Ok(())
+    }
+
+    async fn seek_to(&self, ctxt: &SignalContext<'_>, position_ms: u32) -> fdo::Result<()> {
+        self.run(Action::Seek(position_ms)).await?;
+        Self::seeked(ctxt, i64::from(position_ms) * 1000).await?;
+        Ok(())
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    async fn next(&self) -> fdo::Result<()> {
+        self.run(Action::Next).await
+    }
+
+    async fn previous(&self) -> fdo::Result<()> {
+        self.run(Action::Previous).await
+    }
+
+    async fn pause(&self) -> fdo::Result<()> {
+        self.run(Action::Pause).await
+    }
+
+    async fn play_pause(&self) -> fdo::Result<()> {
+        self.run(Action::PlayPause).await
+    }
+
+    // Spotify has no "stop", so this pauses.
+    async fn stop(&self) -> fdo::Result<()> {
+        self.run(Action::Pause).await
+    }
+
+    async fn play(&self) -> fdo::Result<()> {
+        self.run(Action::Play).await
+    }
+
+    // `offset` is relative to the current position, in microseconds.
+    async fn seek(&self, #[zbus(signal_context)] ctxt: SignalContext<'_>, offset: i64) -> fdo::Result<()> {
+        let Some(state) = self.host.now_playing() else {
+            return Ok(());
+        };
+        let Some(track) = &state.item else {
+            return Ok(());
+        };
+        let position = i64::from(state.progress_ms.unwrap_or(0)) + offset / 1000;
+        if position >= i64::from(track.duration_ms) {
+            // Seeking past the end skips to the next track, as MPRIS asks.
+            self.run(Action::Next).await
+        } else {
+            self.seek_to(&ctxt, position.max(0) as u32).await
+        }
+    }
+
+    async fn set_position(
+        &self,
+        #[zbus(signal_context)] ctxt: SignalContext<'_>,
+        track_id: ObjectPath<'_>,
+        position: i64,
+    ) -> fdo::Result<()> {
+        let Some(track) = self.host.now_playing().and_then(|state| state.item) else {
+            return Ok(());
+        };
+        // Requests for another track or beyond its end are ignored.
+        if track_id.as_str() != self::track_id(&track).as_str()
+            || position < 0
+            || position / 1000 > i64::from(track.duration_ms)
+        {
+            return Ok(());
+        }
+        self.seek_to(&ctxt, (position / 1000) as u32).await
+    }
+
+    async fn open_uri(&self, uri: String) -> fdo::Result<()> {
+        self.run(Action::OpenUri(uri)).await
+    }
+
+    // The new position, in microseconds.
+    #[zbus(signal)]
+    async fn seeked(ctxt: &SignalContext<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> String {
+        playback_status(self.host.now_playing().as_ref()).to_string()
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    // Required to be writable, but only 1.0 is supported.
+    #[zbus(property)]
+    fn set_rate(&self, _rate: f64) {}
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn shuffle(&self) -> bool {
+        self.host
+            .now_playing()
+            .and_then(|state| state.shuffle_state)
+            .unwrap_or(false)
+    }
+
+    #[zbus(property)]
+    async fn set_shuffle(&self, shuffle: bool) -> zbus::Result<()> {
+        self.run(Action::SetShuffle(shuffle)).await.map_err(zbus::Error::from)
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        metadata(self.host.now_playing().and_then(|state| state.item).as_ref())
+    }
+
+    // 0.0 to 1.0.
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        self.host
+            .now_playing()
+            .and_then(|state| state.device)
+            .and_then(|device| device.volume_percent)
+            .map(|volume| f64::from(volume) / 100.0)
+            .unwrap_or(0.0)
+    }
+
+    #[zbus(property)]
+    async fn set_volume(&self, volume: f64) -> zbus::Result<()> {
+        let percent = (volume.clamp(0.0, 1.0) * 100.0).round() as u8;
+        self.run(Action::SetVolume(percent)).await.map_err(zbus::Error::from)
+    }
+
+    // Microseconds. Clients are expected to poll this, so no change signals.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        self.host
+            .now_playing()
+            .and_then(|state| state.progress_ms)
+            .map(|progress| i64::from(progress) * 1000)
+            .unwrap_or(0)
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+// The property values last announced on the bus.
+#[derive(Default, PartialEq)]
+struct Published {
+    track: Option<String>,
+    status: &'static str,
+    volume: Option<u32>,
+    shuffle: Option<bool>,
+}
+
+impl Published {
+    fn of(state: Option<&PlaybackState>) -> Self {
+        Published {
+            track: state.and_then(|s| s.item.as_ref()).map(|track| track.uri.clone()),
+            status: playback_status(state),
+            volume: state.and_then(|s| s.device.as_ref()).and_then(|d| d.volume_percent),
+            shuffle: state.and_then(|s| s.shuffle_state),
+        }
+    }
+}
+
+pub struct Mpris {
+    connection: Connection,
+    published: Mutex<Published>,
+}
+
+impl Mpris {
+    // Claim the bus name on the connection `builder` makes and serve the
+    // player there.
+    async fn serve(builder: zbus::connection::Builder<'_>, host: Arc<dyn Host>) -> zbus::Result<Self> {
+        let connection = builder
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, Root { host: host.clone() })?
+            .serve_at(OBJECT_PATH, Player { host })?
+            .build()
+            .await?;
+        Ok(Mpris {
+            connection,
+            published: Mutex::new(Published::default()),
+        })
+    }
+
+    // Emit PropertiesChanged for whatever `state` changed.
+    async fn publish(&self, state: Option<&PlaybackState>) -> zbus::Result<()> {
+        let current = Published::of(state);
+        let (track, status, volume, shuffle) = {
+            let mut published = self.published.lock().unwrap();
+            if *published == current {
+                return Ok(());
+            }
+            let changed = (
+                published.track != current.track,
+                published.status != current.status,
+                published.volume != current.volume,
+                published.shuffle != current.shuffle,
+            );
+            *published = current;
+            changed
+        };
+
+        let iface = self
+            .connection
+            .object_server()
+            .interface::<_, Player>(OBJECT_PATH)
+            .await?;
+        let player = iface.get().await;
+        let ctxt = iface.signal_context();
+        if track {
+            player.metadata_changed(ctxt).await?;
+        }
+        if status {
+            player.playback_status_changed(ctxt).await?;
+        }
+        if volume {
+            player.volume_changed(ctxt).await?;
+        }
+        if shuffle {
+            player.shuffle_changed(ctxt).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Claim the MPRIS bus name and serve the player. Without a session bus
+/// (e.g. on a bare kiosk) this only logs and the app carries on.
+pub async fn start(app: tauri::AppHandle) {
+    let result = async { Mpris::serve(zbus::connection::Builder::session()?, Arc::new(app.clone())).await }.await;
+    match result {
+        Ok(mpris) => {
+            app.manage(mpris);
+        }
+        Err(e) => log::error!("Failed to start MPRIS service: {}", e),
+    }
+}
+
+async fn publish(app: &tauri::AppHandle) -> zbus::Result<()> {
+    match app.try_state::<Mpris>() {
+        Some(mpris) => mpris.publish(control::now_playing(app).as_ref()).await,
+        None => Ok(()),
+    }
+}
+
+pub fn observe(app: &tauri::AppHandle) {
+    if app.try_state::<Mpris>().is_none() {
+        return;
+    }
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = publish(&app).await {
+            log::error!("Failed to publish MPRIS properties: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use zbus::fdo::PropertiesProxy;
+    use zbus::message::Type as MessageType;
+    use zbus::names::InterfaceName;
+    use zbus::zvariant::OwnedObjectPath;
+    use zbus::{MatchRule, MessageStream};
+
+    use super::*;
+    use crate::spotify::fixtures;
+
+    const PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+
+    // A private session bus, stopped when dropped.
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        fn start() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("the MPRIS tests need dbus-daemon");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Bus {
+                daemon,
+                address: address.trim().to_string(),
+            }
+        }
+
+        fn builder(&self) -> zbus::connection::Builder<'static> {
+            zbus::connection::Builder::address(self.address.as_str()).unwrap()
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeHost {
+        state: Mutex<Option<PlaybackState>>,
+        actions: Mutex<Vec<Action>>,
+    }
+
+    impl Host for FakeHost {
+        fn now_playing(&self) -> Option<PlaybackState> {
+            self.state.lock().unwrap().clone()
+        }
+
+        fn dispatch(&self, action: Action) -> BoxFuture<'_, Result<(), String>> {
+            self.actions.lock().unwrap().push(action);
+            Box::pin(async { Ok(()) })
+        }
+
+        fn raise(&self) {}
+
+        fn quit(&self) {}
+
+        fn changed(&self) {}
+    }
+
+    fn playing(id: &str, title: &str) -> PlaybackState {
+        let mut state = fixtures::playing(id, 12_000);
+        state.item.as_mut().unwrap().name = title.to_string();
+        state
+    }
+
+    // The position in the next Seeked signal.
+    async fn next_seek(seeked: &mut MessageStream) -> i64 {
+        let message = tokio::time::timeout(Duration::from_secs(5), seeked.next())
+            .await
+            .expect("no Seeked signal")
+            .unwrap()
+            .unwrap();
+        message.body().deserialize::<i64>().unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_the_player() {
+        let bus = Bus::start();
+        let host = Arc::new(FakeHost::default());
+        *host.state.lock().unwrap() = Some(playing("abc", "First"));
+        let mpris = Mpris::serve(bus.builder(), host.clone()).await.unwrap();
+        mpris.publish(host.now_playing().as_ref()).await.unwrap();
+
+        let client = bus.builder().build().await.unwrap();
+        let properties = PropertiesProxy::builder(&client)
+            .destination(BUS_NAME)
+            .unwrap()
+            .path(OBJECT_PATH)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let player = InterfaceName::from_static_str_unchecked(PLAYER);
+
+        let status = properties.get(player.clone(), "PlaybackStatus").await.unwrap();
+        assert_eq!(String::try_from(status).unwrap(), "Playing");
+        let volume = properties.get(player.clone(), "Volume").await.unwrap();
+        assert_eq!(f64::try_from(volume).unwrap(), 0.5);
+
+        let metadata = properties.get(player.clone(), "Metadata").await.unwrap();
+        let metadata = HashMap::<String, OwnedValue>::try_from(metadata).unwrap();
+        let get = |key: &str| metadata[key].try_clone().unwrap();
+        assert_eq!(
+            OwnedObjectPath::try_from(get("mpris:trackid")).unwrap().as_str(),
+            "/org/mpris/MediaPlayer2/Track/abc"
+        );
+        assert_eq!(String::try_from(get("xesam:title")).unwrap(), "First");
+        assert_eq!(Vec::<String>::try_from(get("xesam:artist")).unwrap(), ["One", "Two"]);
+        assert_eq!(
+            Vec::<String>::try_from(get("xesam:albumArtist")).unwrap(),
+            ["Various Artists"]
+        );
+        assert_eq!(i64::try_from(get("mpris:length")).unwrap(), 200_000_000);
+        assert_eq!(i32::try_from(get("xesam:trackNumber")).unwrap(), 3);
+        assert_eq!(
+            String::try_from(get("mpris:artUrl")).unwrap(),
+            "https://i.scdn.co/image/x"
+        );
+
+        // Only the track changes, so only the metadata is announced.
+        let mut changes = properties.receive_properties_changed().await.unwrap();
+        *host.state.lock().unwrap() = Some(playing("def", "Second"));
+        mpris.publish(host.now_playing().as_ref()).await.unwrap();
+        let signal = tokio::time::timeout(Duration::from_secs(5), changes.next())
+            .await
+            .expect("no PropertiesChanged signal")
+            .unwrap();
+        let args = signal.args().unwrap();
+        assert_eq!(args.interface_name().as_str(), PLAYER);
+        let changed: Vec<&str> = args.changed_properties().keys().copied().collect();
+        assert_eq!(changed, ["Metadata"]);
+        let metadata =
+            HashMap::<String, OwnedValue>::try_from(args.changed_properties()["Metadata"].try_to_owned().unwrap())
+                .unwrap();
+        assert_eq!(
+            String::try_from(metadata["xesam:title"].try_clone().unwrap()).unwrap(),
+            "Second"
+        );
+
+        // Nothing new, nothing sent.
+        mpris.publish(host.now_playing().as_ref()).await.unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(200), changes.next())
+            .await
+            .is_err());
+
+        client
+            .call_method(Some(BUS_NAME), OBJECT_PATH, Some(PLAYER), "PlayPause", &())
+            .await
+            .unwrap();
+
+        // Seeks are announced with their new position.
+        let rule = MatchRule::builder()
+            .msg_type(MessageType::Signal)
+            .interface(PLAYER)
+            .unwrap()
+            .member("Seeked")
+            .unwrap()
+            .build();
+        let mut seeked = MessageStream::for_match_rule(rule, &client, None).await.unwrap();
+        client
+            .call_method(Some(BUS_NAME), OBJECT_PATH, Some(PLAYER), "Seek", &(-20_000_000i64))
+            .await
+            .unwrap();
+        assert_eq!(next_seek(&mut seeked).await, 0);
+        let track = OwnedObjectPath::try_from("/org/mpris/MediaPlayer2/Track/def").unwrap();
+        client
+            .call_method(
+                Some(BUS_NAME),
+                OBJECT_PATH,
+                Some(PLAYER),
+                "SetPosition",
+                &(&track, 5_000_000i64),
+            )
+            .await
+            .unwrap();
+        assert_eq!(next_seek(&mut seeked).await, 5_000_000);
+        assert_eq!(
+            *host.actions.lock().unwrap(),
+            [Action::PlayPause, Action::Seek(0), Action::Seek(5_000)]
+        );
+    }
+}
@@ -0,0 +1,571 @@
+// MQTT integration for home automation, such as Home Assistant with a local
+// broker.
+//
+// The playback state is published as retained JSON on `<prefix>/state`
+// whenever something other than the progress changes. `<prefix>/availability`
+// says `online`, or `offline` once the connection drops (set by the broker
+// through a last will). Commands arrive on `<prefix>/command/<name>`: `play`,
+// `pause`, `play_pause`, `next` and `previous` ignore the payload, `volume`
+// takes 0-100, `playlist` an ID, URI or name, and `transfer` a device ID or
+// name. With discovery on, matching Home Assistant entities are announced
+// under the discovery prefix every time the connection comes up.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::Manager;
+use tokio::sync::mpsc;
+
+use crate::control::Action;
+use crate::spotify::{PlaybackState, Track};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+fn default_host() -> String {
+    "localhost".to_string()
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "playback-controller".to_string()
+}
+
+fn default_topic_prefix() -> String {
+    "playback".to_string()
+}
+
+fn default_discovery() -> bool {
+    true
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MqttConfig {
+    #[serde(default = "default_host")]
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default = "default_topic_prefix")]
+    pub topic_prefix: String,
+    #[serde(default = "default_discovery")]
+    pub discovery: bool,
+    #[serde(default = "default_discovery_prefix")]
+    pub discovery_prefix: String,
+}
+
+// What was last published on the state topic.
+#[derive(Default)]
+struct Published {
+    // The payload without the progress, to tell whether anything changed.
+    summary: Option<Value>,
+    payload: Option<Vec<u8>>,
+}
+
+pub struct Mqtt {
+    config: MqttConfig,
+    client: AsyncClient,
+    published: Arc<Mutex<Published>>,
+    connection: tauri::async_runtime::JoinHandle<()>,
+}
+
+impl Drop for Mqtt {
+    fn drop(&mut self) {
+        self.connection.abort();
+    }
+}
+
+fn state_topic(config: &MqttConfig) -> String {
+    format!("{}/state", config.topic_prefix)
+}
+
+fn availability_topic(config: &MqttConfig) -> String {
+    format!("{}/availability", config.topic_prefix)
+}
+
+fn command_topic(config: &MqttConfig, command: &str) -> String {
+    format!("{}/command/{}", config.topic_prefix, command)
+}
+
+fn parse_command(command: &str, payload: &str) -> Result<Action, String> {
+    let payload = payload.trim();
+    match command {
+        "play" => Ok(Action::Play),
+        "pause" => Ok(Action::Pause),
+        "play_pause" => Ok(Action::PlayPause),
+        "next" => Ok(Action::Next),
+        "previous" => Ok(Action::Previous),
+        // Home Assistant sends numbers as floats, e.g. "40.0".
+        "volume" => match payload.parse::<f64>() {
+            Ok(volume) if (0.0..=100.0).contains(&volume) => Ok(Action::SetVolume(volume.round() as u8)),
+            _ => Err(format!("Invalid volume '{}'.", payload)),
+        },
+        "playlist" if !payload.is_empty() => Ok(Action::Playlist(payload.to_string())),
+        "transfer" if !payload.is_empty() => Ok(Action::Transfer(payload.to_string())),
+        "playlist" | "transfer" => Err(format!("'{}' needs a payload.", command)),
+        _ => Err(format!("Unknown command '{}'.", command)),
+    }
+}
+
+// Home Assistant discovery topics and payloads.
+fn discovery(config: &MqttConfig) -> Vec<(String, Value)> {
+    let node = &config.client_id;
+    let device = json!({
+        "identifiers": [node],
+        "name": "Playback Controller",
+    });
+    let entity = |component: &str, object: &str, name: &str, fields: Value| {
+        let mut payload = json!({
+            "name": name,
+            "unique_id": format!("{}_{}", node, object),
+            "availability_topic": availability_topic(config),
+            "device": device,
+        });
+        if let (Some(payload), Value::Object(fields)) = (payload.as_object_mut(), fields) {
+            payload.extend(fields);
+        }
+        (format!("{}/{}/{}/{}/config", config.discovery_prefix, component, node, object), payload)
+    };
+
+    let mut entities = vec![
+        entity(
+            "sensor",
+            "track",
+            "Track",
+            json!({
+                "state_topic": state_topic(config),
+                "value_template": "{{ value_json.title | default('') }}",
+                "json_attributes_topic": state_topic(config),
+                "icon": "mdi:music",
+            }),
+        ),
+        entity(
+            "sensor",
+            "state",
+            "Playback",
+            json!({
+                "state_topic": state_topic(config),
+                "value_template": "{{ value_json.state }}",
+            }),
+        ),
+        entity(
+            "number",
+            "volume",
+            "Volume",
+            json!({
+                "command_topic": command_topic(config, "volume"),
+                "state_topic": state_topic(config),
+                "value_template": "{{ value_json.volume }}",
+                "min": 0,
+                "max": 100,
+                "step": 1,
+                "unit_of_measurement": "%",
+                "icon": "mdi:volume-high",
+            }),
+        ),
+        entity(
+            "text",
+            "playlist",
+            "Playlist",
+            json!({ "command_topic": command_topic(config, "playlist") }),
+        ),
+        entity(
+            "text",
+            "transfer",
+            "Device",
+            json!({
+                "command_topic": command_topic(config, "transfer"),
+                "state_topic": state_topic(config),
+                "value_template": "{{ value_json.device | default('') }}",
+            }),
+        ),
+    ];
+    for (command, name) in [
+        ("play", "Play"),
+        ("pause", "Pause"),
+        ("play_pause", "Play/Pause"),
+        ("next", "Next"),
+        ("previous", "Previous"),
+    ] {
+        entities.push(entity(
+            "button",
+            command,
+            name,
+            json!({ "command_topic": command_topic(config, command) }),
+        ));
+    }
+    entities
+}
+
+// Announce the entities and the current state once connected.
+fn on_connect(client: &AsyncClient, config: &MqttConfig, published: &Mutex<Published>) {
+    let mut requests = vec![client.try_subscribe(command_topic(config, "+"), QoS::AtLeastOnce)];
+    requests.push(client.try_publish(availability_topic(config), QoS::AtLeastOnce, true, "online"));
+    if config.discovery {
+        for (topic, payload) in discovery(config) {
+            requests.push(client.try_publish(topic, QoS::AtLeastOnce, true, payload.to_string()));
+        }
+    }
+    if let Some(payload) = published.lock().unwrap().payload.clone() {
+        requests.push(client.try_publish(state_topic(config), QoS::AtLeastOnce, true, payload));
+    }
+    if let Some(Err(e)) = requests.into_iter().find(Result::is_err) {
+        log::error!("Failed to queue MQTT messages: {}", e);
+    }
+}
+
+impl Mqtt {
+    /// Connect to the broker in the background, reconnecting whenever the
+    /// connection drops. Commands arrive on the returned receiver.
+    pub fn start(config: MqttConfig) -> (Arc<Self>, mpsc::UnboundedReceiver<Action>) {
+        let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        options.set_last_will(LastWill::new(
+            availability_topic(&config),
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let Some(username) = &config.username {
+            options.set_credentials(username, config.password.clone().unwrap_or_default());
+        }
+        // Enough room for the discovery messages sent on every connect.
+        let (client, mut event_loop) = AsyncClient::new(options, 64);
+
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let published = Arc::new(Mutex::new(Published::default()));
+        let connection = {
+            let client = client.clone();
+            let config = config.clone();
+            let published = published.clone();
+            let prefix = format!("{}/command/", config.topic_prefix);
+            tauri::async_runtime::spawn(async move {
+                // Only report an error when it changes, not on every retry.
+                let mut last_error = None;
+                loop {
+                    match event_loop.poll().await {
+                        Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                            last_error = None;
+                            on_connect(&client, &config, &published);
+                        }
+                        Ok(Event::Incoming(Packet::Publish(message))) => {
+                            let Some(command) = message.topic.strip_prefix(&prefix) else {
+                                continue;
+                            };
+                            match parse_command(command, &String::from_utf8_lossy(&message.payload)) {
+                                Ok(action) => {
+                                    if commands.send(action).is_err() {
+                                        return;
+                                    }
+                                }
+                                Err(e) => log::warn!("Ignoring MQTT command on {}: {}", message.topic, e),
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            let e = e.to_string();
+                            if last_error.as_ref() != Some(&e) {
+                                log::warn!("MQTT connection to {}:{} failed: {}", config.host, config.port, e);
+                                last_error = Some(e);
+                            }
+                            tokio::time::sleep(RECONNECT_DELAY).await;
+                        }
+                    }
+                }
+            })
+        };
+
+        let mqtt = Mqtt {
+            config,
+            client,
+            published,
+            connection,
+        };
+        (Arc::new(mqtt), receiver)
+    }
+
+    pub fn config(&self) -> &MqttConfig {
+        &self.config
+    }
+
+    /// Publish `state` (or that nothing is playing) if anything but the
+    /// progress changed since the last time.
+    pub fn update(&self, state: Option<&PlaybackState>) {
+        let summary = match state {
+            Some(state) => {
+                let track = state.item.as_ref();
+                json!({
+                    "state": if state.is_playing { "playing" } else { "paused" },
+                    "title": track.map(|track| &track.name),
+                    "artist": track.map(Track::artist_names),
+                    "album": track.map(|track| &track.album.name),
+                    "uri": track.map(|track| &track.uri),
+                    "image": track.and_then(|track| track.album.images.first().map(|image| &image.url)),
+                    "duration_ms": track.map(|track| track.duration_ms),
+                    "device": state.device.as_ref().map(|device| &device.name),
+                    "volume": state.device.as_ref().and_then(|device| device.volume_percent),
+                    "shuffle": state.shuffle_state,
+                })
+            }
+            None => json!({ "state": "idle" }),
+        };
+
+        let mut published = self.published.lock().unwrap();
+        if published.summary.as_ref() == Some(&summary) {
+            return;
+        }
+        let mut payload = summary.clone();
+        if let Some(state) = state {
+            payload["progress_ms"] = json!(state.progress_ms);
+        }
+        let payload = payload.to_string().into_bytes();
+        match self
+            .client
+            .try_publish(state_topic(&self.config), QoS::AtLeastOnce, true, payload.clone())
+        {
+            Ok(()) => {
+                published.summary = Some(summary);
+                published.payload = Some(payload);
+            }
+            Err(e) => log::error!("Failed to publish playback state: {}", e),
+        }
+    }
+}
+
+// Publish a playback payload, if MQTT is configured.
+pub fn observe(app: &tauri::AppHandle, state: Option<&PlaybackState>) {
+    if let Some(mqtt) = app.try_state::<Arc<Mqtt>>() {
+        mqtt.update(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use rumqttc::Publish;
+
+    use super::*;
+    use crate::control;
+    use crate::spotify::fixtures;
+
+    fn config(host: &str, port: u16, prefix: &str) -> MqttConfig {
+        MqttConfig {
+            host: host.to_string(),
+            port,
+            client_id: prefix.to_string(),
+            username: None,
+            password: None,
+            topic_prefix: prefix.to_string(),
+            discovery: true,
+            discovery_prefix: format!("{}-discovery", prefix),
+        }
+    }
+
+    fn playing(progress_ms: u32, volume: u32) -> PlaybackState {
+        let mut state = fixtures::playing("abc", progress_ms);
+        state.device.as_mut().unwrap().volume_percent = Some(volume);
+        state
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse_command("play", "ignored"), Ok(Action::Play));
+        assert_eq!(parse_command("play_pause", ""), Ok(Action::PlayPause));
+        assert_eq!(parse_command("volume", "40.0"), Ok(Action::SetVolume(40)));
+        assert_eq!(parse_command("volume", " 55 "), Ok(Action::SetVolume(55)));
+        assert_eq!(parse_command("volume", "49.6"), Ok(Action::SetVolume(50)));
+        assert_eq!(parse_command("volume", "100"), Ok(Action::SetVolume(100)));
+        for volume in ["-1", "100.5", "1e3", "loud", "", "NaN"] {
+            assert!(parse_command("volume", volume).is_err(), "{}", volume);
+        }
+        assert_eq!(
+            parse_command("playlist", " Road trip "),
+            Ok(Action::Playlist("Road trip".to_string()))
+        );
+        assert_eq!(
+            parse_command("transfer", "Kitchen"),
+            Ok(Action::Transfer("Kitchen".to_string()))
+        );
+        for command in ["playlist", "transfer"] {
+            assert!(parse_command(command, "").is_err());
+            assert!(parse_command(command, "  ").is_err());
+        }
+        assert!(parse_command("rewind", "").is_err());
+    }
+
+    #[test]
+    fn discovery_entities() {
+        let entities = discovery(&config("localhost", 1883, "playback"));
+        let topics: Vec<&str> = entities.iter().map(|(topic, _)| topic.as_str()).collect();
+        assert_eq!(
+            topics,
+            [
+                "playback-discovery/sensor/playback/track/config",
+                "playback-discovery/sensor/playback/state/config",
+                "playback-discovery/number/playback/volume/config",
+                "playback-discovery/text/playback/playlist/config",
+                "playback-discovery/text/playback/transfer/config",
+                "playback-discovery/button/playback/play/config",
+                "playback-discovery/button/playback/pause/config",
+                "playback-discovery/button/playback/play_pause/config",
+                "playback-discovery/button/playback/next/config",
+                "playback-discovery/button/playback/previous/config",
+            ]
+        );
+        for (_, payload) in &entities {
+            assert_eq!(payload["availability_topic"], "playback/availability");
+            assert_eq!(payload["device"]["identifiers"], json!(["playback"]));
+        }
+        let volume = &entities[2].1;
+        assert_eq!(volume["unique_id"], "playback_volume");
+        assert_eq!(volume["command_topic"], "playback/command/volume");
+        assert_eq!(volume["state_topic"], "playback/state");
+        assert_eq!(volume["max"], 100);
+        assert_eq!(entities[8].1["command_topic"], "playback/command/next");
+    }
+
+    #[tokio::test]
+    async fn progress_alone_is_not_republished() {
+        // Nothing listens on the port, so messages only queue up.
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let (mqtt, _commands) = Mqtt::start(config("127.0.0.1", port, "playback"));
+        let payload = || -> Value {
+            let published = mqtt.published.lock().unwrap();
+            serde_json::from_slice(published.payload.as_ref().unwrap()).unwrap()
+        };
+
+        mqtt.update(Some(&playing(1_000, 40)));
+        assert_eq!(payload()["progress_ms"], 1_000);
+        assert_eq!(payload()["artist"], "One, Two");
+        mqtt.update(Some(&playing(6_000, 40)));
+        assert_eq!(payload()["progress_ms"], 1_000);
+
+        mqtt.update(Some(&playing(7_000, 45)));
+        assert_eq!(payload()["progress_ms"], 7_000);
+        assert_eq!(payload()["volume"], 45);
+
+        mqtt.update(None);
+        assert_eq!(payload(), json!({ "state": "idle" }));
+    }
+
+    #[tokio::test]
+    async fn merged_payloads_are_not_republished() {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let (mqtt, _commands) = Mqtt::start(config("127.0.0.1", port, "playback"));
+        let payload = || -> Value {
+            let published = mqtt.published.lock().unwrap();
+            serde_json::from_slice(published.payload.as_ref().unwrap()).unwrap()
+        };
+        // What `/me/player/currently-playing` returns between full payloads.
+        let currently_playing = |progress_ms| {
+            let mut state = playing(progress_ms, 0);
+            state.device = None;
+            state.shuffle_state = None;
+            state.repeat_state = None;
+            state
+        };
+
+        let mut last = playing(1_000, 40);
+        mqtt.update(Some(&last));
+        for progress_ms in [2_000, 3_000, 4_000] {
+            last = if progress_ms % 2_000 == 0 {
+                control::merge(Some(&last), &currently_playing(progress_ms))
+            } else {
+                playing(progress_ms, 40)
+            };
+            mqtt.update(Some(&last));
+            assert_eq!(payload()["progress_ms"], 1_000);
+            assert_eq!(payload()["device"], "Desk");
+            assert_eq!(payload()["volume"], 40);
+            assert_eq!(payload()["shuffle"], false);
+        }
+
+        // Unmerged, the same payload looks like a change.
+        mqtt.update(Some(&currently_playing(5_000)));
+        assert_eq!(payload()["progress_ms"], 5_000);
+        assert_eq!(payload()["device"], Value::Null);
+    }
+
+    // Run with e.g. `MQTT_TEST_BROKER=localhost:1883 cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore = "needs an MQTT broker in MQTT_TEST_BROKER"]
+    async fn against_a_broker() {
+        let broker = std::env::var("MQTT_TEST_BROKER").expect("MQTT_TEST_BROKER is not set");
+        let (host, port) = broker.rsplit_once(':').expect("MQTT_TEST_BROKER should be host:port");
+        let port: u16 = port.parse().unwrap();
+        let prefix = format!("playback-test-{}", std::process::id());
+        let config = config(host, port, &prefix);
+
+        let (mqtt, mut commands) = Mqtt::start(config.clone());
+        mqtt.update(Some(&playing(1_000, 40)));
+
+        // Another client sees the retained messages and sends a command.
+        let (watcher, mut events) = AsyncClient::new(MqttOptions::new(format!("{}-watcher", prefix), host, port), 64);
+        watcher
+            .subscribe(format!("{}/#", prefix), QoS::AtLeastOnce)
+            .await
+            .unwrap();
+        watcher
+            .subscribe(format!("{}/#", config.discovery_prefix), QoS::AtLeastOnce)
+            .await
+            .unwrap();
+        let (received, mut messages) = mpsc::unbounded_channel::<Publish>();
+        let watching = tokio::spawn(async move {
+            while let Ok(event) = events.poll().await {
+                if let Event::Incoming(Packet::Publish(message)) = event {
+                    let _ = received.send(message);
+                }
+            }
+        });
+
+        let mut seen = std::collections::HashMap::new();
+        let expected = 2 + discovery(&config).len();
+        while seen.len() < expected {
+            let message = tokio::time::timeout(Duration::from_secs(10), messages.recv())
+                .await
+                .expect("timed out waiting for retained messages")
+                .unwrap();
+            if !message.payload.is_empty() {
+                seen.insert(message.topic.clone(), message.payload.to_vec());
+            }
+        }
+        assert_eq!(seen[&availability_topic(&config)], b"online");
+        let state: Value = serde_json::from_slice(&seen[&state_topic(&config)]).unwrap();
+        assert_eq!(state["title"], "Title abc");
+
+        watcher
+            .publish(command_topic(&config, "volume"), QoS::AtLeastOnce, false, "40.0")
+            .await
+            .unwrap();
+        let action = tokio::time::timeout(Duration::from_secs(10), commands.recv())
+            .await
+            .unwrap();
+        assert_eq!(action, Some(Action::SetVolume(40)));
+
+        // Leave nothing retained on the broker.
+        for topic in seen.keys() {
+            watcher
+                .publish(topic.clone(), QoS::AtLeastOnce, true, Vec::new())
+                .await
+                .unwrap();
+        }
+        drop(mqtt);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        watching.abort();
+    }
+}
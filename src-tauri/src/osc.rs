@@ -0,0 +1,636 @@
+// OSC (Open Sound Control) over UDP, for show control software such as QLab
+// and TouchOSC.
+//
+// Messages under the address prefix (`/playback` by default) control
+// playback: `play`, `pause`, `toggle`, `next` and `previous` take no
+// arguments, or a number or boolean where zero and false are ignored so
+// buttons that also send on release work. `volume` takes a float from 0 to 1
+// or an int from 0 to 100, `shuffle` a number or boolean, `seek` a position
+// in seconds, `playlist` an ID, URI or name and `transfer` a device ID or
+// name. Bundles are unpacked and run straight away, whatever their time tag.
+//
+// Whenever the playback state changes, the changed values are sent to each
+// feedback target from the listening socket: `state` ("playing", "paused"
+// or "idle"), `title`, `artist` and `album` as strings, `volume` as a float
+// from 0 to 1 and `shuffle` as an int.
+
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+use tokio::sync::mpsc;
+
+use crate::control::Action;
+use crate::spotify::{PlaybackState, Track};
+
+// Larger than any message this address space needs.
+const MAX_PACKET: usize = 8192;
+
+fn default_bind() -> String {
+    "127.0.0.1:9000".to_string()
+}
+
+fn default_prefix() -> String {
+    "/playback".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OscConfig {
+    // Use `0.0.0.0:<port>` to accept messages from other machines.
+    #[serde(default = "default_bind")]
+    pub bind: String,
+    // `host:port` addresses that get feedback messages.
+    #[serde(default)]
+    pub feedback: Vec<String>,
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Arg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Blob(Vec<u8>),
+    Long(i64),
+    Double(f64),
+    Bool(bool),
+    Nil,
+}
+
+impl Arg {
+    fn number(&self) -> Option<f64> {
+        match self {
+            Arg::Int(value) => Some(*value as f64),
+            Arg::Float(value) => Some(*value as f64),
+            Arg::Long(value) => Some(*value as f64),
+            Arg::Double(value) => Some(*value),
+            Arg::Bool(value) => Some(if *value { 1.0 } else { 0.0 }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Message {
+    address: String,
+    args: Vec<Arg>,
+}
+
+// Reads the big-endian, 4-byte aligned fields of an OSC packet.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.bytes.len() {
+            return Err("Truncated OSC packet.".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self
+            .bytes
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or("Unterminated OSC string.")?;
+        let text = String::from_utf8_lossy(&self.bytes[..len]).into_owned();
+        self.take((len + 4) & !3)?;
+        Ok(text)
+    }
+
+    fn blob(&mut self) -> Result<Vec<u8>, String> {
+        let len = i32::from_be_bytes(self.array()?);
+        let len = usize::try_from(len).map_err(|_| "Negative OSC blob size.")?;
+        let blob = self.take(len)?.to_vec();
+        self.take((4 - len % 4) % 4)?;
+        Ok(blob)
+    }
+}
+
+fn decode_message(bytes: &[u8]) -> Result<Message, String> {
+    let mut reader = Reader { bytes };
+    let address = reader.string()?;
+    // Very old senders leave out the type tags.
+    if reader.bytes.is_empty() {
+        return Ok(Message { address, args: Vec::new() });
+    }
+    let tags = reader.string()?;
+    let tags = tags.strip_prefix(',').ok_or("Missing OSC type tags.")?;
+    let mut args = Vec::new();
+    for tag in tags.chars() {
+        args.push(match tag {
+            'i' => Arg::Int(i32::from_be_bytes(reader.array()?)),
+            'f' => Arg::Float(f32::from_be_bytes(reader.array()?)),
+            's' | 'S' => Arg::Str(reader.string()?),
+            'b' => Arg::Blob(reader.blob()?),
+            'h' => Arg::Long(i64::from_be_bytes(reader.array()?)),
+            'd' => Arg::Double(f64::from_be_bytes(reader.array()?)),
+            // Time tags, colors and MIDI messages are read and ignored.
+            't' => {
+                reader.take(8)?;
+                Arg::Nil
+            }
+            'r' | 'm' | 'c' => {
+                reader.take(4)?;
+                Arg::Nil
+            }
+            'T' => Arg::Bool(true),
+            'F' => Arg::Bool(false),
+            'N' | 'I' => Arg::Nil,
+            _ => return Err(format!("Unsupported OSC type tag '{}'.", tag)),
+        });
+    }
+    Ok(Message { address, args })
+}
+
+// The messages in a packet, with bundles flattened.
+fn decode(bytes: &[u8], messages: &mut Vec<Message>) -> Result<(), String> {
+    let Some(mut elements) = bytes.strip_prefix(b"#bundle\0") else {
+        messages.push(decode_message(bytes)?);
+        return Ok(());
+    };
+    let mut reader = Reader { bytes: elements };
+    reader.take(8)?; // Time tag
+    elements = reader.bytes;
+    while !elements.is_empty() {
+        let mut reader = Reader { bytes: elements };
+        let len = i32::from_be_bytes(reader.array()?);
+        let len = usize::try_from(len).map_err(|_| "Negative OSC bundle element size.")?;
+        decode(reader.take(len)?, messages)?;
+        elements = reader.bytes;
+    }
+    Ok(())
+}
+
+fn push_string(bytes: &mut Vec<u8>, text: &str) {
+    bytes.extend_from_slice(text.as_bytes());
+    bytes.resize((bytes.len() + 4) & !3, 0);
+}
+
+fn encode(message: &Message) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    push_string(&mut bytes, &message.address);
+    let mut tags = String::from(",");
+    let mut data = Vec::new();
+    for arg in &message.args {
+        match arg {
+            Arg::Int(value) => {
+                tags.push('i');
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+            Arg::Float(value) => {
+                tags.push('f');
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+            Arg::Str(text) => {
+                tags.push('s');
+                push_string(&mut data, text);
+            }
+            Arg::Blob(blob) => {
+                tags.push('b');
+                data.extend_from_slice(&(blob.len() as i32).to_be_bytes());
+                data.extend_from_slice(blob);
+                data.resize((data.len() + 3) & !3, 0);
+            }
+            Arg::Long(value) => {
+                tags.push('h');
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+            Arg::Double(value) => {
+                tags.push('d');
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+            Arg::Bool(value) => tags.push(if *value { 'T' } else { 'F' }),
+            Arg::Nil => tags.push('N'),
+        }
+    }
+    push_string(&mut bytes, &tags);
+    bytes.extend(data);
+    bytes
+}
+
+// `None` for trigger messages that should be ignored, such as a button
+// being released.
+fn parse_command(command: &str, args: &[Arg]) -> Result<Option<Action>, String> {
+    let number = || {
+        args.first()
+            .and_then(Arg::number)
+            .ok_or(format!("'{}' needs a number.", command))
+    };
+    let text = || match args.first() {
+        Some(Arg::Str(text)) if !text.is_empty() => Ok(text.clone()),
+        _ => Err(format!("'{}' needs a string.", command)),
+    };
+    let pressed = args.first().and_then(Arg::number) != Some(0.0);
+    let trigger = |action| Ok(pressed.then_some(action));
+
+    match command {
+        "play" => trigger(Action::Play),
+        "pause" => trigger(Action::Pause),
+        "toggle" => trigger(Action::PlayPause),
+        "next" => trigger(Action::Next),
+        "previous" => trigger(Action::Previous),
+        "volume" => {
+            let volume = match args.first() {
+                Some(Arg::Float(_) | Arg::Double(_)) => number()? * 100.0,
+                _ => number()?,
+            };
+            if !(0.0..=100.0).contains(&volume) {
+                return Err(format!("Volume {} is out of range.", volume));
+            }
+            Ok(Some(Action::SetVolume(volume.round() as u8)))
+        }
+        "shuffle" => Ok(Some(Action::SetShuffle(number()? != 0.0))),
+        "seek" => {
+            let seconds = number()?;
+            if seconds < 0.0 {
+                return Err("Cannot seek to a negative position.".to_string());
+            }
+            Ok(Some(Action::Seek((seconds * 1000.0).round() as u32)))
+        }
+        "playlist" => Ok(Some(Action::Playlist(text()?))),
+        "transfer" => Ok(Some(Action::Transfer(text()?))),
+        _ => Err(format!("Unknown command '{}'.", command)),
+    }
+}
+
+// The values sent as feedback, to tell which of them changed.
+#[derive(Clone, PartialEq)]
+struct Feedback {
+    state: &'static str,
+    title: String,
+    artist: String,
+    album: String,
+    volume: Option<u32>,
+    shuffle: Option<bool>,
+}
+
+impl Feedback {
+    fn new(state: Option<&PlaybackState>) -> Self {
+        let track = state.and_then(|state| state.item.as_ref());
+        Feedback {
+            state: match state {
+                Some(state) if state.is_playing => "playing",
+                Some(_) => "paused",
+                None => "idle",
+            },
+            title: track.map(|track| track.name.clone()).unwrap_or_default(),
+            artist: track.map(Track::artist_names).unwrap_or_default(),
+            album: track.map(|track| track.album.name.clone()).unwrap_or_default(),
+            volume: state.and_then(|state| state.device.as_ref()?.volume_percent),
+            shuffle: state.and_then(|state| state.shuffle_state),
+        }
+    }
+
+    // Messages for the values that differ from `previous`.
+    fn messages(&self, prefix: &str, previous: Option<&Feedback>) -> Vec<Message> {
+        let first = previous.is_none();
+        let previous = previous.unwrap_or(self);
+        let message = |name: &str, arg| Message {
+            address: format!("{}/{}", prefix, name),
+            args: vec![arg],
+        };
+        let mut messages = Vec::new();
+        if first || previous.state != self.state {
+            messages.push(message("state", Arg::Str(self.state.to_string())));
+        }
+        if first || previous.title != self.title {
+            messages.push(message("title", Arg::Str(self.title.clone())));
+        }
+        if first || previous.artist != self.artist {
+            messages.push(message("artist", Arg::Str(self.artist.clone())));
+        }
+        if first || previous.album != self.album {
+            messages.push(message("album", Arg::Str(self.album.clone())));
+        }
+        if let Some(volume) = self.volume.filter(|_| first || previous.volume != self.volume) {
+            messages.push(message("volume", Arg::Float(volume as f32 / 100.0)));
+        }
+        if let Some(shuffle) = self.shuffle.filter(|_| first || previous.shuffle != self.shuffle) {
+            messages.push(message("shuffle", Arg::Int(shuffle as i32)));
+        }
+        messages
+    }
+}
+
+pub struct Osc {
+    config: Arc<Mutex<OscConfig>>,
+    socket: UdpSocket,
+    last: Mutex<Option<Feedback>>,
+    listener: tauri::async_runtime::JoinHandle<()>,
+}
+
+impl Drop for Osc {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+impl Osc {
+    /// Bind the configured address and listen for messages in the
+    /// background. Commands arrive on the returned receiver.
+    pub fn start(config: OscConfig) -> Result<(Arc<Self>, mpsc::UnboundedReceiver<Action>), String> {
+        let socket = UdpSocket::bind(&config.bind)
+            .and_then(|socket| socket.set_nonblocking(true).map(|()| socket))
+            .map_err(|e| format!("Failed to bind OSC to {}: {}", config.bind, e))?;
+        let listening = socket
+            .try_clone()
+            .map_err(|e| format!("Failed to bind OSC to {}: {}", config.bind, e))?;
+
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let config = Arc::new(Mutex::new(config));
+        let listener = {
+            let config = config.clone();
+            tauri::async_runtime::spawn(async move {
+                let socket = match tokio::net::UdpSocket::from_std(listening) {
+                    Ok(socket) => socket,
+                    Err(e) => {
+                        log::error!("Failed to listen for OSC: {}", e);
+                        return;
+                    }
+                };
+                let mut buffer = vec![0; MAX_PACKET];
+                loop {
+                    let (len, sender) = match socket.recv_from(&mut buffer).await {
+                        Ok(received) => received,
+                        Err(e) => {
+                            log::error!("Failed to receive OSC: {}", e);
+                            continue;
+                        }
+                    };
+                    let mut messages = Vec::new();
+                    if let Err(e) = decode(&buffer[..len], &mut messages) {
+                        log::warn!("Ignoring OSC packet from {}: {}", sender, e);
+                        continue;
+                    }
+                    let prefix = format!("{}/", config.lock().unwrap().prefix);
+                    for message in messages {
+                        let Some(command) = message.address.strip_prefix(&prefix) else {
+                            continue;
+                        };
+                        match parse_command(command, &message.args) {
+                            Ok(Some(action)) => {
+                                if commands.send(action).is_err() {
+                                    return;
+                                }
+                            }
+                            Ok(None) => {}
+                            Err(e) => log::warn!("Ignoring OSC message {}: {}", message.address, e),
+                        }
+                    }
+                }
+            })
+        };
+
+        let osc = Osc {
+            config,
+            socket,
+            last: Mutex::new(None),
+            listener,
+        };
+        Ok((Arc::new(osc), receiver))
+    }
+
+    pub fn bind(&self) -> String {
+        self.config.lock().unwrap().bind.clone()
+    }
+
+    /// Take new feedback targets and prefix without rebinding. Targets get
+    /// the full state with the next update.
+    pub fn reconfigure(&self, config: OscConfig) {
+        *self.config.lock().unwrap() = config;
+        *self.last.lock().unwrap() = None;
+    }
+
+    /// Send what changed in `state` (or that nothing is playing) to the
+    /// feedback targets.
+    pub fn update(&self, state: Option<&PlaybackState>) {
+        let config = self.config.lock().unwrap().clone();
+        if config.feedback.is_empty() {
+            return;
+        }
+        let feedback = Feedback::new(state);
+        let mut last = self.last.lock().unwrap();
+        let messages = feedback.messages(&config.prefix, last.as_ref());
+        *last = Some(feedback);
+        drop(last);
+
+        for target in &config.feedback {
+            let address = match target.to_socket_addrs().map(|mut addresses| addresses.next()) {
+                Ok(Some(address)) => address,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::warn!("Invalid OSC feedback target {}: {}", target, e);
+                    continue;
+                }
+            };
+            for message in &messages {
+                if let Err(e) = self.socket.send_to(&encode(message), address) {
+                    log::error!("Failed to send OSC feedback to {}: {}", target, e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// Send feedback for a playback payload, if OSC is configured.
+pub fn observe(app: &tauri::AppHandle, state: Option<&PlaybackState>) {
+    if let Some(osc) = app.try_state::<Arc<Osc>>() {
+        osc.update(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control;
+    use crate::spotify::fixtures;
+
+    fn message(address: &str, args: Vec<Arg>) -> Message {
+        Message {
+            address: address.to_string(),
+            args,
+        }
+    }
+
+    fn decode_all(bytes: &[u8]) -> Result<Vec<Message>, String> {
+        let mut messages = Vec::new();
+        decode(bytes, &mut messages)?;
+        Ok(messages)
+    }
+
+    fn bundle(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = b"#bundle\0".to_vec();
+        bytes.extend_from_slice(&1u64.to_be_bytes());
+        for element in elements {
+            bytes.extend_from_slice(&(element.len() as i32).to_be_bytes());
+            bytes.extend_from_slice(element);
+        }
+        bytes
+    }
+
+    #[test]
+    fn round_trips_messages() {
+        let original = message(
+            "/playback/test",
+            vec![
+                Arg::Int(-7),
+                Arg::Float(0.5),
+                Arg::Str("abc".to_string()),
+                Arg::Str("abcd".to_string()),
+                Arg::Blob(vec![1, 2, 3, 4, 5]),
+                Arg::Long(1 << 40),
+                Arg::Double(0.25),
+                Arg::Bool(true),
+                Arg::Bool(false),
+                Arg::Nil,
+            ],
+        );
+        let bytes = encode(&original);
+        assert_eq!(bytes.len() % 4, 0);
+        assert_eq!(decode_all(&bytes).unwrap(), vec![original]);
+    }
+
+    #[test]
+    fn rejects_malformed_packets() {
+        // Missing padding after the address and after a blob.
+        assert!(decode_all(b"/playback/play\0").is_err());
+        let mut blob = encode(&message("/x", vec![Arg::Blob(vec![1, 2, 3])]));
+        blob.pop();
+        assert!(decode_all(&blob).is_err());
+        // Unterminated string.
+        assert!(decode_all(b"/playback").is_err());
+
+        // Negative and oversized blob sizes.
+        let mut negative = encode(&message("/x", vec![Arg::Blob(Vec::new())]));
+        let len = negative.len();
+        negative[len - 4..].copy_from_slice(&(-4i32).to_be_bytes());
+        assert!(decode_all(&negative).is_err());
+        let mut oversized = encode(&message("/x", vec![Arg::Blob(vec![0; 4])]));
+        oversized[len - 4..len].copy_from_slice(&1000i32.to_be_bytes());
+        assert!(decode_all(&oversized).is_err());
+
+        // Negative and oversized bundle element sizes.
+        let play = encode(&message("/playback/play", Vec::new()));
+        let mut packet = bundle(std::slice::from_ref(&play));
+        packet[16..20].copy_from_slice(&(-1i32).to_be_bytes());
+        assert!(decode_all(&packet).is_err());
+        packet[16..20].copy_from_slice(&(play.len() as i32 + 4).to_be_bytes());
+        assert!(decode_all(&packet).is_err());
+        // Time tag cut short.
+        assert!(decode_all(b"#bundle\0\0\0\0\0").is_err());
+
+        // Type tags without the leading comma, and unknown tags.
+        assert!(decode_all(b"/x\0\0i\0\0\0\0\0\0\x01").is_err());
+        assert!(decode_all(b"/x\0\0,q\0\0").is_err());
+    }
+
+    #[test]
+    fn flattens_nested_bundles() {
+        let play = encode(&message("/playback/play", Vec::new()));
+        let volume = encode(&message("/playback/volume", vec![Arg::Int(40)]));
+        let next = encode(&message("/playback/next", Vec::new()));
+        let packet = bundle(&[play, bundle(&[volume, bundle(&[])]), next]);
+        let addresses: Vec<_> = decode_all(&packet)
+            .unwrap()
+            .into_iter()
+            .map(|message| message.address)
+            .collect();
+        assert_eq!(addresses, ["/playback/play", "/playback/volume", "/playback/next"]);
+    }
+
+    #[test]
+    fn messages_without_type_tags() {
+        assert_eq!(
+            decode_all(b"/playback/next\0\0").unwrap(),
+            vec![message("/playback/next", Vec::new())]
+        );
+        assert_eq!(parse_command("next", &[]), Ok(Some(Action::Next)));
+    }
+
+    #[test]
+    fn volume_scaling() {
+        let volume = |arg| parse_command("volume", &[arg]);
+        assert_eq!(volume(Arg::Float(0.5)), Ok(Some(Action::SetVolume(50))));
+        assert_eq!(volume(Arg::Double(1.0)), Ok(Some(Action::SetVolume(100))));
+        assert_eq!(volume(Arg::Int(1)), Ok(Some(Action::SetVolume(1))));
+        assert_eq!(volume(Arg::Long(75)), Ok(Some(Action::SetVolume(75))));
+        assert!(volume(Arg::Float(1.5)).is_err());
+        assert!(volume(Arg::Int(101)).is_err());
+        assert!(volume(Arg::Int(-1)).is_err());
+        assert!(volume(Arg::Str("50".to_string())).is_err());
+        assert!(parse_command("volume", &[]).is_err());
+    }
+
+    #[test]
+    fn releases_are_ignored() {
+        for command in ["play", "pause", "toggle", "next", "previous"] {
+            assert_eq!(parse_command(command, &[Arg::Int(0)]), Ok(None), "{}", command);
+            assert_eq!(parse_command(command, &[Arg::Float(0.0)]), Ok(None), "{}", command);
+            assert_eq!(parse_command(command, &[Arg::Bool(false)]), Ok(None), "{}", command);
+        }
+        assert_eq!(parse_command("play", &[Arg::Int(1)]), Ok(Some(Action::Play)));
+        assert_eq!(parse_command("pause", &[Arg::Bool(true)]), Ok(Some(Action::Pause)));
+        assert_eq!(parse_command("toggle", &[Arg::Float(1.0)]), Ok(Some(Action::PlayPause)));
+        assert_eq!(parse_command("previous", &[Arg::Nil]), Ok(Some(Action::Previous)));
+        // Only triggers ignore zero.
+        assert_eq!(
+            parse_command("shuffle", &[Arg::Int(0)]),
+            Ok(Some(Action::SetShuffle(false)))
+        );
+        assert_eq!(parse_command("seek", &[Arg::Float(0.0)]), Ok(Some(Action::Seek(0))));
+        assert!(parse_command("rewind", &[]).is_err());
+    }
+
+    #[test]
+    fn feedback_follows_changes() {
+        let names =
+            |messages: Vec<Message>| -> Vec<String> { messages.into_iter().map(|message| message.address).collect() };
+        // What `/me/player/currently-playing` returns between full payloads.
+        let currently_playing = |track| {
+            let mut state = fixtures::playing(track, 0);
+            state.device = None;
+            state.shuffle_state = None;
+            state.repeat_state = None;
+            state
+        };
+
+        let mut last = fixtures::playing("a", 0);
+        let mut feedback = Feedback::new(Some(&last));
+        assert_eq!(
+            names(feedback.messages("/p", None)),
+            [
+                "/p/state",
+                "/p/title",
+                "/p/artist",
+                "/p/album",
+                "/p/volume",
+                "/p/shuffle"
+            ]
+        );
+        for (track, expected) in [("a", &[][..]), ("b", &["/p/title"][..]), ("b", &[][..])] {
+            last = control::merge(Some(&last), &currently_playing(track));
+            let next = Feedback::new(Some(&last));
+            assert_eq!(names(next.messages("/p", Some(&feedback))), expected, "{}", track);
+            feedback = next;
+        }
+
+        // Without merging, the volume and shuffle state drop out and come
+        // back with the next full payload, which sends them again.
+        let unmerged = Feedback::new(Some(&currently_playing("b")));
+        assert!(names(unmerged.messages("/p", Some(&feedback))).is_empty());
+        let full = Feedback::new(Some(&fixtures::playing("b", 0)));
+        assert_eq!(names(full.messages("/p", Some(&unmerged))), ["/p/volume", "/p/shuffle"]);
+    }
+}
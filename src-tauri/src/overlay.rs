@@ -0,0 +1,252 @@
+// Now-playing files for streaming software, such as OBS "read from file" text
+// sources and image sources.
+//
+// Every playback payload updates a set of text files rendered from
+// templates, a JSON file with the same details and a copy of the cover
+// image, all in the configured directory. Files are only rewritten when
+// their content changes, and always through a temporary file and a rename so
+// readers never see a half-written file. While nothing is playing the text
+// files are empty, the JSON is `null` and the cover is removed. Covers come
+// from the artwork cache when the app has one.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tauri::Manager;
+
+use crate::artwork::{check_url, ArtworkCache};
+use crate::spotify::{PlaybackState, Track};
+
+const JSON_FILE: &str = "now-playing.json";
+const COVER_FILE: &str = "cover.jpg";
+
+fn default_files() -> BTreeMap<String, String> {
+    BTreeMap::from([("now-playing.txt".to_string(), "{artist} — {title}".to_string())])
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OverlayConfig {
+    pub directory: PathBuf,
+    // File name to template. Templates can use `{title}`, `{artist}`,
+    // `{album}`, `{progress}` and `{duration}`.
+    #[serde(default = "default_files")]
+    pub files: BTreeMap<String, String>,
+    #[serde(default = "default_true")]
+    pub json: bool,
+    #[serde(default = "default_true")]
+    pub cover: bool,
+}
+
+// A cover to download for the track that just started.
+pub struct Cover {
+    track_uri: String,
+    url: String,
+}
+
+pub struct Overlay {
+    config: OverlayConfig,
+    // URI of the track whose cover was last requested.
+    track: Mutex<Option<String>>,
+    // What was last written to each file.
+    written: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+fn format_time(ms: u32) -> String {
+    let seconds = ms / 1000;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn render(template: &str, track: &Track, progress_ms: u32) -> String {
+    template
+        .replace("{title}", &track.name)
+        .replace("{artist}", &track.artist_names())
+        .replace("{album}", &track.album.name)
+        .replace("{progress}", &format_time(progress_ms))
+        .replace("{duration}", &format_time(track.duration_ms))
+}
+
+// Template files go straight into the directory, so their names must not
+// lead anywhere else.
+fn is_plain_file_name(name: &str) -> bool {
+    if name.contains(['/', '\\']) {
+        return false;
+    }
+    let mut components = Path::new(name).components();
+    matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none()
+}
+
+// Replace `path` in one step, so it either has the old or the new content.
+fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, bytes)?;
+    std::fs::rename(&tmp, path)
+}
+
+impl Overlay {
+    pub fn new(config: OverlayConfig) -> Result<Self, String> {
+        if let Some(name) = config.files.keys().find(|name| !is_plain_file_name(name)) {
+            return Err(format!("Overlay file '{}' must be a plain file name.", name));
+        }
+        std::fs::create_dir_all(&config.directory)
+            .map_err(|e| format!("Failed to create {}: {}", config.directory.display(), e))?;
+        Ok(Overlay {
+            config,
+            track: Mutex::new(None),
+            written: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn write(&self, name: &str, bytes: Vec<u8>) {
+        let mut written = self.written.lock().unwrap();
+        if written.get(name) == Some(&bytes) {
+            return;
+        }
+        match write_atomic(&self.config.directory.join(name), &bytes) {
+            Ok(()) => {
+                written.insert(name.to_string(), bytes);
+            }
+            Err(e) => log::error!("Failed to write {}: {}", name, e),
+        }
+    }
+
+    fn remove_cover(&self) {
+        self.written.lock().unwrap().remove(COVER_FILE);
+        match std::fs::remove_file(self.config.directory.join(COVER_FILE)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => log::error!("Failed to remove cover: {}", e),
+            _ => {}
+        }
+    }
+
+    /// Write the files for `state`, or clear them if nothing is playing.
+    /// Returns the cover to pass to `save_cover` when the track changed.
+    pub fn update(&self, state: Option<&PlaybackState>) -> Option<Cover> {
+        let playing = state.and_then(|state| state.item.as_ref().map(|track| (state, track)));
+
+        for (name, template) in &self.config.files {
+            let text = match playing {
+                Some((state, track)) => render(template, track, state.progress_ms.unwrap_or(0)),
+                None => String::new(),
+            };
+            self.write(name, text.into_bytes());
+        }
+
+        if self.config.json {
+            let value = playing.map(|(state, track)| {
+                json!({
+                    "title": track.name,
+                    "artist": track.artist_names(),
+                    "artists": track.artists,
+                    "album": track.album.name,
+                    "uri": track.uri,
+                    "is_playing": state.is_playing,
+                    "progress_ms": state.progress_ms.unwrap_or(0),
+                    "duration_ms": track.duration_ms,
+                    "image": track.album.images.first().map(|image| &image.url),
+                })
+            });
+            self.write(JSON_FILE, serde_json::to_vec_pretty(&value).unwrap_or_default());
+        }
+
+        if !self.config.cover {
+            return None;
+        }
+        let uri = playing.map(|(_, track)| track.uri.clone());
+        {
+            let mut track = self.track.lock().unwrap();
+            if *track == uri {
+                return None;
+            }
+            track.clone_from(&uri);
+        }
+        let image = playing.and_then(|(_, track)| track.album.images.first().map(|image| image.url.clone()));
+        let (Some(track_uri), Some(url)) = (uri, image) else {
+            self.remove_cover();
+            return None;
+        };
+        Some(Cover { track_uri, url })
+    }
+
+    /// Download `cover`, through `artwork` if given, and write it unless the
+    /// track changed in the meantime.
+    pub async fn save_cover(&self, cover: Cover, artwork: Option<&ArtworkCache>) {
+        let bytes = match artwork {
+            Some(artwork) => artwork.fetch(&cover.url).await.map(|artwork| artwork.bytes),
+            None => download(&cover.url).await,
+        };
+        match bytes {
+            // Skip covers that arrive after the track changed again.
+            Ok(bytes) if self.track.lock().unwrap().as_deref() == Some(cover.track_uri.as_str()) => {
+                self.write(COVER_FILE, bytes)
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("No cover for the overlay: {}", e),
+        }
+    }
+}
+
+async fn download(url: &str) -> Result<Vec<u8>, String> {
+    check_url(url)?;
+    let resp = reqwest::get(url)
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(|e| format!("Failed to download artwork: {:?}", e))?;
+    resp.bytes()
+        .await
+        .map(|bytes| bytes.to_vec())
+        .map_err(|e| format!("Failed to download artwork: {:?}", e))
+}
+
+// Update the overlay files, if configured, from a playback payload.
+pub fn observe(app: &tauri::AppHandle, state: Option<&PlaybackState>) {
+    let Some(overlay) = app.try_state::<Arc<Overlay>>() else {
+        return;
+    };
+    if let Some(cover) = overlay.update(state) {
+        let (app, overlay) = (app.clone(), overlay.inner().clone());
+        tauri::async_runtime::spawn(async move {
+            overlay
+                .save_cover(cover, app.try_state::<ArtworkCache>().as_deref())
+                .await
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names_stay_in_the_directory() {
+        for name in ["now-playing.txt", "a..b.txt", ".hidden"] {
+            assert!(is_plain_file_name(name), "{}", name);
+        }
+        for name in [
+            "",
+            ".",
+            "..",
+            "../x.txt",
+            "sub/x.txt",
+            "/etc/x",
+            "..\\x.txt",
+            "sub\\x.txt",
+        ] {
+            assert!(!is_plain_file_name(name), "{}", name);
+        }
+
+        let config = OverlayConfig {
+            directory: std::env::temp_dir().join(format!("overlay-{}", std::process::id())),
+            files: BTreeMap::from([("../escape.txt".to_string(), "{title}".to_string())]),
+            json: false,
+            cover: false,
+        };
+        assert!(Overlay::new(config).is_err());
+    }
+}
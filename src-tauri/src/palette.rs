@@ -0,0 +1,374 @@
+// Colour palette extraction from album art, so the UI can tint itself per
+// song without doing image maths in JS.
+//
+// The image is shrunk to a thumbnail and its pixels are grouped into coarse
+// colour buckets. The most common bucket is the dominant colour, and the
+// accents are the most common buckets that are saturated (vibrant) or
+// greyish (muted) enough. Text colours are picked to meet WCAG contrast
+// ratios against the dominant colour.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use image::imageops::FilterType;
+use serde::Serialize;
+use tauri::{command, Manager};
+
+use crate::artwork::{check_url, ArtworkCache};
+
+const THUMBNAIL_SIZE: u32 = 64;
+// Bits kept per channel when bucketing colours.
+const BUCKET_BITS: u8 = 4;
+// WCAG AA for normal text.
+const MIN_TEXT_CONTRAST: f64 = 4.5;
+const MAX_CACHED_PALETTES: usize = 256;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    pub fn hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.0, self.1, self.2)
+    }
+
+    // Hue in degrees, saturation and lightness in 0..=1.
+    fn hsl(self) -> (f64, f64, f64) {
+        let r = f64::from(self.0) / 255.0;
+        let g = f64::from(self.1) / 255.0;
+        let b = f64::from(self.2) / 255.0;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let l = (max + min) / 2.0;
+        let d = max - min;
+        if d == 0.0 {
+            return (0.0, 0.0, l);
+        }
+        let s = d / (1.0 - (2.0 * l - 1.0).abs());
+        let h = if max == r {
+            60.0 * (((g - b) / d).rem_euclid(6.0))
+        } else if max == g {
+            60.0 * ((b - r) / d + 2.0)
+        } else {
+            60.0 * ((r - g) / d + 4.0)
+        };
+        (h, s, l)
+    }
+
+    fn from_hsl(h: f64, s: f64, l: f64) -> Rgb {
+        let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        let x = c * (1.0 - ((h / 60.0).rem_euclid(2.0) - 1.0).abs());
+        let m = l - c / 2.0;
+        let (r, g, b) = match (h / 60.0) as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let channel = |v: f64| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+        Rgb(channel(r), channel(g), channel(b))
+    }
+
+    fn luminance(self) -> f64 {
+        let linear = |c: u8| {
+            let c = f64::from(c) / 255.0;
+            if c <= 0.03928 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        0.2126 * linear(self.0) + 0.7152 * linear(self.1) + 0.0722 * linear(self.2)
+    }
+
+    pub fn contrast(self, other: Rgb) -> f64 {
+        let (a, b) = (self.luminance(), other.luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub dominant: String,
+    pub vibrant: String,
+    pub muted: String,
+    // Body text on the dominant colour: white or near-black.
+    pub text: String,
+    // Accent text on the dominant colour, derived from the vibrant colour.
+    pub text_accent: String,
+}
+
+struct Bucket {
+    count: u32,
+    sum: [u64; 3],
+}
+
+impl Bucket {
+    fn color(&self) -> Rgb {
+        let n = u64::from(self.count);
+        Rgb(
+            (self.sum[0] / n) as u8,
+            (self.sum[1] / n) as u8,
+            (self.sum[2] / n) as u8,
+        )
+    }
+}
+
+// Lighten or darken `color` until it reaches `min` contrast against `background`.
+fn readable_on(color: Rgb, background: Rgb, min: f64) -> Rgb {
+    let (h, s, l) = color.hsl();
+    // Head for whichever of white and black stands out more; on mid tones
+    // only one of them can reach the contrast wanted.
+    let lighten = Rgb(255, 255, 255).contrast(background) >= Rgb(0, 0, 0).contrast(background);
+    let mut candidate = color;
+    for step in 0..=20 {
+        if candidate.contrast(background) >= min {
+            return candidate;
+        }
+        let delta = f64::from(step) / 20.0;
+        let lightness = if lighten { l + (1.0 - l) * delta } else { l * (1.0 - delta) };
+        candidate = Rgb::from_hsl(h, s, lightness);
+    }
+    candidate
+}
+
+/// Compute the palette of an encoded image (JPEG, PNG or WebP).
+pub fn extract(bytes: &[u8]) -> Result<Palette, String> {
+    let image = image::load_from_memory(bytes)
+        .map_err(|e| format!("Failed to decode artwork: {}", e))?
+        .resize(THUMBNAIL_SIZE, THUMBNAIL_SIZE, FilterType::Triangle)
+        .to_rgb8();
+
+    let shift = 8 - BUCKET_BITS;
+    let mut buckets: HashMap<(u8, u8, u8), Bucket> = HashMap::new();
+    for pixel in image.pixels() {
+        let [r, g, b] = pixel.0;
+        let bucket = buckets
+            .entry((r >> shift, g >> shift, b >> shift))
+            .or_insert(Bucket { count: 0, sum: [0; 3] });
+        bucket.count += 1;
+        bucket.sum[0] += u64::from(r);
+        bucket.sum[1] += u64::from(g);
+        bucket.sum[2] += u64::from(b);
+    }
+
+    let mut swatches: Vec<(Rgb, u32)> = buckets.values().map(|b| (b.color(), b.count)).collect();
+    // Most common first; ties broken by colour so the result is deterministic.
+    swatches.sort_by(|a, b| b.1.cmp(&a.1).then((a.0 .0, a.0 .1, a.0 .2).cmp(&(b.0 .0, b.0 .1, b.0 .2))));
+    let dominant = swatches.first().map(|s| s.0).ok_or("Artwork has no pixels.")?;
+
+    // Ignore specks so a few noisy pixels cannot become the accent colour.
+    let total: u32 = swatches.iter().map(|s| s.1).sum();
+    let significant = |count: u32| f64::from(count) / f64::from(total) >= 0.005;
+    let pick = |accept: &dyn Fn(f64, f64) -> bool| {
+        swatches
+            .iter()
+            .filter(|(color, count)| {
+                let (_, s, l) = color.hsl();
+                significant(*count) && accept(s, l)
+            })
+            .max_by(|a, b| {
+                let score = |(color, count): &(Rgb, u32)| {
+                    let (_, s, _) = color.hsl();
+                    f64::from(*count) * (0.5 + s)
+                };
+                score(a).total_cmp(&score(b))
+            })
+            .map(|s| s.0)
+    };
+
+    let (h, s, l) = dominant.hsl();
+    let vibrant = pick(&|s, l| s >= 0.45 && (0.3..=0.75).contains(&l))
+        .unwrap_or_else(|| Rgb::from_hsl(h, s.max(0.6), l.clamp(0.45, 0.6)));
+    let muted = pick(&|s, l| s < 0.45 && (0.2..=0.7).contains(&l))
+        .unwrap_or_else(|| Rgb::from_hsl(h, s.min(0.25), l.clamp(0.3, 0.5)));
+
+    let white = Rgb(255, 255, 255);
+    let black = Rgb(18, 18, 18);
+    let text = if white.contrast(dominant) >= black.contrast(dominant) { white } else { black };
+
+    Ok(Palette {
+        dominant: dominant.hex(),
+        vibrant: vibrant.hex(),
+        muted: muted.hex(),
+        text: text.hex(),
+        text_accent: readable_on(vibrant, dominant, MIN_TEXT_CONTRAST).hex(),
+    })
+}
+
+// Palettes by image URL.
+#[derive(Default)]
+pub struct PaletteCache(Mutex<HashMap<String, Palette>>);
+
+impl PaletteCache {
+    pub fn get(&self, url: &str) -> Option<Palette> {
+        self.0.lock().unwrap().get(url).cloned()
+    }
+
+    // Starts over once full; palettes are cheap to compute again.
+    pub fn insert(&self, url: &str, palette: Palette) {
+        let mut palettes = self.0.lock().unwrap();
+        if palettes.len() >= MAX_CACHED_PALETTES && !palettes.contains_key(url) {
+            palettes.clear();
+        }
+        palettes.insert(url.to_string(), palette);
+    }
+}
+
+/// The palette of the image at `url`, computed once per URL.
+pub async fn for_image(app: &tauri::AppHandle, url: &str) -> Result<Palette, String> {
+    let cache = app.state::<PaletteCache>();
+    if let Some(palette) = cache.get(url) {
+        return Ok(palette);
+    }
+
+    check_url(url)?;
+    let bytes = match app.try_state::<ArtworkCache>() {
+        Some(artwork) => artwork.fetch(url).await?.bytes,
+        None => reqwest::get(url)
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|e| format!("Failed to download artwork: {:?}", e))?
+            .bytes()
+            .await
+            .map_err(|e| format!("Failed to download artwork: {:?}", e))?
+            .to_vec(),
+    };
+    let palette = tauri::async_runtime::spawn_blocking(move || extract(&bytes))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    cache.insert(url, palette.clone());
+    Ok(palette)
+}
+
+#[command]
+pub async fn get_palette(app: tauri::AppHandle, url: String) -> Result<Palette, String> {
+    for_image(&app, &url).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageFormat, RgbImage};
+
+    use super::*;
+
+    // A PNG filled with `regions`, each covering `share` of the rows.
+    fn png(regions: &[(Rgb, f64)]) -> Vec<u8> {
+        let size = 100;
+        let mut rows = Vec::new();
+        for (color, share) in regions {
+            rows.extend(std::iter::repeat(*color).take((share * f64::from(size)).round() as usize));
+        }
+        let image = RgbImage::from_fn(size, size, |_, y| {
+            let Rgb(r, g, b) = rows[(y as usize).min(rows.len() - 1)];
+            image::Rgb([r, g, b])
+        });
+        let mut bytes = Cursor::new(Vec::new());
+        image.write_to(&mut bytes, ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    fn palette(url: &str) -> Palette {
+        Palette {
+            dominant: url.to_string(),
+            vibrant: String::new(),
+            muted: String::new(),
+            text: String::new(),
+            text_accent: String::new(),
+        }
+    }
+
+    #[test]
+    fn contrast_ratios() {
+        let white = Rgb(255, 255, 255);
+        let black = Rgb(0, 0, 0);
+        assert!((white.contrast(black) - 21.0).abs() < 1e-9);
+        assert!((black.contrast(white) - 21.0).abs() < 1e-9);
+        assert!((white.contrast(white) - 1.0).abs() < 1e-9);
+        // WCAG's own example: #777 on white just misses AA.
+        assert!(Rgb(0x77, 0x77, 0x77).contrast(white) < MIN_TEXT_CONTRAST);
+        assert!(Rgb(0x76, 0x76, 0x76).contrast(white) >= MIN_TEXT_CONTRAST);
+        assert_eq!(Rgb(255, 0, 128).hex(), "#ff0080");
+    }
+
+    #[test]
+    fn picks_vibrant_and_muted_accents() {
+        let navy = Rgb(20, 30, 70);
+        let red = Rgb(220, 40, 40);
+        let grey = Rgb(120, 110, 110);
+        let palette = extract(&png(&[(navy, 0.6), (red, 0.25), (grey, 0.15)])).unwrap();
+        assert_eq!(palette.dominant, navy.hex());
+        assert_eq!(palette.vibrant, red.hex());
+        assert_eq!(palette.muted, grey.hex());
+        assert_eq!(palette.text, "#ffffff");
+    }
+
+    #[test]
+    fn accents_are_derived_when_missing() {
+        // A single pale, greyish colour has no vibrant swatch.
+        let beige = Rgb(200, 190, 170);
+        let palette = extract(&png(&[(beige, 1.0)])).unwrap();
+        assert_eq!(palette.dominant, beige.hex());
+        assert_eq!(palette.text, "#121212");
+        let (_, s, l) = Rgb(200, 190, 170).hsl();
+        assert!(s < 0.45 && l > 0.7);
+        assert_ne!(palette.vibrant, palette.dominant);
+        assert_ne!(palette.muted, palette.dominant);
+
+        // Specks below half a percent are not picked as accents.
+        let palette = extract(&png(&[(Rgb(10, 10, 10), 0.996), (Rgb(0, 255, 0), 0.004)])).unwrap();
+        assert_ne!(palette.vibrant, Rgb(0, 255, 0).hex());
+    }
+
+    #[test]
+    fn accent_text_is_readable() {
+        for (dominant, accent) in [
+            (Rgb(20, 30, 70), Rgb(40, 60, 160)),
+            (Rgb(240, 235, 220), Rgb(250, 200, 60)),
+            (Rgb(128, 128, 128), Rgb(200, 60, 60)),
+        ] {
+            let palette = extract(&png(&[(dominant, 0.7), (accent, 0.3)])).unwrap();
+            let parse = |hex: &str| {
+                let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+                Rgb(channel(1), channel(3), channel(5))
+            };
+            let background = parse(&palette.dominant);
+            assert!(
+                parse(&palette.text).contrast(background) >= MIN_TEXT_CONTRAST,
+                "{:?}",
+                palette
+            );
+            assert!(
+                parse(&palette.text_accent).contrast(background) >= MIN_TEXT_CONTRAST,
+                "{:?}",
+                palette
+            );
+        }
+    }
+
+    #[test]
+    fn undecodable_artwork() {
+        assert!(extract(b"not an image").is_err());
+    }
+
+    #[test]
+    fn cache_starts_over_when_full() {
+        let cache = PaletteCache::default();
+        for i in 0..MAX_CACHED_PALETTES {
+            cache.insert(&i.to_string(), palette(&i.to_string()));
+        }
+        // Replacing an entry does not count as growing.
+        cache.insert("0", palette("zero"));
+        assert_eq!(cache.get("0").unwrap().dominant, "zero");
+        assert!(cache.get("255").is_some());
+
+        cache.insert("new", palette("new"));
+        assert!(cache.get("0").is_none());
+        assert!(cache.get("255").is_none());
+        assert_eq!(cache.get("new").unwrap().dominant, "new");
+    }
+}
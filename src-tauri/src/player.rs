@@ -0,0 +1,131 @@
+// Playback control on top of the Web API helpers in `spotify`, without any
+// Tauri state, so the commands, the outside controls and the `playback-ctl`
+// binary all send the same requests.
+
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::spotify::{self, Device, Page, PlaybackState, Playlist};
+
+#[derive(Deserialize)]
+struct Devices {
+    devices: Vec<Device>,
+}
+
+pub async fn play(access: &str) -> Result<(), String> {
+    spotify::send_empty(Method::PUT, access, "/me/player/play", &json!({})).await
+}
+
+pub async fn pause(access: &str) -> Result<(), String> {
+    spotify::send_empty(Method::PUT, access, "/me/player/pause", &json!({})).await
+}
+
+pub async fn next(access: &str) -> Result<(), String> {
+    spotify::send_empty(Method::POST, access, "/me/player/next", &json!({})).await
+}
+
+pub async fn previous(access: &str) -> Result<(), String> {
+    spotify::send_empty(Method::POST, access, "/me/player/previous", &json!({})).await
+}
+
+/// Seek to `position_ms` in the current track.
+pub async fn seek(access: &str, position_ms: u32) -> Result<(), String> {
+    let path = format!("/me/player/seek?position_ms={}", position_ms);
+    spotify::send_empty(Method::PUT, access, &path, &json!({})).await
+}
+
+/// Set the volume of the active device, in percent.
+pub async fn set_volume(access: &str, volume: u8) -> Result<(), String> {
+    let path = format!("/me/player/volume?volume_percent={}", volume.min(100));
+    spotify::send_empty(Method::PUT, access, &path, &json!({})).await
+}
+
+pub async fn set_shuffle(access: &str, shuffle: bool) -> Result<(), String> {
+    let path = format!("/me/player/shuffle?state={}", shuffle);
+    spotify::send_empty(Method::PUT, access, &path, &json!({})).await
+}
+
+/// Set the repeat mode: `off`, `context` or `track`.
+pub async fn set_repeat(access: &str, mode: &str) -> Result<(), String> {
+    if !matches!(mode, "off" | "context" | "track") {
+        return Err(format!("Unknown repeat mode '{}'.", mode));
+    }
+    let path = format!("/me/player/repeat?state={}", mode);
+    spotify::send_empty(Method::PUT, access, &path, &json!({})).await
+}
+
+/// Save a track to the user's library ("Liked Songs").
+pub async fn save_track(access: &str, id: &str) -> Result<(), String> {
+    let body = json!({ "ids": [id] });
+    let forbidden = "Spotify did not allow saving the track. Sign in again to allow saving tracks.";
+    spotify::send_empty_scoped(Method::PUT, access, "/me/tracks", &body, forbidden).await
+}
+
+/// Start playing a context (album, playlist or artist) by its URI.
+pub async fn play_context(access: &str, context_uri: &str) -> Result<(), String> {
+    let body = json!({ "context_uri": context_uri });
+    spotify::send_empty(Method::PUT, access, "/me/player/play", &body).await
+}
+
+/// The current playback, or `None` if no device is active.
+pub async fn playback_state(access: &str) -> Result<Option<PlaybackState>, String> {
+    spotify::get_optional(access, "/me/player").await
+}
+
+pub async fn devices(access: &str) -> Result<Vec<Device>, String> {
+    let devices: Devices = spotify::get(access, "/me/player/devices").await?;
+    Ok(devices.devices)
+}
+
+/// The device with ID or name `query`, ignoring case in names.
+pub async fn find_device(access: &str, query: &str) -> Result<Device, String> {
+    let devices = devices(access).await?;
+    devices
+        .iter()
+        .find(|device| device.id.as_deref() == Some(query))
+        .or_else(|| devices.iter().find(|device| device.name == query))
+        .or_else(|| devices.iter().find(|device| device.name.eq_ignore_ascii_case(query)))
+        .cloned()
+        .ok_or(format!("No device named '{}'.", query))
+}
+
+/// Move playback to `device_id`, starting it there if `play` is set and
+/// otherwise keeping it playing or paused as it was.
+pub async fn transfer(access: &str, device_id: &str, play: bool) -> Result<(), String> {
+    let mut body = json!({ "device_ids": [device_id] });
+    if play {
+        body["play"] = json!(true);
+    }
+    spotify::send_empty(Method::PUT, access, "/me/player", &body).await
+}
+
+/// The ID of the playlist `query` names: an ID or URI as is, otherwise one
+/// of the user's playlists with that name.
+pub async fn find_playlist(access: &str, query: &str) -> Result<String, String> {
+    if let Some(id) = query.strip_prefix("spotify:playlist:") {
+        return Ok(id.to_string());
+    }
+    let playlists = playlists(access).await?;
+    playlists
+        .iter()
+        .find(|playlist| playlist.id == query)
+        .or_else(|| playlists.iter().find(|playlist| playlist.name == query))
+        .or_else(|| playlists.iter().find(|playlist| playlist.name.eq_ignore_ascii_case(query)))
+        .map(|playlist| playlist.id.clone())
+        // Playlists the user does not follow can still be played by ID.
+        .or_else(|| (query.len() == 22 && query.chars().all(|c| c.is_ascii_alphanumeric())).then(|| query.to_string()))
+        .ok_or(format!("No playlist named '{}'.", query))
+}
+
+/// Every playlist the user owns or follows.
+pub async fn playlists(access: &str) -> Result<Vec<Playlist>, String> {
+    let mut playlists = Vec::new();
+    let mut next = Some("/me/playlists?limit=50".to_string());
+    while let Some(path) = next {
+        let page: Page<Playlist> = spotify::get(access, &path).await?;
+        playlists.extend(page.items);
+        next = page.next;
+    }
+    Ok(playlists)
+}
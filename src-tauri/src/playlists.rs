@@ -0,0 +1,137 @@
+use reqwest::Method;
+use serde_json::json;
+use tauri::command;
+
+use crate::backend_log;
+use crate::spotify::{self, Playlist, Snapshot, User};
+
+#[command]
+pub async fn create_playlist(
+    app: tauri::AppHandle,
+    access: String,
+    name: String,
+    description: Option<String>,
+    public: bool,
+    collaborative: bool,
+) -> Result<Playlist, String> {
+    // Spotify rejects collaborative playlists that are also public.
+    if collaborative && public {
+        return Err("A collaborative playlist cannot be public.".to_string());
+    }
+
+    let me: User = spotify::get(&access, "/me").await?;
+    let playlist: Playlist = spotify::send(
+        Method::POST,
+        &access,
+        &format!("/users/{}/playlists", me.id),
+        &json!({
+            "name": name,
+            "description": description.unwrap_or_default(),
+            "public": public,
+            "collaborative": collaborative,
+        }),
+    )
+    .await?;
+
+    backend_log(&app, format!("Created playlist '{}' ({}).", playlist.name, playlist.id));
+    Ok(playlist)
+}
+
+#[command]
+pub async fn update_playlist_details(
+    app: tauri::AppHandle,
+    access: String,
+    id: String,
+    name: Option<String>,
+    description: Option<String>,
+    public: Option<bool>,
+    collaborative: Option<bool>,
+) -> Result<(), String> {
+    if collaborative == Some(true) && public == Some(true) {
+        return Err("A collaborative playlist cannot be public.".to_string());
+    }
+
+    // Only send the fields the caller wants to change.
+    let mut details = serde_json::Map::new();
+    if let Some(name) = name {
+        details.insert("name".into(), json!(name));
+    }
+    if let Some(description) = description {
+        details.insert("description".into(), json!(description));
+    }
+    if let Some(public) = public {
+        details.insert("public".into(), json!(public));
+    }
+    if let Some(collaborative) = collaborative {
+        details.insert("collaborative".into(), json!(collaborative));
+    }
+    if details.is_empty() {
+        return Err("No playlist details to update.".to_string());
+    }
+
+    spotify::send_empty(
+        Method::PUT,
+        &access,
+        &format!("/playlists/{}", id),
+        &serde_json::Value::Object(details),
+    )
+    .await?;
+
+    backend_log(&app, format!("Updated details of playlist {}.", id));
+    Ok(())
+}
+
+// Moves `range_length` items starting at `range_start` so they end up before
+// the item currently at `insert_before`. Returns the new snapshot ID.
+#[command]
+pub async fn reorder_playlist_items(
+    app: tauri::AppHandle,
+    access: String,
+    id: String,
+    range_start: u32,
+    insert_before: u32,
+    range_length: Option<u32>,
+    snapshot_id: Option<String>,
+) -> Result<String, String> {
+    let mut body = json!({
+        "range_start": range_start,
+        "insert_before": insert_before,
+        "range_length": range_length.unwrap_or(1),
+    });
+    if let Some(snapshot_id) = snapshot_id {
+        body["snapshot_id"] = json!(snapshot_id);
+    }
+
+    let snapshot: Snapshot =
+        spotify::send(Method::PUT, &access, &format!("/playlists/{}/tracks", id), &body).await?;
+
+    backend_log(&app, format!("Reordered items in playlist {}.", id));
+    Ok(snapshot.snapshot_id)
+}
+
+// Removes every occurrence of the given track/episode URIs. Returns the new
+// snapshot ID.
+#[command]
+pub async fn remove_playlist_items(
+    app: tauri::AppHandle,
+    access: String,
+    id: String,
+    uris: Vec<String>,
+    snapshot_id: Option<String>,
+) -> Result<String, String> {
+    if uris.is_empty() {
+        return Err("No playlist items to remove.".to_string());
+    }
+
+    let tracks: Vec<_> = uris.iter().map(|uri| json!({ "uri": uri })).collect();
+    let mut body = json!({ "tracks": tracks });
+    if let Some(snapshot_id) = snapshot_id {
+        body["snapshot_id"] = json!(snapshot_id);
+    }
+
+    let snapshot: Snapshot =
+        spotify::send(Method::DELETE, &access, &format!("/playlists/{}/tracks", id), &body).await?;
+
+    backend_log(&app, format!("Removed {} item(s) from playlist {}.", uris.len(), id));
+    Ok(snapshot.snapshot_id)
+}
@@ -0,0 +1,681 @@
+// Scrobbling plays to Last.fm-compatible APIs (Last.fm itself, Maloja,
+// Libre.fm, ...) and to ListenBrainz.
+//
+// Every playback payload the backend fetches is passed to `observe`. A new
+// track sends a "now playing" update, and once it has played for half its
+// length or 4 minutes, whichever comes first, it is queued for scrobbling.
+// Each service keeps its queue on disk until the server accepts the
+// scrobbles, so plays made while offline are sent later. Scrobbles the server
+// refuses outright are moved to a separate file next to the queue, so one bad
+// scrobble cannot hold up the rest.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use md5::{Digest, Md5};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use tauri::Manager;
+
+use crate::backend_log;
+use crate::history::now_ms;
+use crate::listenbrainz::ListenBrainz;
+use crate::spotify::PlaybackState;
+
+// Last.fm ignores tracks shorter than this.
+const MIN_TRACK_MS: u32 = 30_000;
+// Last.fm error codes for "service offline", "temporarily unavailable" and
+// "rate limit exceeded", which it can send with a 200 status.
+const LASTFM_RETRY_CODES: [i64; 3] = [11, 16, 29];
+const MAX_THRESHOLD_MS: u32 = 4 * 60 * 1000;
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+fn default_api_url() -> String {
+    "https://ws.audioscrobbler.com/2.0/".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScrobblerConfig {
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+    pub api_key: String,
+    pub api_secret: String,
+    // Obtained once through the `auth.getMobileSession` or web auth flow.
+    pub session_key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Scrobble {
+    // First artist only, which is what Last.fm expects.
+    pub artist: String,
+    pub artists: Vec<String>,
+    pub track: String,
+    pub album: String,
+    #[serde(default)]
+    pub track_id: Option<String>,
+    pub duration_ms: u32,
+    // When the track started playing, in Unix seconds.
+    pub timestamp: i64,
+}
+
+/// Why scrobbles were not accepted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubmitError {
+    // The service could not be reached or could not take them right now;
+    // they stay queued.
+    Retry(String),
+    // The service refused them and would refuse them again.
+    Rejected(String),
+}
+
+impl SubmitError {
+    /// Server errors and rate limiting are worth retrying, other client
+    /// errors are not.
+    pub fn from_status(status: StatusCode, message: String) -> Self {
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            SubmitError::Retry(message)
+        } else {
+            SubmitError::Rejected(message)
+        }
+    }
+}
+
+impl std::fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubmitError::Retry(message) | SubmitError::Rejected(message) => f.write_str(message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Submission {
+    NowPlaying(Scrobble),
+    Scrobble(Scrobble),
+}
+
+// How far into a track it has to be played to be scrobbled.
+fn threshold_ms(duration_ms: u32) -> u32 {
+    (duration_ms / 2).min(MAX_THRESHOLD_MS)
+}
+
+// The track currently playing and whether it has been queued already.
+struct Candidate {
+    track_uri: String,
+    scrobble: Scrobble,
+    progress_ms: u32,
+    queued: bool,
+}
+
+#[derive(Default)]
+pub struct Tracker {
+    current: Mutex<Option<Candidate>>,
+}
+
+impl Tracker {
+    /// Work out what a playback payload means for scrobbling.
+    pub fn update(&self, state: &PlaybackState, now: i64) -> Vec<Submission> {
+        let Some(track) = &state.item else {
+            return Vec::new();
+        };
+        let progress_ms = state.progress_ms.unwrap_or(0);
+        let mut submissions = Vec::new();
+        let mut current = self.current.lock().unwrap();
+
+        // A new track, or the same one started over after it was scrobbled.
+        let restarted = match current.as_ref() {
+            Some(c) if c.track_uri == track.uri => c.queued && progress_ms + MIN_TRACK_MS < c.progress_ms,
+            _ => true,
+        };
+        if restarted {
+            let artists: Vec<String> = track.artists.iter().map(|a| a.name.clone()).collect();
+            let scrobble = Scrobble {
+                artist: artists.first().cloned().unwrap_or_default(),
+                artists,
+                track: track.name.clone(),
+                album: track.album.name.clone(),
+                track_id: track.id.clone(),
+                duration_ms: track.duration_ms,
+                timestamp: (now - progress_ms as i64) / 1000,
+            };
+            submissions.push(Submission::NowPlaying(scrobble.clone()));
+            *current = Some(Candidate {
+                track_uri: track.uri.clone(),
+                scrobble,
+                progress_ms,
+                queued: false,
+            });
+        }
+
+        let candidate = current.as_mut().unwrap();
+        candidate.progress_ms = progress_ms;
+        if !candidate.queued
+            && !candidate.scrobble.artist.is_empty()
+            && track.duration_ms > MIN_TRACK_MS
+            && progress_ms >= threshold_ms(track.duration_ms)
+        {
+            candidate.queued = true;
+            submissions.push(Submission::Scrobble(candidate.scrobble.clone()));
+        }
+        submissions
+    }
+}
+
+pub struct LastFm {
+    config: ScrobblerConfig,
+    client: Client,
+}
+
+impl LastFm {
+    pub fn new(config: ScrobblerConfig) -> Self {
+        LastFm {
+            config,
+            client: Client::new(),
+        }
+    }
+
+    fn sign(&self, params: &BTreeMap<String, String>) -> String {
+        let mut hasher = Md5::new();
+        for (key, value) in params {
+            hasher.update(key.as_bytes());
+            hasher.update(value.as_bytes());
+        }
+        hasher.update(self.config.api_secret.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    async fn call(&self, method: &str, mut params: BTreeMap<String, String>) -> Result<serde_json::Value, SubmitError> {
+        params.insert("method".into(), method.into());
+        params.insert("api_key".into(), self.config.api_key.clone());
+        params.insert("sk".into(), self.config.session_key.clone());
+        let signature = self.sign(&params);
+        params.insert("api_sig".into(), signature);
+        params.insert("format".into(), "json".into());
+
+        let resp = self
+            .client
+            .post(&self.config.api_url)
+            .form(&params)
+            .send()
+            .await
+            .map_err(|e| SubmitError::Retry(format!("Failed to reach scrobbler: {:?}", e)))?;
+        let status = resp.status();
+        let body: serde_json::Value = resp.json().await.unwrap_or_default();
+
+        // Last.fm reports some errors with a 200 status.
+        if !status.is_success() || body.get("error").is_some() {
+            let message = format!(
+                "Scrobbler error ({}): {}",
+                status,
+                body["message"].as_str().unwrap_or("Unknown error")
+            );
+            return Err(match body["error"].as_i64() {
+                Some(code) if LASTFM_RETRY_CODES.contains(&code) => SubmitError::Retry(message),
+                _ => SubmitError::from_status(status, message),
+            });
+        }
+        Ok(body)
+    }
+
+    pub async fn now_playing(&self, scrobble: &Scrobble) -> Result<(), String> {
+        let mut params = BTreeMap::new();
+        params.insert("artist".to_string(), scrobble.artist.clone());
+        params.insert("track".to_string(), scrobble.track.clone());
+        if !scrobble.album.is_empty() {
+            params.insert("album".to_string(), scrobble.album.clone());
+        }
+        params.insert("duration".to_string(), (scrobble.duration_ms / 1000).to_string());
+        self.call("track.updateNowPlaying", params)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    pub async fn scrobble(&self, batch: &[Scrobble]) -> Result<(), SubmitError> {
+        let mut params = BTreeMap::new();
+        for (i, scrobble) in batch.iter().enumerate() {
+            params.insert(format!("artist[{}]", i), scrobble.artist.clone());
+            params.insert(format!("track[{}]", i), scrobble.track.clone());
+            if !scrobble.album.is_empty() {
+                params.insert(format!("album[{}]", i), scrobble.album.clone());
+            }
+            params.insert(format!("duration[{}]", i), (scrobble.duration_ms / 1000).to_string());
+            params.insert(format!("timestamp[{}]", i), scrobble.timestamp.to_string());
+        }
+        let body = self.call("track.scrobble", params).await?;
+        // Scrobbles Last.fm filters out (too old, unknown artist, ...) are
+        // accepted but dropped, so there is nothing to retry.
+        let ignored = &body["scrobbles"]["@attr"]["ignored"];
+        let ignored = ignored.as_u64().or_else(|| ignored.as_str()?.parse().ok()).unwrap_or(0);
+        if ignored > 0 {
+            log::warn!("The scrobbler ignored {} of {} scrobble(s).", ignored, batch.len());
+        }
+        Ok(())
+    }
+}
+
+pub enum Api {
+    LastFm(LastFm),
+    ListenBrainz(ListenBrainz),
+}
+
+impl Api {
+    async fn now_playing(&self, scrobble: &Scrobble) -> Result<(), String> {
+        match self {
+            Api::LastFm(api) => api.now_playing(scrobble).await,
+            Api::ListenBrainz(api) => api.playing_now(scrobble).await,
+        }
+    }
+
+    async fn scrobble(&self, batch: &[Scrobble]) -> Result<(), SubmitError> {
+        match self {
+            Api::LastFm(api) => api.scrobble(batch).await,
+            Api::ListenBrainz(api) => api.submit(batch).await,
+        }
+    }
+
+    // The most scrobbles the service accepts in one request.
+    fn max_batch(&self) -> usize {
+        match self {
+            Api::LastFm(_) => 50,
+            Api::ListenBrainz(_) => crate::listenbrainz::MAX_LISTENS_PER_REQUEST,
+        }
+    }
+}
+
+// One scrobbling service with its own progress tracking and retry queue.
+pub struct Scrobbler {
+    pub name: &'static str,
+    pub api: Api,
+    tracker: Tracker,
+    queue_path: PathBuf,
+    queue: Mutex<Vec<Scrobble>>,
+}
+
+impl Scrobbler {
+    pub fn new(name: &'static str, api: Api, queue_path: PathBuf) -> Self {
+        // A missing or unreadable queue just starts out empty.
+        let queue = std::fs::read_to_string(&queue_path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+
+        Scrobbler {
+            name,
+            api,
+            tracker: Tracker::default(),
+            queue_path,
+            queue: Mutex::new(queue),
+        }
+    }
+
+    pub fn update(&self, state: &PlaybackState, now: i64) -> Vec<Submission> {
+        self.tracker.update(state, now)
+    }
+
+    pub async fn now_playing(&self, scrobble: &Scrobble) -> Result<(), String> {
+        self.api.now_playing(scrobble).await
+    }
+
+    fn rejected_path(&self) -> PathBuf {
+        self.queue_path.with_extension("rejected.json")
+    }
+
+    // Set aside scrobbles the service refused, so they are kept for the
+    // user to look at without blocking the queue.
+    fn reject(&self, batch: &[Scrobble], error: &str) {
+        let path = self.rejected_path();
+        log::warn!(
+            "{} rejected {} scrobble(s), moved to {}: {}",
+            self.name,
+            batch.len(),
+            path.display(),
+            error
+        );
+        let mut rejected: Vec<Scrobble> = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        rejected.extend_from_slice(batch);
+        let result = serde_json::to_vec(&rejected)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| std::fs::write(&path, bytes).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            log::error!("Failed to save rejected {} scrobbles: {}", self.name, e);
+        }
+    }
+
+    fn save_queue(&self, queue: &[Scrobble]) {
+        let tmp = self.queue_path.with_extension("tmp");
+        let result = serde_json::to_vec(queue)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| std::fs::write(&tmp, bytes).map_err(|e| e.to_string()))
+            .and_then(|_| std::fs::rename(&tmp, &self.queue_path).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            log::error!("Failed to save {} queue: {}", self.name, e);
+        }
+    }
+
+    pub fn enqueue(&self, scrobble: Scrobble) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.push(scrobble);
+        self.save_queue(&queue);
+    }
+
+    pub fn pending(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    /// Send everything in the queue. Stops at the first batch that could
+    /// not be delivered, which stays queued for the next attempt; refused
+    /// scrobbles are set aside. Returns how many were accepted.
+    pub async fn flush(&self) -> Result<usize, String> {
+        let mut sent = 0;
+        // After a batch is refused, its scrobbles are sent one at a time so
+        // that only the bad ones are set aside.
+        let mut one_by_one = 0;
+        loop {
+            // Take the batch out of the queue while it is in flight so that
+            // concurrent flushes never send the same scrobble twice.
+            let batch: Vec<Scrobble> = {
+                let mut queue = self.queue.lock().unwrap();
+                let limit = if one_by_one > 0 { 1 } else { self.api.max_batch() };
+                let n = queue.len().min(limit);
+                queue.drain(..n).collect()
+            };
+            if batch.is_empty() {
+                return Ok(sent);
+            }
+
+            let result = self.api.scrobble(&batch).await;
+            let mut queue = self.queue.lock().unwrap();
+            match result {
+                Ok(()) => sent += batch.len(),
+                Err(SubmitError::Retry(e)) => {
+                    queue.splice(0..0, batch);
+                    return Err(e);
+                }
+                Err(SubmitError::Rejected(_)) if batch.len() > 1 => {
+                    one_by_one = batch.len();
+                    queue.splice(0..0, batch);
+                    continue;
+                }
+                Err(SubmitError::Rejected(e)) => self.reject(&batch, &e),
+            }
+            one_by_one = one_by_one.saturating_sub(batch.len());
+            self.save_queue(&queue);
+        }
+    }
+}
+
+// The configured scrobbling services.
+#[derive(Default)]
+pub struct Scrobblers(pub Vec<Scrobbler>);
+
+impl Scrobblers {
+    /// Feed a playback payload to every scrobbler. Whatever that produces is
+    /// sent in the background, and the outcome passed to `report`.
+    pub fn observe(self: &Arc<Self>, state: &PlaybackState, report: impl Fn(String) + Clone + Send + 'static) {
+        let now = now_ms();
+        for (index, scrobbler) in self.0.iter().enumerate() {
+            for submission in scrobbler.update(state, now) {
+                let scrobblers = self.clone();
+                let report = report.clone();
+                tauri::async_runtime::spawn(async move {
+                    let scrobbler = &scrobblers.0[index];
+                    let result = match &submission {
+                        Submission::NowPlaying(scrobble) => scrobbler.now_playing(scrobble).await,
+                        Submission::Scrobble(scrobble) => {
+                            scrobbler.enqueue(scrobble.clone());
+                            scrobbler.flush().await.map(|_| ())
+                        }
+                    };
+                    match (result, submission) {
+                        (Ok(()), Submission::Scrobble(s)) => {
+                            report(format!("Scrobbled {} - {} to {}.", s.artist, s.track, scrobbler.name))
+                        }
+                        (Ok(()), Submission::NowPlaying(_)) => {}
+                        (Err(e), _) => report(format!("{} ({} {} scrobble(s) queued)", e, scrobbler.pending(), scrobbler.name)),
+                    }
+                });
+            }
+        }
+    }
+
+    /// Try once to send whatever is left in the queues, passing successes to
+    /// `report`.
+    pub async fn retry(&self, report: impl Fn(String)) {
+        for scrobbler in &self.0 {
+            if scrobbler.pending() == 0 {
+                continue;
+            }
+            match scrobbler.flush().await {
+                Ok(sent) => report(format!("Sent {} queued scrobble(s) to {}.", sent, scrobbler.name)),
+                Err(e) => log::warn!("Failed to send queued scrobbles to {}: {}", scrobbler.name, e),
+            }
+        }
+    }
+}
+
+// Feed a playback payload to every configured scrobbler.
+pub fn observe(app: &tauri::AppHandle, state: &PlaybackState) {
+    let Some(scrobblers) = app.try_state::<Arc<Scrobblers>>() else {
+        return;
+    };
+    let app = app.clone();
+    scrobblers.observe(state, move |message| backend_log(&app, message));
+}
+
+// Periodically retry whatever is left in the queues.
+pub async fn retry_queue(app: tauri::AppHandle) {
+    loop {
+        tokio::time::sleep(RETRY_INTERVAL).await;
+        app.state::<Arc<Scrobblers>>()
+            .retry(|message| backend_log(&app, message))
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify::fixtures::{mock_server, playing, temp_path};
+
+    fn scrobbled(submissions: &[Submission]) -> Vec<&Scrobble> {
+        submissions
+            .iter()
+            .filter_map(|submission| match submission {
+                Submission::Scrobble(scrobble) => Some(scrobble),
+                Submission::NowPlaying(_) => None,
+            })
+            .collect()
+    }
+
+    fn scrobble(track: &str, timestamp: i64) -> Scrobble {
+        Scrobble {
+            artist: "One".to_string(),
+            artists: vec!["One".to_string(), "Two".to_string()],
+            track: track.to_string(),
+            album: "Album".to_string(),
+            track_id: Some("abc".to_string()),
+            duration_ms: 200_000,
+            timestamp,
+        }
+    }
+
+    fn last_fm(url: &str) -> LastFm {
+        LastFm::new(ScrobblerConfig {
+            api_url: url.to_string(),
+            api_key: "key".to_string(),
+            api_secret: "secret".to_string(),
+            session_key: "session".to_string(),
+        })
+    }
+
+    #[test]
+    fn scrobbles_after_half_the_track() {
+        let tracker = Tracker::default();
+        let start = 1_700_000_000_000;
+        let first = tracker.update(&playing("a", 0), start);
+        assert!(matches!(&first[..], [Submission::NowPlaying(scrobble)] if scrobble.track == "Title a"));
+
+        assert!(scrobbled(&tracker.update(&playing("a", 99_999), start + 99_999)).is_empty());
+        let submissions = tracker.update(&playing("a", 100_000), start + 100_000);
+        let scrobbles = scrobbled(&submissions);
+        assert_eq!(scrobbles.len(), 1);
+        assert_eq!(scrobbles[0].artist, "One");
+        assert_eq!(scrobbles[0].artists, ["One", "Two"]);
+        assert_eq!(scrobbles[0].timestamp, start / 1000);
+
+        // Only once per play.
+        assert!(tracker.update(&playing("a", 150_000), start + 150_000).is_empty());
+    }
+
+    #[test]
+    fn long_tracks_scrobble_after_four_minutes() {
+        let tracker = Tracker::default();
+        let mut state = playing("long", 0);
+        state.item.as_mut().unwrap().duration_ms = 600_000;
+        tracker.update(&state, 0);
+
+        state.progress_ms = Some(239_999);
+        assert!(scrobbled(&tracker.update(&state, 239_999)).is_empty());
+        state.progress_ms = Some(240_000);
+        assert_eq!(scrobbled(&tracker.update(&state, 240_000)).len(), 1);
+    }
+
+    #[test]
+    fn short_tracks_never_scrobble() {
+        let tracker = Tracker::default();
+        let mut state = playing("short", 0);
+        state.item.as_mut().unwrap().duration_ms = MIN_TRACK_MS;
+        tracker.update(&state, 0);
+        state.progress_ms = Some(MIN_TRACK_MS);
+        assert!(scrobbled(&tracker.update(&state, 30_000)).is_empty());
+    }
+
+    #[test]
+    fn replays_and_restarts() {
+        let tracker = Tracker::default();
+        tracker.update(&playing("a", 0), 0);
+        assert_eq!(scrobbled(&tracker.update(&playing("a", 120_000), 120_000)).len(), 1);
+
+        // Seeking back a little is not a new play.
+        assert!(tracker.update(&playing("a", 100_000), 130_000).is_empty());
+
+        // Starting over after the scrobble is, and counts again.
+        let submissions = tracker.update(&playing("a", 0), 300_000);
+        assert!(matches!(&submissions[..], [Submission::NowPlaying(_)]));
+        let submissions = tracker.update(&playing("a", 100_000), 400_000);
+        let scrobbles = scrobbled(&submissions);
+        assert_eq!(scrobbles.len(), 1);
+        assert_eq!(scrobbles[0].timestamp, 300);
+
+        // Going back to the start before the scrobble is the same play.
+        tracker.update(&playing("b", 50_000), 500_000);
+        assert!(tracker.update(&playing("b", 0), 550_000).is_empty());
+
+        // Another track is a new play even without a scrobble.
+        let submissions = tracker.update(&playing("c", 0), 600_000);
+        assert!(matches!(&submissions[..], [Submission::NowPlaying(scrobble)] if scrobble.track == "Title c"));
+    }
+
+    #[tokio::test]
+    async fn last_fm_signs_and_batches() {
+        let server = mock_server(|_| (StatusCode::OK, serde_json::json!({ "scrobbles": {} }))).await;
+        let scrobbler = Scrobbler::new(
+            "Last.fm",
+            Api::LastFm(last_fm(&server.url)),
+            temp_path("lastfm-batches.json"),
+        );
+        for i in 0..60 {
+            scrobbler.enqueue(scrobble(&format!("Song {}", i), 1_700_000_000 + i));
+        }
+        assert_eq!(scrobbler.flush().await.unwrap(), 60);
+        assert_eq!(scrobbler.pending(), 0);
+        std::fs::remove_file(temp_path("lastfm-batches.json")).unwrap();
+
+        let received = server.received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        for (request, size) in received.iter().zip([50, 10]) {
+            let mut form = request.form();
+            assert_eq!(form["method"], "track.scrobble");
+            assert_eq!(form["api_key"], "key");
+            assert_eq!(form["sk"], "session");
+            assert_eq!(form["format"], "json");
+            assert_eq!(form.keys().filter(|key| key.starts_with("track[")).count(), size);
+
+            // The signature covers every parameter but itself and `format`.
+            let signature = form.remove("api_sig").unwrap();
+            form.remove("format");
+            let mut hasher = Md5::new();
+            for (key, value) in &form {
+                hasher.update(format!("{}{}", key, value));
+            }
+            hasher.update("secret");
+            assert_eq!(signature, format!("{:x}", hasher.finalize()));
+        }
+        let first = received[0].form();
+        assert_eq!(first["artist[0]"], "One");
+        assert_eq!(first["track[0]"], "Song 0");
+        assert_eq!(first["album[0]"], "Album");
+        assert_eq!(first["duration[0]"], "200");
+        assert_eq!(first["timestamp[49]"], "1700000049");
+        assert_eq!(received[1].form()["track[9]"], "Song 59");
+    }
+
+    #[tokio::test]
+    async fn rejected_scrobbles_are_set_aside() {
+        // Last.fm refuses anything with "Bad" in it.
+        let server = mock_server(|request| {
+            if request.form().values().any(|value| value == "Bad") {
+                (
+                    StatusCode::BAD_REQUEST,
+                    serde_json::json!({ "error": 6, "message": "Invalid parameters" }),
+                )
+            } else {
+                (StatusCode::OK, serde_json::json!({ "scrobbles": {} }))
+            }
+        })
+        .await;
+        let queue_path = temp_path("lastfm-rejected.json");
+        let scrobbler = Scrobbler::new("Last.fm", Api::LastFm(last_fm(&server.url)), queue_path.clone());
+        scrobbler.enqueue(scrobble("First", 1_700_000_000));
+        scrobbler.enqueue(scrobble("Bad", 1_700_000_300));
+        scrobbler.enqueue(scrobble("Third", 1_700_000_600));
+
+        assert_eq!(scrobbler.flush().await.unwrap(), 2);
+        assert_eq!(scrobbler.pending(), 0);
+        let rejected_path = queue_path.with_extension("rejected.json");
+        let rejected: Vec<Scrobble> = serde_json::from_str(&std::fs::read_to_string(&rejected_path).unwrap()).unwrap();
+        assert_eq!(rejected, [scrobble("Bad", 1_700_000_300)]);
+        std::fs::remove_file(&queue_path).unwrap();
+        std::fs::remove_file(&rejected_path).unwrap();
+
+        // The whole batch, then each scrobble on its own.
+        assert_eq!(server.received.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn transient_failures_stay_queued() {
+        let server = mock_server(|_| {
+            (
+                StatusCode::OK,
+                serde_json::json!({ "error": 16, "message": "Service temporarily unavailable" }),
+            )
+        })
+        .await;
+        let queue_path = temp_path("lastfm-transient.json");
+        let scrobbler = Scrobbler::new("Last.fm", Api::LastFm(last_fm(&server.url)), queue_path.clone());
+        scrobbler.enqueue(scrobble("First", 1_700_000_000));
+        scrobbler.enqueue(scrobble("Second", 1_700_000_300));
+
+        let error = scrobbler.flush().await.unwrap_err();
+        assert!(error.contains("temporarily unavailable"), "{}", error);
+        assert_eq!(scrobbler.pending(), 2);
+        assert!(!queue_path.with_extension("rejected.json").exists());
+        std::fs::remove_file(&queue_path).unwrap();
+    }
+}
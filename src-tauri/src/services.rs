@@ -0,0 +1,141 @@
+// The services the config turns on, built the same way by the app and by the
+// headless daemon: the scrobblers, the overlay files and the MQTT, OSC and
+// MIDI controllers, along with the credentials they share.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+
+use crate::api;
+use crate::config::Config;
+use crate::control::Action;
+use crate::credentials::{self, Credentials};
+use crate::listenbrainz::ListenBrainz;
+use crate::midi::{self, Midi};
+use crate::mqtt::Mqtt;
+use crate::osc::Osc;
+use crate::overlay::Overlay;
+use crate::scrobble::{self, LastFm, Scrobbler, Scrobblers};
+
+/// Actions from an outside controller, with its name for the log.
+pub type Commands = (&'static str, mpsc::UnboundedReceiver<Action>);
+
+pub struct Services {
+    pub api_enabled: bool,
+    pub credentials: Arc<Credentials>,
+    pub scrobblers: Arc<Scrobblers>,
+    pub overlay: Option<Arc<Overlay>>,
+    pub mqtt: Option<Arc<Mqtt>>,
+    pub osc: Option<Arc<Osc>>,
+    pub midi: Option<Arc<Midi>>,
+}
+
+impl Services {
+    /// Start what `config` asks for. Data files live in `data_dir` and the
+    /// MIDI mapping next to the config at `config_path`.
+    ///
+    /// Commands from a new MQTT connection, OSC socket or MIDI port come back
+    /// with the services. When reloading, an unchanged MQTT connection is
+    /// kept from `previous`, and so is an OSC socket on the same address,
+    /// which takes the new settings. The MIDI port of `previous` is closed.
+    pub fn load(
+        config: &Config,
+        config_path: &Path,
+        data_dir: &Path,
+        previous: Option<&Services>,
+    ) -> (Self, Vec<Commands>) {
+        let credentials = Arc::new(Credentials::open(data_dir.join(credentials::CREDENTIALS_FILE)));
+        if config.api.enabled {
+            if let Err(e) = api::ensure_token(&credentials) {
+                log::error!("Failed to create API token: {}", e);
+            }
+        }
+
+        let mut scrobblers = Vec::new();
+        if let Some(lastfm) = &config.scrobbler {
+            scrobblers.push(Scrobbler::new(
+                "Last.fm",
+                scrobble::Api::LastFm(LastFm::new(lastfm.clone())),
+                data_dir.join("scrobble-queue.json"),
+            ));
+        }
+        if let Some(listenbrainz) = &config.listenbrainz {
+            scrobblers.push(Scrobbler::new(
+                "ListenBrainz",
+                scrobble::Api::ListenBrainz(ListenBrainz::new(listenbrainz.clone(), credentials.clone())),
+                data_dir.join("listenbrainz-queue.json"),
+            ));
+        }
+
+        let overlay = config.overlay.clone().and_then(|overlay| {
+            Overlay::new(overlay)
+                .map_err(|e| log::error!("{}", e))
+                .ok()
+                .map(Arc::new)
+        });
+
+        let mut commands = Vec::new();
+        let kept = previous
+            .and_then(|previous| previous.mqtt.clone())
+            .filter(|mqtt| Some(mqtt.config()) == config.mqtt.as_ref());
+        let mqtt = match (kept, &config.mqtt) {
+            (Some(mqtt), _) => Some(mqtt),
+            (None, Some(mqtt)) => {
+                let (mqtt, receiver) = Mqtt::start(mqtt.clone());
+                commands.push(("MQTT", receiver));
+                Some(mqtt)
+            }
+            (None, None) => None,
+        };
+
+        let kept = previous.and_then(|previous| previous.osc.clone());
+        let osc = match (kept, config.osc.clone()) {
+            (Some(osc), Some(config)) if osc.bind() == config.bind => {
+                osc.reconfigure(config);
+                Some(osc)
+            }
+            (_, Some(config)) => match Osc::start(config) {
+                Ok((osc, receiver)) => {
+                    commands.push(("OSC", receiver));
+                    Some(osc)
+                }
+                Err(e) => {
+                    log::error!("{}", e);
+                    None
+                }
+            },
+            (_, None) => None,
+        };
+
+        // Reopened every time, to pick up a replugged device and edits to
+        // the mapping file. The old connection has to let go of the port
+        // first.
+        if let Some(midi) = previous.and_then(|previous| previous.midi.as_ref()) {
+            midi.stop();
+        }
+        let midi = config.midi.clone().and_then(|midi| {
+            match Midi::start(midi, config_path.with_file_name(midi::MAPPING_FILE)) {
+                Ok((midi, receiver)) => {
+                    commands.push(("MIDI", receiver));
+                    Some(midi)
+                }
+                Err(e) => {
+                    log::error!("{}", e);
+                    None
+                }
+            }
+        });
+
+        let services = Services {
+            api_enabled: config.api.enabled,
+            credentials,
+            scrobblers: Arc::new(Scrobblers(scrobblers)),
+            overlay,
+            mqtt,
+            osc,
+            midi,
+        };
+        (services, commands)
+    }
+}
@@ -0,0 +1,288 @@
+// Global keyboard shortcuts, registered from the backend so they work while
+// the window is not focused.
+//
+// Bindings come from the `shortcuts` section of the config file and map an
+// action to an accelerator such as "CommandOrControl+Shift+Space" or
+// "MediaPlayPause". The config file is checked for changes every few seconds
+// and the shortcuts are registered again when the section changes. Bindings
+// that cannot be registered, because they do not parse, are bound twice or
+// are taken by another application, are reported in the backend log and with
+// a `shortcut-conflicts` event.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tauri::{command, Emitter, Manager};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutEvent, ShortcutState};
+
+use crate::config::Config;
+use crate::control::{self, Action};
+
+pub const CONFLICTS_EVENT: &str = "shortcut-conflicts";
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+fn default_volume_step() -> u8 {
+    5
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ShortcutAction {
+    PlayPause,
+    Next,
+    Previous,
+    VolumeUp,
+    VolumeDown,
+    Like,
+    ToggleFullscreen,
+}
+
+impl ShortcutAction {
+    fn describe(self) -> &'static str {
+        match self {
+            ShortcutAction::PlayPause => "play/pause",
+            ShortcutAction::Next => "next",
+            ShortcutAction::Previous => "previous",
+            ShortcutAction::VolumeUp => "volume up",
+            ShortcutAction::VolumeDown => "volume down",
+            ShortcutAction::Like => "like",
+            ShortcutAction::ToggleFullscreen => "toggle fullscreen",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ShortcutsConfig {
+    #[serde(default)]
+    pub bindings: BTreeMap<ShortcutAction, String>,
+    // Percent to change the volume by, at most 100.
+    #[serde(default = "default_volume_step")]
+    pub volume_step: u8,
+}
+
+impl Default for ShortcutsConfig {
+    fn default() -> Self {
+        ShortcutsConfig {
+            bindings: BTreeMap::new(),
+            volume_step: default_volume_step(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Shortcuts {
+    config: Mutex<Option<ShortcutsConfig>>,
+    // Registered shortcut IDs and what they do.
+    bound: Mutex<HashMap<u32, ShortcutAction>>,
+    conflicts: Mutex<Vec<String>>,
+}
+
+// Parse `config`'s bindings. Bindings that do not parse or reuse a shortcut
+// come back as conflicts.
+fn parse_bindings(config: &ShortcutsConfig) -> (Vec<(Shortcut, ShortcutAction, &str)>, Vec<String>) {
+    let mut parsed: Vec<(Shortcut, ShortcutAction, &str)> = Vec::new();
+    let mut conflicts = Vec::new();
+    for (&action, accelerator) in &config.bindings {
+        let shortcut = match accelerator.parse::<Shortcut>() {
+            Ok(shortcut) => shortcut,
+            Err(e) => {
+                conflicts.push(format!(
+                    "'{}' for {} is not a valid shortcut: {}",
+                    accelerator,
+                    action.describe(),
+                    e
+                ));
+                continue;
+            }
+        };
+        if let Some((_, other, _)) = parsed.iter().find(|(bound, _, _)| bound.id() == shortcut.id()) {
+            conflicts.push(format!(
+                "'{}' is bound to both {} and {}.",
+                accelerator,
+                other.describe(),
+                action.describe()
+            ));
+            continue;
+        }
+        parsed.push((shortcut, action, accelerator));
+    }
+    (parsed, conflicts)
+}
+
+// Replace the registered shortcuts with `config`'s. Blocks until the main
+// thread has registered them, so it must not run there.
+fn register(app: &tauri::AppHandle, config: &ShortcutsConfig) {
+    let global = app.global_shortcut();
+    if let Err(e) = global.unregister_all() {
+        log::error!("Failed to unregister shortcuts: {}", e);
+    }
+
+    let (parsed, mut conflicts) = parse_bindings(config);
+    let mut bound = HashMap::new();
+    for (shortcut, action, accelerator) in parsed {
+        // Fails when another application holds the shortcut.
+        if let Err(e) = global.register(shortcut) {
+            conflicts.push(format!(
+                "Could not register '{}' for {}: {}",
+                accelerator,
+                action.describe(),
+                e
+            ));
+            continue;
+        }
+        bound.insert(shortcut.id(), action);
+    }
+
+    let shortcuts = app.state::<Shortcuts>();
+    *shortcuts.bound.lock().unwrap() = bound;
+    for conflict in &conflicts {
+        crate::backend_log(app, conflict.clone());
+    }
+    app.emit(CONFLICTS_EVENT, &conflicts)
+        .unwrap_or_else(|err| log::error!("Failed to emit shortcut conflicts: {:?}", err));
+    *shortcuts.conflicts.lock().unwrap() = conflicts;
+}
+
+/// Handler for the global shortcut plugin.
+pub fn handle(app: &tauri::AppHandle, shortcut: &Shortcut, event: ShortcutEvent) {
+    if event.state() != ShortcutState::Pressed {
+        return;
+    }
+    let shortcuts = app.state::<Shortcuts>();
+    let Some(action) = shortcuts.bound.lock().unwrap().get(&shortcut.id()).copied() else {
+        return;
+    };
+    let step = shortcuts
+        .config
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map_or(default_volume_step(), |config| config.volume_step);
+    // Capped so that both directions fit an i8.
+    let up = step.min(100) as i8;
+    let down = -up;
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let result = match action {
+            ShortcutAction::ToggleFullscreen => crate::toggle_fullscreen(app.clone()).await,
+            ShortcutAction::PlayPause => control::dispatch(&app, Action::PlayPause).await,
+            ShortcutAction::Next => control::dispatch(&app, Action::Next).await,
+            ShortcutAction::Previous => control::dispatch(&app, Action::Previous).await,
+            ShortcutAction::VolumeUp => control::dispatch(&app, Action::ChangeVolume(up)).await,
+            ShortcutAction::VolumeDown => control::dispatch(&app, Action::ChangeVolume(down)).await,
+            ShortcutAction::Like => control::dispatch(&app, Action::Like).await,
+        };
+        if let Err(e) = result {
+            crate::backend_log(&app, format!("Shortcut for {} failed: {}", action.describe(), e));
+        }
+    });
+}
+
+/// Register the configured shortcuts, and again whenever the `shortcuts`
+/// section of the config file changes.
+pub fn watch(app: tauri::AppHandle, config_path: PathBuf) {
+    tauri::async_runtime::spawn(async move {
+        let mut modified: Option<Option<SystemTime>> = None;
+        loop {
+            let current = std::fs::metadata(&config_path).and_then(|meta| meta.modified()).ok();
+            if modified != Some(current) {
+                modified = Some(current);
+                match Config::load(&config_path) {
+                    Ok(config) => {
+                        let shortcuts = app.state::<Shortcuts>();
+                        let changed = shortcuts.config.lock().unwrap().as_ref() != Some(&config.shortcuts);
+                        if changed {
+                            *shortcuts.config.lock().unwrap() = Some(config.shortcuts.clone());
+                            let app = app.clone();
+                            let registered =
+                                tauri::async_runtime::spawn_blocking(move || register(&app, &config.shortcuts)).await;
+                            if let Err(e) = registered {
+                                log::error!("Failed to register shortcuts: {}", e);
+                            }
+                        }
+                    }
+                    // Keep the current shortcuts until the file is fixed.
+                    Err(e) => crate::backend_log(&app, e),
+                }
+            }
+            tokio::time::sleep(RELOAD_INTERVAL).await;
+        }
+    });
+}
+
+/// Problems from the last time the shortcuts were registered.
+#[command]
+pub fn get_shortcut_conflicts(shortcuts: tauri::State<'_, Shortcuts>) -> Vec<String> {
+    shortcuts.conflicts.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(bindings: &[(ShortcutAction, &str)]) -> ShortcutsConfig {
+        ShortcutsConfig {
+            bindings: bindings
+                .iter()
+                .map(|(action, accelerator)| (*action, accelerator.to_string()))
+                .collect(),
+            ..ShortcutsConfig::default()
+        }
+    }
+
+    #[test]
+    fn config_section() {
+        let config: ShortcutsConfig =
+            serde_json::from_str(r#"{ "bindings": { "play_pause": "MediaPlayPause", "volume_up": "Alt+Up" } }"#)
+                .unwrap();
+        assert_eq!(config.volume_step, 5);
+        assert_eq!(config.bindings[&ShortcutAction::PlayPause], "MediaPlayPause");
+        assert_eq!(config.bindings[&ShortcutAction::VolumeUp], "Alt+Up");
+        assert!(serde_json::from_str::<ShortcutsConfig>(r#"{ "bindings": { "rewind": "F1" } }"#).is_err());
+    }
+
+    #[test]
+    fn parses_bindings() {
+        let config = config(&[
+            (ShortcutAction::PlayPause, "CommandOrControl+Shift+Space"),
+            (ShortcutAction::Next, "MediaTrackNext"),
+            (ShortcutAction::Like, "Alt+L"),
+        ]);
+        let (parsed, conflicts) = parse_bindings(&config);
+        assert!(conflicts.is_empty(), "{:?}", conflicts);
+        let parsed: Vec<(ShortcutAction, &str)> = parsed
+            .iter()
+            .map(|(_, action, accelerator)| (*action, *accelerator))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                (ShortcutAction::PlayPause, "CommandOrControl+Shift+Space"),
+                (ShortcutAction::Next, "MediaTrackNext"),
+                (ShortcutAction::Like, "Alt+L"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_conflicts() {
+        let config = config(&[
+            (ShortcutAction::PlayPause, "Alt+P"),
+            (ShortcutAction::Next, "Alt+Nope"),
+            // The same shortcut, written differently.
+            (ShortcutAction::Previous, "alt+p"),
+            (ShortcutAction::Like, ""),
+        ]);
+        let (parsed, conflicts) = parse_bindings(&config);
+        let actions: Vec<ShortcutAction> = parsed.iter().map(|(_, action, _)| *action).collect();
+        assert_eq!(actions, [ShortcutAction::PlayPause]);
+        assert_eq!(conflicts.len(), 3, "{:?}", conflicts);
+        assert!(conflicts[0].starts_with("'Alt+Nope' for next is not a valid shortcut"));
+        assert_eq!(conflicts[1], "'alt+p' is bound to both play/pause and previous.");
+        assert!(conflicts[2].starts_with("'' for like is not a valid shortcut"));
+    }
+}
@@ -0,0 +1,426 @@
+// Shared helpers for talking to the Spotify Web API.
+//
+// Commands build a path relative to `API_BASE` and get back a typed model
+// (or `()` for endpoints that answer with an empty body). Errors are already
+// formatted as user-facing strings, matching the rest of the commands.
+
+use std::sync::OnceLock;
+
+use reqwest::{Client, Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+pub const API_BASE: &str = "https://api.spotify.com/v1";
+
+// One client for every request, so connections to the API are pooled.
+fn client() -> &'static Client {
+    static CLIENT: OnceLock<Client> = OnceLock::new();
+    CLIENT.get_or_init(Client::new)
+}
+
+fn endpoint(path: &str) -> String {
+    if path.starts_with("http") {
+        path.to_string()
+    } else {
+        format!("{}{}", API_BASE, path)
+    }
+}
+
+async fn request(
+    method: Method,
+    access: &str,
+    path: &str,
+    body: Option<&serde_json::Value>,
+) -> Result<reqwest::Response, String> {
+    check(send_unchecked(method, access, path, body).await?).await
+}
+
+// Turn an unsuccessful response into an error with Spotify's message.
+async fn check(resp: reqwest::Response) -> Result<reqwest::Response, String> {
+    if resp.status().is_success() {
+        Ok(resp)
+    } else {
+        let error_text = resp.text().await.unwrap_or("Unknown error".to_string());
+        Err(format!("Spotify API error: {}", error_text))
+    }
+}
+
+// Send a request and return the response whatever its status.
+async fn send_unchecked(
+    method: Method,
+    access: &str,
+    path: &str,
+    body: Option<&serde_json::Value>,
+) -> Result<reqwest::Response, String> {
+    let mut req = client().request(method, endpoint(path)).bearer_auth(access);
+    if let Some(body) = body {
+        req = req.json(body);
+    }
+
+    req.send()
+        .await
+        .map_err(|e| format!("Failed to reach Spotify API: {:?}", e))
+}
+
+/// GET `path` and parse the response body as `T`.
+pub async fn get<T: DeserializeOwned>(access: &str, path: &str) -> Result<T, String> {
+    let resp = request(Method::GET, access, path, None).await?;
+    resp.json()
+        .await
+        .map_err(|e| format!("Could not parse Spotify response: {:?}", e))
+}
+
+/// Like `get`, but `None` for endpoints that answer `204 No Content` when
+/// there is nothing to return (such as `/me/player` without an active device).
+pub async fn get_optional<T: DeserializeOwned>(access: &str, path: &str) -> Result<Option<T>, String> {
+    let resp = request(Method::GET, access, path, None).await?;
+    if resp.status() == StatusCode::NO_CONTENT {
+        return Ok(None);
+    }
+    resp.json()
+        .await
+        .map(Some)
+        .map_err(|e| format!("Could not parse Spotify response: {:?}", e))
+}
+
+/// Send `body` with `method` to `path` and parse the response body as `T`.
+pub async fn send<T: DeserializeOwned>(
+    method: Method,
+    access: &str,
+    path: &str,
+    body: &serde_json::Value,
+) -> Result<T, String> {
+    let resp = request(method, access, path, Some(body)).await?;
+    resp.json()
+        .await
+        .map_err(|e| format!("Could not parse Spotify response: {:?}", e))
+}
+
+/// Send `body` with `method` to `path`, ignoring whatever Spotify answers with.
+pub async fn send_empty(
+    method: Method,
+    access: &str,
+    path: &str,
+    body: &serde_json::Value,
+) -> Result<(), String> {
+    let resp = request(method, access, path, Some(body)).await?;
+    if resp.status() != StatusCode::NO_CONTENT {
+        // Drain the body so the pooled connection can be reused.
+        let _ = resp.bytes().await;
+    }
+    Ok(())
+}
+
+/// Like `send_empty`, but answers `403 Forbidden` with `forbidden`. Spotify
+/// refuses requests that need a scope the user signed in without.
+pub async fn send_empty_scoped(
+    method: Method,
+    access: &str,
+    path: &str,
+    body: &serde_json::Value,
+    forbidden: &str,
+) -> Result<(), String> {
+    let resp = send_unchecked(method, access, path, Some(body)).await?;
+    if resp.status() == StatusCode::FORBIDDEN {
+        let _ = resp.bytes().await;
+        return Err(forbidden.to_string());
+    }
+    // Drain the body so the pooled connection can be reused.
+    let _ = check(resp).await?.bytes().await;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Image {
+    pub url: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    pub id: String,
+    pub display_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Playlist {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub public: Option<bool>,
+    pub collaborative: bool,
+    pub snapshot_id: String,
+    pub uri: String,
+    pub images: Option<Vec<Image>>,
+    pub owner: User,
+}
+
+// Returned by every endpoint that modifies the items of a playlist.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub snapshot_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SimplifiedArtist {
+    pub id: Option<String>,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SimplifiedAlbum {
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub artists: Vec<SimplifiedArtist>,
+    #[serde(default)]
+    pub images: Vec<Image>,
+    // `YYYY`, `YYYY-MM` or `YYYY-MM-DD` depending on the album.
+    pub release_date: Option<String>,
+    // `album`, `single` or `compilation`.
+    pub album_type: Option<String>,
+    pub total_tracks: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Track {
+    // Local files have no ID.
+    pub id: Option<String>,
+    pub name: String,
+    pub uri: String,
+    pub duration_ms: u32,
+    #[serde(default)]
+    pub explicit: bool,
+    pub popularity: Option<u32>,
+    pub track_number: Option<u32>,
+    #[serde(default)]
+    pub artists: Vec<SimplifiedArtist>,
+    #[serde(default)]
+    pub album: SimplifiedAlbum,
+}
+
+impl Track {
+    /// The artists' names as one string, such as "Artist One, Artist Two".
+    pub fn artist_names(&self) -> String {
+        self.artists
+            .iter()
+            .map(|artist| artist.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Followers {
+    pub total: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Artist {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub genres: Vec<String>,
+    #[serde(default)]
+    pub images: Vec<Image>,
+    #[serde(default)]
+    pub followers: Followers,
+    pub popularity: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Copyright {
+    pub text: String,
+    // `C` for the copyright, `P` for the sound recording (performance) copyright.
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+// Full album object from `/albums/{id}`. Its tracks have no `album` of their own.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Album {
+    pub id: String,
+    pub name: String,
+    pub uri: String,
+    pub album_type: String,
+    pub total_tracks: u32,
+    pub release_date: String,
+    pub release_date_precision: String,
+    pub label: Option<String>,
+    #[serde(default)]
+    pub copyrights: Vec<Copyright>,
+    #[serde(default)]
+    pub images: Vec<Image>,
+    #[serde(default)]
+    pub artists: Vec<SimplifiedArtist>,
+    pub tracks: Page<Track>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Context {
+    pub uri: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Device {
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub volume_percent: Option<u32>,
+}
+
+// Payload of both `/me/player` and `/me/player/currently-playing` (which has
+// no device). `item` is missing while an ad is playing and is an episode
+// (without artists or album) for podcasts.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlaybackState {
+    pub device: Option<Device>,
+    pub progress_ms: Option<u32>,
+    #[serde(default)]
+    pub is_playing: bool,
+    // Only in `/me/player`.
+    pub shuffle_state: Option<bool>,
+    // `off`, `context` or `track`; only in `/me/player`.
+    pub repeat_state: Option<String>,
+    pub context: Option<Context>,
+    pub item: Option<Track>,
+}
+
+// Offset-based page, used by most listing endpoints.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: u32,
+    pub limit: u32,
+    pub offset: u32,
+    pub next: Option<String>,
+    pub previous: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Cursors {
+    pub after: Option<String>,
+    pub before: Option<String>,
+}
+
+// Cursor-based page, used by `/me/player/recently-played`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CursorPage<T> {
+    pub items: Vec<T>,
+    pub limit: u32,
+    pub next: Option<String>,
+    pub cursors: Option<Cursors>,
+}
+
+// Shared by the tests of the modules that follow playback.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, StatusCode};
+
+    use super::PlaybackState;
+
+    /// A `/me/player` payload playing `spotify:track:<track>` by "One" and
+    /// "Two" on a device at 50% volume.
+    pub fn playing(track: &str, progress_ms: u32) -> PlaybackState {
+        serde_json::from_value(serde_json::json!({
+            "device": { "id": "d1", "name": "Desk", "type": "Computer", "volume_percent": 50 },
+            "progress_ms": progress_ms,
+            "is_playing": true,
+            "shuffle_state": false,
+            "repeat_state": "off",
+            "item": {
+                "id": track,
+                "name": format!("Title {}", track),
+                "uri": format!("spotify:track:{}", track),
+                "duration_ms": 200_000,
+                "track_number": 3,
+                "artists": [{ "id": "a1", "name": "One" }, { "id": "a2", "name": "Two" }],
+                "album": {
+                    "name": "Album",
+                    "artists": [{ "id": "a3", "name": "Various Artists" }],
+                    "images": [{ "url": "https://i.scdn.co/image/x" }],
+                },
+            },
+        }))
+        .unwrap()
+    }
+
+    /// A path in the temp directory that is unique to this test run.
+    pub fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("playback-test-{}-{}", std::process::id(), name))
+    }
+
+    /// A request the mock server received.
+    pub struct Received {
+        pub path: String,
+        pub authorization: Option<String>,
+        pub body: Vec<u8>,
+    }
+
+    impl Received {
+        pub fn json(&self) -> serde_json::Value {
+            serde_json::from_slice(&self.body).unwrap_or_default()
+        }
+
+        pub fn form(&self) -> BTreeMap<String, String> {
+            url::form_urlencoded::parse(&self.body).into_owned().collect()
+        }
+    }
+
+    /// Stands in for a web API on a local port, recording every request and
+    /// answering it with the status and JSON body `respond` gives.
+    pub struct MockServer {
+        pub url: String,
+        pub received: Arc<Mutex<Vec<Received>>>,
+    }
+
+    pub async fn mock_server(
+        respond: impl Fn(&Received) -> (StatusCode, serde_json::Value) + Send + Sync + 'static,
+    ) -> MockServer {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let respond = Arc::new(respond);
+        let log = received.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let (log, respond) = (log.clone(), respond.clone());
+            async move {
+                Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
+                    let (log, respond) = (log.clone(), respond.clone());
+                    async move {
+                        let path = req.uri().path().to_string();
+                        let authorization = req
+                            .headers()
+                            .get(hyper::header::AUTHORIZATION)
+                            .and_then(|value| value.to_str().ok())
+                            .map(str::to_string);
+                        let body = hyper::body::to_bytes(req.into_body()).await?.to_vec();
+                        let request = Received {
+                            path,
+                            authorization,
+                            body,
+                        };
+                        let (status, body) = respond(&request);
+                        log.lock().unwrap().push(request);
+                        Ok::<_, hyper::Error>(
+                            Response::builder()
+                                .status(status)
+                                .header(hyper::header::CONTENT_TYPE, "application/json")
+                                .body(Body::from(body.to_string()))
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let url = format!("http://{}/", server.local_addr());
+        tokio::spawn(server);
+        MockServer { url, received }
+    }
+}
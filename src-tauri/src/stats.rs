@@ -0,0 +1,383 @@
+// Listening statistics computed from the local history.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, TimeZone, Timelike};
+use serde::Serialize;
+use tauri::command;
+
+use crate::history::{History, HistoryQuery, PlayRecord};
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DailyTotal {
+    // Local date, `YYYY-MM-DD`.
+    pub date: String,
+    pub listened_ms: u64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct WeeklyTotal {
+    // Local date of the Monday starting the week, `YYYY-MM-DD`.
+    pub week_start: String,
+    pub listened_ms: u64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TopArtist {
+    pub name: String,
+    pub id: Option<String>,
+    pub plays: u32,
+    pub listened_ms: u64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TopTrack {
+    pub track_uri: String,
+    pub title: String,
+    pub artists: Vec<String>,
+    pub plays: u32,
+    pub listened_ms: u64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PlaylistSkipRate {
+    pub context_uri: String,
+    pub plays: u32,
+    pub skips: u32,
+    pub skip_rate: f64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct GenreShare {
+    pub genre: String,
+    pub listened_ms: u64,
+    // Fraction of the listening time of plays with known genres.
+    pub share: f64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ListeningStats {
+    pub plays: u32,
+    pub listened_ms: u64,
+    pub daily: Vec<DailyTotal>,
+    pub weekly: Vec<WeeklyTotal>,
+    pub top_artists: Vec<TopArtist>,
+    pub top_tracks: Vec<TopTrack>,
+    pub playlist_skip_rates: Vec<PlaylistSkipRate>,
+    // Listening time indexed by `[weekday][hour]`, Monday first, local time.
+    pub hour_heatmap: Vec<Vec<u64>>,
+    pub genres: Vec<GenreShare>,
+}
+
+// Sort by a count, highest first, ties broken by name so the output is stable.
+fn rank<T, K: Ord>(items: &mut [T], key: impl Fn(&T) -> (u64, K)) {
+    items.sort_by(|a, b| {
+        let (ka, na) = key(a);
+        let (kb, nb) = key(b);
+        kb.cmp(&ka).then(na.cmp(&nb))
+    });
+}
+
+pub fn compute<Tz: TimeZone>(
+    records: &[PlayRecord],
+    genres: &HashMap<String, Vec<String>>,
+    tz: &Tz,
+    top: usize,
+) -> ListeningStats {
+    let mut daily: HashMap<NaiveDate, u64> = HashMap::new();
+    let mut weekly: HashMap<NaiveDate, u64> = HashMap::new();
+    let mut artists: HashMap<&str, TopArtist> = HashMap::new();
+    let mut tracks: HashMap<&str, TopTrack> = HashMap::new();
+    let mut playlists: HashMap<&str, (u32, u32)> = HashMap::new();
+    let mut genre_ms: HashMap<&str, u64> = HashMap::new();
+    let mut heatmap = vec![vec![0u64; 24]; 7];
+    let mut listened_total = 0u64;
+
+    for record in records {
+        let listened = u64::from(record.listened_ms);
+        listened_total += listened;
+
+        let Some(started) = DateTime::from_timestamp_millis(record.started_at) else {
+            continue;
+        };
+        let local = started.with_timezone(tz);
+        let date = local.date_naive();
+        let week_start = date - Duration::days(i64::from(date.weekday().num_days_from_monday()));
+        *daily.entry(date).or_default() += listened;
+        *weekly.entry(week_start).or_default() += listened;
+        heatmap[date.weekday().num_days_from_monday() as usize][local.hour() as usize] += listened;
+
+        // IDs only line up with names when every artist has one (local files
+        // have none).
+        let ids_match = record.artist_ids.len() == record.artists.len();
+        for (i, name) in record.artists.iter().enumerate() {
+            let artist = artists.entry(name).or_insert_with(|| TopArtist {
+                name: name.clone(),
+                id: None,
+                plays: 0,
+                listened_ms: 0,
+            });
+            if ids_match && artist.id.is_none() {
+                artist.id = Some(record.artist_ids[i].clone());
+            }
+            artist.plays += 1;
+            artist.listened_ms += listened;
+        }
+
+        let track = tracks.entry(&record.track_uri).or_insert_with(|| TopTrack {
+            track_uri: record.track_uri.clone(),
+            title: record.title.clone(),
+            artists: record.artists.clone(),
+            plays: 0,
+            listened_ms: 0,
+        });
+        track.plays += 1;
+        track.listened_ms += listened;
+
+        if let Some(context) = record
+            .context_uri
+            .as_deref()
+            .filter(|uri| uri.starts_with("spotify:playlist:"))
+        {
+            let (plays, skips) = playlists.entry(context).or_default();
+            *plays += 1;
+            *skips += u32::from(record.skipped);
+        }
+
+        let mut play_genres: Vec<&str> = record
+            .artist_ids
+            .iter()
+            .filter_map(|id| genres.get(id))
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        play_genres.sort_unstable();
+        play_genres.dedup();
+        for genre in play_genres {
+            *genre_ms.entry(genre).or_default() += listened;
+        }
+    }
+
+    let mut daily: Vec<_> = daily
+        .into_iter()
+        .map(|(date, listened_ms)| DailyTotal {
+            date: date.to_string(),
+            listened_ms,
+        })
+        .collect();
+    daily.sort_by(|a, b| a.date.cmp(&b.date));
+
+    let mut weekly: Vec<_> = weekly
+        .into_iter()
+        .map(|(week_start, listened_ms)| WeeklyTotal {
+            week_start: week_start.to_string(),
+            listened_ms,
+        })
+        .collect();
+    weekly.sort_by(|a, b| a.week_start.cmp(&b.week_start));
+
+    let mut top_artists: Vec<_> = artists.into_values().collect();
+    rank(&mut top_artists, |a| (u64::from(a.plays), a.name.clone()));
+    top_artists.truncate(top);
+
+    let mut top_tracks: Vec<_> = tracks.into_values().collect();
+    rank(&mut top_tracks, |t| (u64::from(t.plays), t.track_uri.clone()));
+    top_tracks.truncate(top);
+
+    let mut playlist_skip_rates: Vec<_> = playlists
+        .into_iter()
+        .map(|(uri, (plays, skips))| PlaylistSkipRate {
+            context_uri: uri.to_string(),
+            plays,
+            skips,
+            skip_rate: f64::from(skips) / f64::from(plays),
+        })
+        .collect();
+    rank(&mut playlist_skip_rates, |p| (u64::from(p.plays), p.context_uri.clone()));
+
+    // Plays can count towards several genres, so shares are relative to the
+    // sum over genres rather than to the total listening time.
+    let genre_total: u64 = genre_ms.values().sum();
+    let mut genres: Vec<_> = genre_ms
+        .into_iter()
+        .map(|(genre, listened_ms)| GenreShare {
+            genre: genre.to_string(),
+            listened_ms,
+            share: if genre_total == 0 {
+                0.0
+            } else {
+                listened_ms as f64 / genre_total as f64
+            },
+        })
+        .collect();
+    rank(&mut genres, |g| (g.listened_ms, g.genre.clone()));
+
+    ListeningStats {
+        plays: records.len() as u32,
+        listened_ms: listened_total,
+        daily,
+        weekly,
+        top_artists,
+        top_tracks,
+        playlist_skip_rates,
+        hour_heatmap: heatmap,
+        genres,
+    }
+}
+
+// `from` and `to` are Unix timestamps in milliseconds; either can be left out
+// for an open-ended range.
+#[command]
+pub fn get_listening_stats(
+    history: tauri::State<'_, History>,
+    from: Option<i64>,
+    to: Option<i64>,
+    top: Option<usize>,
+) -> Result<ListeningStats, String> {
+    let query = HistoryQuery {
+        from,
+        to,
+        ..Default::default()
+    };
+    let records = history
+        .query(&query)
+        .map_err(|e| format!("Failed to query listening history: {}", e))?;
+    let genres = history
+        .artist_genres()
+        .map_err(|e| format!("Failed to load artist genres: {}", e))?;
+
+    Ok(compute(&records, &genres, &Local, top.unwrap_or(10)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use chrono_tz::Europe::Berlin;
+
+    fn play(track: &str, artists: &[&str], started: DateTime<Utc>, listened_ms: u32) -> PlayRecord {
+        PlayRecord {
+            id: 0,
+            track_id: Some(track.to_string()),
+            track_uri: format!("spotify:track:{}", track),
+            title: track.to_string(),
+            artists: artists.iter().map(|name| name.to_string()).collect(),
+            artist_ids: artists.iter().map(|name| format!("id-{}", name)).collect(),
+            album: "Album".to_string(),
+            context_uri: None,
+            device: None,
+            started_at: started.timestamp_millis(),
+            duration_ms: 200_000,
+            listened_ms,
+            skipped: false,
+        }
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    #[test]
+    fn buckets_across_spring_forward() {
+        // Berlin moves from 02:00 CET to 03:00 CEST at 01:00 UTC on 31 March
+        // 2024, a Sunday.
+        let records = [
+            play("a", &["X"], utc(2024, 3, 30, 23, 30), 1_000),
+            play("b", &["X"], utc(2024, 3, 31, 0, 30), 2_000),
+            play("c", &["X"], utc(2024, 3, 31, 1, 30), 4_000),
+            // Already Monday in Berlin.
+            play("d", &["X"], utc(2024, 3, 31, 22, 30), 8_000),
+        ];
+        let stats = compute(&records, &HashMap::new(), &Berlin, 10);
+
+        assert_eq!(
+            stats.daily,
+            [
+                DailyTotal {
+                    date: "2024-03-31".to_string(),
+                    listened_ms: 7_000
+                },
+                DailyTotal {
+                    date: "2024-04-01".to_string(),
+                    listened_ms: 8_000
+                },
+            ]
+        );
+        assert_eq!(
+            stats.weekly,
+            [
+                WeeklyTotal {
+                    week_start: "2024-03-25".to_string(),
+                    listened_ms: 7_000
+                },
+                WeeklyTotal {
+                    week_start: "2024-04-01".to_string(),
+                    listened_ms: 8_000
+                },
+            ]
+        );
+        let sunday = &stats.hour_heatmap[6];
+        assert_eq!(sunday[0], 1_000);
+        assert_eq!(sunday[1], 2_000);
+        // 02:00 does not exist that night.
+        assert_eq!(sunday[2], 0);
+        assert_eq!(sunday[3], 4_000);
+        assert_eq!(stats.hour_heatmap[0][0], 8_000);
+        assert_eq!(stats.listened_ms, 15_000);
+    }
+
+    #[test]
+    fn buckets_across_fall_back() {
+        // 02:00-03:00 happens twice on 27 October 2024, first in CEST and
+        // then in CET.
+        let records = [
+            play("a", &["X"], utc(2024, 10, 27, 0, 30), 1_000),
+            play("b", &["X"], utc(2024, 10, 27, 1, 30), 2_000),
+            play("c", &["X"], utc(2024, 10, 27, 22, 59), 4_000),
+            play("d", &["X"], utc(2024, 10, 27, 23, 0), 8_000),
+        ];
+        let stats = compute(&records, &HashMap::new(), &Berlin, 10);
+
+        let sunday = &stats.hour_heatmap[6];
+        assert_eq!(sunday[2], 3_000);
+        assert_eq!(sunday[23], 4_000);
+        assert_eq!(stats.hour_heatmap[0][0], 8_000);
+        let days: Vec<(&str, u64)> = stats.daily.iter().map(|d| (d.date.as_str(), d.listened_ms)).collect();
+        assert_eq!(days, [("2024-10-27", 7_000), ("2024-10-28", 8_000)]);
+    }
+
+    #[test]
+    fn ranks_and_rates() {
+        let start = utc(2024, 5, 1, 12, 0);
+        let mut records = vec![
+            play("a", &["X", "Y"], start, 10_000),
+            play("a", &["X", "Y"], start, 10_000),
+            play("b", &["Y"], start, 30_000),
+        ];
+        records[0].context_uri = Some("spotify:playlist:p".to_string());
+        records[1].context_uri = Some("spotify:playlist:p".to_string());
+        records[1].skipped = true;
+        records[2].context_uri = Some("spotify:album:not-a-playlist".to_string());
+        let genres = HashMap::from([
+            ("id-X".to_string(), vec!["rock".to_string()]),
+            ("id-Y".to_string(), vec!["rock".to_string(), "jazz".to_string()]),
+        ]);
+        let stats = compute(&records, &genres, &Utc, 1);
+
+        assert_eq!(stats.plays, 3);
+        assert_eq!(stats.top_artists.len(), 1);
+        assert_eq!(stats.top_artists[0].name, "Y");
+        assert_eq!(stats.top_artists[0].id.as_deref(), Some("id-Y"));
+        assert_eq!(stats.top_artists[0].plays, 3);
+        assert_eq!(stats.top_tracks[0].track_uri, "spotify:track:a");
+        assert_eq!(stats.top_tracks[0].plays, 2);
+
+        assert_eq!(stats.playlist_skip_rates.len(), 1);
+        assert_eq!(stats.playlist_skip_rates[0].skip_rate, 0.5);
+
+        // A play counts once per genre, even with several artists in it.
+        let shares: Vec<(&str, u64)> = stats.genres.iter().map(|g| (g.genre.as_str(), g.listened_ms)).collect();
+        assert_eq!(shares, [("jazz", 50_000), ("rock", 50_000)]);
+        assert_eq!(stats.genres[0].share, 0.5);
+    }
+}
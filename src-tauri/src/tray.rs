@@ -0,0 +1,347 @@
+// System tray icon with a playback menu, for running the controller
+// minimized all day.
+//
+// The menu shows the current track and has play/pause, next, previous,
+// shuffle, repeat and a submenu of the available devices. It is rebuilt when
+// the playback changes; the device list is fetched again at most once a
+// minute. The tooltip shows the current track. With `close_to_tray` set,
+// closing the main window hides it instead of quitting, and "Show" brings it
+// back.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tauri::menu::{CheckMenuItem, IsMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu};
+use tauri::tray::TrayIconBuilder;
+use tauri::{Manager, Wry};
+
+use crate::control::{self, Action};
+use crate::player;
+use crate::spotify::{Device, PlaybackState};
+
+const TRAY_ID: &str = "main";
+const TOOLTIP: &str = "Playback Controller";
+const DEVICES_MAX_AGE: Duration = Duration::from_secs(60);
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrayConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub close_to_tray: bool,
+}
+
+impl Default for TrayConfig {
+    fn default() -> Self {
+        TrayConfig {
+            enabled: true,
+            close_to_tray: false,
+        }
+    }
+}
+
+// What the menu shows, to tell when it needs rebuilding.
+#[derive(Clone, PartialEq, Debug, Default)]
+struct Summary {
+    // "Title — Artist".
+    track: Option<String>,
+    playing: bool,
+    shuffle: Option<bool>,
+    repeat: Option<String>,
+    device: Option<String>,
+}
+
+impl Summary {
+    fn new(state: Option<&PlaybackState>) -> Self {
+        let Some(state) = state else {
+            return Summary::default();
+        };
+        Summary {
+            track: state.item.as_ref().map(|track| {
+                if track.artists.is_empty() {
+                    track.name.clone()
+                } else {
+                    format!("{} — {}", track.name, track.artist_names())
+                }
+            }),
+            playing: state.is_playing,
+            shuffle: state.shuffle_state,
+            repeat: state.repeat_state.clone(),
+            device: state.device.as_ref().and_then(|device| device.id.clone()),
+        }
+    }
+}
+
+pub struct Tray {
+    close_to_tray: bool,
+    shown: Mutex<Option<Summary>>,
+    // When the device list was last requested, and the last one received.
+    devices: Mutex<(Option<Instant>, Vec<Device>)>,
+}
+
+fn build_menu(app: &tauri::AppHandle, summary: &Summary, devices: &[Device]) -> tauri::Result<Menu<Wry>> {
+    let track = MenuItem::with_id(
+        app,
+        "track",
+        summary.track.as_deref().unwrap_or("Nothing playing"),
+        false,
+        None::<&str>,
+    )?;
+    let play_pause = MenuItem::with_id(
+        app,
+        "play_pause",
+        if summary.playing { "Pause" } else { "Play" },
+        true,
+        None::<&str>,
+    )?;
+    let next = MenuItem::with_id(app, "next", "Next", true, None::<&str>)?;
+    let previous = MenuItem::with_id(app, "previous", "Previous", true, None::<&str>)?;
+    let shuffle = CheckMenuItem::with_id(
+        app,
+        "shuffle",
+        "Shuffle",
+        summary.shuffle.is_some(),
+        summary.shuffle.unwrap_or(false),
+        None::<&str>,
+    )?;
+
+    let repeat_modes = [("off", "Off"), ("context", "All"), ("track", "One")]
+        .into_iter()
+        .map(|(mode, label)| {
+            CheckMenuItem::with_id(
+                app,
+                format!("repeat:{}", mode),
+                label,
+                summary.repeat.is_some(),
+                summary.repeat.as_deref() == Some(mode),
+                None::<&str>,
+            )
+        })
+        .collect::<tauri::Result<Vec<_>>>()?;
+    let items: Vec<&dyn IsMenuItem<Wry>> = repeat_modes.iter().map(|item| item as &dyn IsMenuItem<Wry>).collect();
+    let repeat = Submenu::with_items(app, "Repeat", true, &items)?;
+
+    // Devices without an ID cannot be controlled and are left out.
+    let device_items = devices
+        .iter()
+        .filter_map(|device| device.id.as_ref().map(|id| (id, device)))
+        .map(|(id, device)| {
+            CheckMenuItem::with_id(
+                app,
+                format!("device:{}", id),
+                &device.name,
+                true,
+                summary.device.as_ref() == Some(id),
+                None::<&str>,
+            )
+        })
+        .collect::<tauri::Result<Vec<_>>>()?;
+    let items: Vec<&dyn IsMenuItem<Wry>> = device_items.iter().map(|item| item as &dyn IsMenuItem<Wry>).collect();
+    let devices = Submenu::with_items(app, "Devices", !items.is_empty(), &items)?;
+
+    let show = MenuItem::with_id(app, "show", "Show", true, None::<&str>)?;
+    let quit = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
+    Menu::with_items(
+        app,
+        &[
+            &track,
+            &PredefinedMenuItem::separator(app)?,
+            &play_pause,
+            &next,
+            &previous,
+            &PredefinedMenuItem::separator(app)?,
+            &shuffle,
+            &repeat,
+            &devices,
+            &PredefinedMenuItem::separator(app)?,
+            &show,
+            &quit,
+        ],
+    )
+}
+
+fn show_window(app: &tauri::AppHandle) {
+    if let Some(window) = app.get_webview_window("main") {
+        let shown = window.show().and_then(|()| window.unminimize()).and_then(|()| window.set_focus());
+        if let Err(e) = shown {
+            log::error!("Failed to show the window: {}", e);
+        }
+    }
+}
+
+fn on_menu_event(app: &tauri::AppHandle, event: MenuEvent) {
+    let id = event.id().as_ref();
+    let action = match id {
+        "show" => return show_window(app),
+        "quit" => return app.exit(0),
+        "play_pause" => Action::PlayPause,
+        "next" => Action::Next,
+        "previous" => Action::Previous,
+        "shuffle" => Action::ToggleShuffle,
+        _ => match (id.strip_prefix("repeat:"), id.strip_prefix("device:")) {
+            (Some(mode), _) => Action::SetRepeat(mode.to_string()),
+            (_, Some(device)) => Action::Transfer(device.to_string()),
+            _ => return,
+        },
+    };
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = control::dispatch(&app, action).await {
+            crate::backend_log(&app, format!("Tray action failed: {}", e));
+        }
+        // Check items toggle themselves when clicked, so always rebuild.
+        if let Some(tray) = app.try_state::<Arc<Tray>>() {
+            *tray.shown.lock().unwrap() = None;
+        }
+        observe(&app);
+    });
+}
+
+/// Add the tray icon, unless it is turned off.
+pub fn create(app: &tauri::App, config: &TrayConfig) -> tauri::Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let menu = build_menu(app.handle(), &Summary::default(), &[])?;
+    let mut builder = TrayIconBuilder::with_id(TRAY_ID)
+        .tooltip(TOOLTIP)
+        .menu(&menu)
+        .on_menu_event(on_menu_event);
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    builder.build(app)?;
+    app.manage(Arc::new(Tray {
+        close_to_tray: config.close_to_tray,
+        shown: Mutex::new(None),
+        devices: Mutex::new((None, Vec::new())),
+    }));
+    Ok(())
+}
+
+/// Hide the main window instead of closing it, if configured.
+pub fn on_window_event(window: &tauri::Window, event: &tauri::WindowEvent) {
+    let tauri::WindowEvent::CloseRequested { api, .. } = event else {
+        return;
+    };
+    let close_to_tray = window
+        .app_handle()
+        .try_state::<Arc<Tray>>()
+        .is_some_and(|tray| tray.close_to_tray);
+    if close_to_tray && window.label() == "main" {
+        api.prevent_close();
+        if let Err(e) = window.hide() {
+            log::error!("Failed to hide the window: {}", e);
+        }
+    }
+}
+
+// Rebuild the menu and tooltip from the remembered playback, if anything
+// they show changed or the device list is due for a refresh.
+pub fn observe(app: &tauri::AppHandle) {
+    let Some(tray) = app.try_state::<Arc<Tray>>() else {
+        return;
+    };
+    let tray = tray.inner().clone();
+    let summary = Summary::new(control::now_playing(app).as_ref());
+    let refresh_devices = {
+        let mut devices = tray.devices.lock().unwrap();
+        let due = devices.0.map_or(true, |fetched| fetched.elapsed() >= DEVICES_MAX_AGE);
+        if due {
+            devices.0 = Some(Instant::now());
+        }
+        due
+    };
+    {
+        let mut shown = tray.shown.lock().unwrap();
+        if !refresh_devices && shown.as_ref() == Some(&summary) {
+            return;
+        }
+        *shown = Some(summary);
+    }
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        if refresh_devices {
+            let devices = match control::access_token(&app) {
+                Ok(access) => player::devices(&access).await,
+                Err(e) => Err(e),
+            };
+            match devices {
+                Ok(devices) => tray.devices.lock().unwrap().1 = devices,
+                Err(e) => log::warn!("Failed to fetch devices for the tray: {}", e),
+            }
+        }
+        let Some(icon) = app.tray_by_id(TRAY_ID) else {
+            return;
+        };
+        // A later update may have started while the devices were fetched, so
+        // apply whatever is newest, holding the lock so that updates finishing
+        // out of order cannot leave an older menu.
+        let shown = tray.shown.lock().unwrap();
+        let Some(summary) = shown.as_ref() else {
+            return;
+        };
+        let devices = tray.devices.lock().unwrap().1.clone();
+        let updated = build_menu(&app, summary, &devices)
+            .and_then(|menu| icon.set_menu(Some(menu)))
+            .and_then(|()| icon.set_tooltip(Some(summary.track.as_deref().unwrap_or(TOOLTIP))));
+        drop(shown);
+        if let Err(e) = updated {
+            log::error!("Failed to update the tray: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spotify::fixtures;
+
+    #[test]
+    fn summary_of_the_playback() {
+        assert_eq!(Summary::new(None), Summary::default());
+
+        let state = fixtures::playing("a", 1_000);
+        let summary = Summary::new(Some(&state));
+        assert_eq!(
+            summary,
+            Summary {
+                track: Some("Title a — One, Two".to_string()),
+                playing: true,
+                shuffle: Some(false),
+                repeat: Some("off".to_string()),
+                device: Some("d1".to_string()),
+            }
+        );
+        // Progress alone does not rebuild the menu.
+        assert_eq!(Summary::new(Some(&fixtures::playing("a", 9_000))), summary);
+
+        let mut paused = state.clone();
+        paused.is_playing = false;
+        assert!(!Summary::new(Some(&paused)).playing);
+
+        let mut nothing = state.clone();
+        nothing.item = None;
+        nothing.device = None;
+        nothing.shuffle_state = None;
+        nothing.repeat_state = None;
+        assert_eq!(
+            Summary::new(Some(&nothing)),
+            Summary {
+                playing: true,
+                ..Summary::default()
+            }
+        );
+
+        let mut no_artists = state;
+        no_artists.item.as_mut().unwrap().artists.clear();
+        assert_eq!(Summary::new(Some(&no_artists)).track.as_deref(), Some("Title a"));
+    }
+}
@@ -0,0 +1,360 @@
+// WebSocket stream of playback events at `/api/events`, for overlays and
+// dashboards that want pushed updates instead of polling the REST API.
+//
+// Clients receive the `events::Envelope`s as JSON text messages. By default
+// every kind of event is sent; `?events=track_changed,progress` narrows that
+// when connecting, and clients can change it later by sending
+// `{"type": "subscribe", "events": [...]}` or `{"type": "unsubscribe", ...}`.
+// The server pings every `HEARTBEAT` and drops clients that miss a pong.
+
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use hyper::header::{self, HeaderValue};
+use hyper::upgrade::Upgraded;
+use hyper::{Body, Request, Response, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::events::{Envelope, PlaybackEvent, SCHEMA_VERSION};
+
+const HEARTBEAT: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe { events: Vec<String> },
+    Unsubscribe { events: Vec<String> },
+}
+
+fn parse_kinds<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<BTreeSet<&'static str>, String> {
+    names
+        .into_iter()
+        .map(|name| {
+            PlaybackEvent::KINDS
+                .iter()
+                .find(|kind| **kind == name.trim())
+                .copied()
+                .ok_or(format!("Unknown event type '{}'.", name))
+        })
+        .collect()
+}
+
+fn status_message(filter: &BTreeSet<&'static str>) -> Message {
+    Message::Text(json!({ "version": SCHEMA_VERSION, "type": "subscribed", "events": filter }).to_string())
+}
+
+fn error_message(error: &str) -> Message {
+    Message::Text(json!({ "version": SCHEMA_VERSION, "type": "error", "error": error }).to_string())
+}
+
+// Apply a subscription change sent by the client, and answer with the new
+// filter or what was wrong with the message.
+fn client_message(filter: &mut BTreeSet<&'static str>, text: &str) -> Message {
+    match serde_json::from_str::<ClientMessage>(text) {
+        Ok(ClientMessage::Subscribe { events }) => match parse_kinds(events.iter().map(String::as_str)) {
+            Ok(kinds) => {
+                filter.extend(kinds);
+                status_message(filter)
+            }
+            Err(e) => error_message(&e),
+        },
+        Ok(ClientMessage::Unsubscribe { events }) => match parse_kinds(events.iter().map(String::as_str)) {
+            Ok(kinds) => {
+                filter.retain(|kind| !kinds.contains(kind));
+                status_message(filter)
+            }
+            Err(e) => error_message(&e),
+        },
+        Err(e) => error_message(&format!("Invalid message: {}", e)),
+    }
+}
+
+fn bad_request(message: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body(Body::from(message.to_string()))
+        .unwrap()
+}
+
+/// Upgrade an (already authorized) request to a WebSocket and stream the
+/// events from `events` on it until the client goes away.
+pub fn handle(events: broadcast::Receiver<Envelope>, mut req: Request<Body>) -> Response<Body> {
+    let is_upgrade = req
+        .headers()
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let Some(key) = req.headers().get(header::SEC_WEBSOCKET_KEY).filter(|_| is_upgrade) else {
+        return bad_request("Expected a WebSocket upgrade.");
+    };
+    let accept = derive_accept_key(key.as_bytes());
+
+    let params: HashMap<String, String> = req
+        .uri()
+        .query()
+        .map(|query| url::form_urlencoded::parse(query.as_bytes()).into_owned().collect())
+        .unwrap_or_default();
+    let filter = match params.get("events") {
+        Some(names) => match parse_kinds(names.split(',')) {
+            Ok(filter) => filter,
+            Err(e) => return bad_request(&e),
+        },
+        None => PlaybackEvent::KINDS.into_iter().collect(),
+    };
+
+    let upgrade = hyper::upgrade::on(&mut req);
+    tauri::async_runtime::spawn(async move {
+        match upgrade.await {
+            Ok(upgraded) => {
+                let socket = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                session(socket, events, filter).await;
+            }
+            Err(e) => log::warn!("WebSocket upgrade failed: {}", e),
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, HeaderValue::from_static("Upgrade"))
+        .header(header::UPGRADE, HeaderValue::from_static("websocket"))
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept)
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn session(
+    mut socket: WebSocketStream<Upgraded>,
+    mut events: broadcast::Receiver<Envelope>,
+    mut filter: BTreeSet<&'static str>,
+) {
+    let mut heartbeat = tokio::time::interval(HEARTBEAT);
+    // The first tick fires right away.
+    heartbeat.tick().await;
+    let mut awaiting_pong = false;
+
+    if socket.send(status_message(&filter)).await.is_err() {
+        return;
+    }
+
+    loop {
+        let outgoing = tokio::select! {
+            event = events.recv() => match event {
+                Ok(envelope) if filter.contains(envelope.event.kind()) => {
+                    match serde_json::to_string(&envelope) {
+                        Ok(text) => Message::Text(text),
+                        Err(e) => {
+                            log::error!("Failed to serialize event: {}", e);
+                            continue;
+                        }
+                    }
+                }
+                Ok(_) => continue,
+                // The client is too slow; it misses some events but stays connected.
+                Err(RecvError::Lagged(missed)) => error_message(&format!("Missed {} events.", missed)),
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.next() => match message {
+                Some(Ok(Message::Text(text))) => client_message(&mut filter, &text),
+                Some(Ok(Message::Pong(_))) => {
+                    awaiting_pong = false;
+                    continue;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // Pings are answered by tungstenite itself.
+                Some(Ok(_)) => continue,
+            },
+            _ = heartbeat.tick() => {
+                if awaiting_pong {
+                    break;
+                }
+                awaiting_pong = true;
+                Message::Ping(Vec::new())
+            }
+        };
+
+        if socket.send(outgoing).await.is_err() {
+            break;
+        }
+    }
+
+    let _ = socket.close(None).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::sync::Arc;
+
+    use hyper::service::{make_service_fn, service_fn};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::tungstenite::Error as WsError;
+
+    use super::*;
+    use crate::api::{self, Route, TOKEN_KEY};
+    use crate::credentials::Credentials;
+    use crate::events::{Progress, VolumeChanged};
+    use crate::spotify::fixtures::temp_path;
+
+    const TOKEN: &str = "0123456789abcdef";
+
+    fn envelope(event: PlaybackEvent) -> Envelope {
+        Envelope {
+            version: SCHEMA_VERSION,
+            timestamp: 1_700_000_000_000,
+            event,
+        }
+    }
+
+    fn text(message: Message) -> serde_json::Value {
+        match message {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("expected text, got {:?}", other),
+        }
+    }
+
+    // Serves the event stream behind the same checks as the API, with
+    // events from `bus`.
+    async fn serve(bus: broadcast::Sender<Envelope>) -> std::net::SocketAddr {
+        let credentials = Arc::new(Credentials::open(temp_path("ws-credentials.json")));
+        credentials.set(TOKEN_KEY, Some(TOKEN.to_string())).unwrap();
+        let make_svc = make_service_fn(move |_conn| {
+            let (credentials, bus) = (credentials.clone(), bus.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |mut req| {
+                    let (credentials, bus) = (credentials.clone(), bus.clone());
+                    async move {
+                        Ok::<_, Infallible>(match api::accept(true, Some(&credentials), &mut req).await {
+                            Ok(Route::Events) => handle(bus.subscribe(), req),
+                            Ok(_) => api::error(StatusCode::NOT_FOUND, "Not Found"),
+                            Err(response) => response,
+                        })
+                    }
+                }))
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    async fn connect(addr: std::net::SocketAddr, query: &str) -> Result<WebSocketStream<TcpStream>, WsError> {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let url = format!("ws://{}/api/events{}", addr, query);
+        tokio_tungstenite::client_async(url, stream)
+            .await
+            .map(|(socket, _)| socket)
+    }
+
+    async fn receive(socket: &mut WebSocketStream<TcpStream>) -> serde_json::Value {
+        let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("no message")
+            .unwrap()
+            .unwrap();
+        text(message)
+    }
+
+    #[test]
+    fn event_names() {
+        assert_eq!(
+            parse_kinds(["progress", " volume "]).unwrap(),
+            BTreeSet::from(["progress", "volume"])
+        );
+        assert!(parse_kinds([]).unwrap().is_empty());
+        assert_eq!(
+            parse_kinds(["progress", "lyrics"]).unwrap_err(),
+            "Unknown event type 'lyrics'."
+        );
+        assert!(parse_kinds(["Progress"]).is_err());
+    }
+
+    #[test]
+    fn subscriptions() {
+        let mut filter = BTreeSet::from(["progress"]);
+        let reply = text(client_message(
+            &mut filter,
+            r#"{ "type": "subscribe", "events": ["volume", "device"] }"#,
+        ));
+        assert_eq!(reply["type"], "subscribed");
+        assert_eq!(reply["events"], json!(["device", "progress", "volume"]));
+
+        let reply = text(client_message(
+            &mut filter,
+            r#"{ "type": "unsubscribe", "events": ["progress", "track_changed"] }"#,
+        ));
+        assert_eq!(reply["events"], json!(["device", "volume"]));
+
+        // Bad requests leave the filter alone.
+        for message in [
+            r#"{ "type": "subscribe", "events": ["lyrics"] }"#,
+            r#"{ "type": "mute" }"#,
+            "not json",
+        ] {
+            let reply = text(client_message(&mut filter, message));
+            assert_eq!(reply["type"], "error", "{}", message);
+            assert_eq!(reply["version"], SCHEMA_VERSION);
+        }
+        assert_eq!(filter, BTreeSet::from(["device", "volume"]));
+    }
+
+    #[tokio::test]
+    async fn streams_filtered_events() {
+        let bus = broadcast::channel(16).0;
+        let addr = serve(bus.clone()).await;
+
+        // Unauthenticated upgrades are refused before the handshake.
+        match connect(addr, "?events=volume").await {
+            Err(WsError::Http(response)) => assert_eq!(response.status(), StatusCode::UNAUTHORIZED),
+            other => panic!("expected a 401, got {:?}", other.map(|_| ())),
+        }
+        match connect(addr, "?token=wrong").await {
+            Err(WsError::Http(response)) => assert_eq!(response.status(), StatusCode::UNAUTHORIZED),
+            other => panic!("expected a 401, got {:?}", other.map(|_| ())),
+        }
+        match connect(addr, &format!("?token={}&events=lyrics", TOKEN)).await {
+            Err(WsError::Http(response)) => assert_eq!(response.status(), StatusCode::BAD_REQUEST),
+            other => panic!("expected a 400, got {:?}", other.map(|_| ())),
+        }
+
+        let mut socket = connect(addr, &format!("?token={}&events=volume", TOKEN)).await.unwrap();
+        assert_eq!(receive(&mut socket).await["events"], json!(["volume"]));
+
+        let progress = || {
+            envelope(PlaybackEvent::Progress(Progress {
+                progress_ms: 1_000,
+                duration_ms: 200_000,
+            }))
+        };
+        bus.send(progress()).unwrap();
+        bus.send(envelope(PlaybackEvent::Volume(VolumeChanged { volume_percent: 30 })))
+            .unwrap();
+        // The progress event was filtered out.
+        let event = receive(&mut socket).await;
+        assert_eq!(event["type"], "volume");
+        assert_eq!(event["data"]["volume_percent"], 30);
+
+        socket
+            .send(Message::Text(
+                json!({ "type": "subscribe", "events": ["progress"] }).to_string(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(receive(&mut socket).await["events"], json!(["progress", "volume"]));
+        bus.send(progress()).unwrap();
+        let event = receive(&mut socket).await;
+        assert_eq!(event["type"], "progress");
+        assert_eq!(event["version"], SCHEMA_VERSION);
+        assert_eq!(event["data"]["progress_ms"], 1_000);
+
+        socket.close(None).await.unwrap();
+        std::fs::remove_file(temp_path("ws-credentials.json")).unwrap();
+    }
+}